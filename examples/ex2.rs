@@ -4,14 +4,15 @@ use rust_streamer::graphic::*;
 use rust_streamer::sample::*;
 use rust_streamer::*;
 
-fn main() {
+fn main() -> Result<(), Error> {
     let p = pipe!(
-        WAVSource::<Stereo<f64>>::new("examples/test85.wav").unwrap(),
+        WAVSource::<Stereo<f64>>::new("examples/test85.wav")?,
         Spectrum::new(1024),
         LowPassFilter::new(1000.0, 1.0 / 2f64.sqrt()),
         Spectrum::new(1024),
         DefaultSink::new()
     );
 
-    p.start(DefaultContext::new());
+    p.start(DefaultContext::new())?.join();
+    Ok(())
 }
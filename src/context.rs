@@ -1,18 +1,28 @@
-use crate::Context;
+use crate::{Context, Error};
 
 use std::collections::HashSet;
 
+/// sample rates probed when an element can only report a supported range.
+pub const COMMON_FREQS: [u32; 11] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000,
+];
+
+#[derive(Clone)]
 pub struct DefaultContext {
     freq: Option<u32>,
-    supported_freq: Option<HashSet<u32>>,
-    preferred_freq: HashSet<u32>,
+    supported_freq: Vec<Vec<u32>>,
+    preferred_freq: Vec<u32>,
+    auto_resample: bool,
+    position: u64,
 }
 impl DefaultContext {
     pub fn new() -> Self {
         DefaultContext {
             freq: None,
-            supported_freq: None,
-            preferred_freq: HashSet::new(),
+            supported_freq: Vec::new(),
+            preferred_freq: Vec::new(),
+            auto_resample: false,
+            position: 0,
         }
     }
     pub fn freq(self, freq: u32) -> Self {
@@ -21,55 +31,191 @@ impl DefaultContext {
             ..self
         }
     }
+    /// lets elements with a fixed native rate, such as `WAVSource`, resample to the
+    /// decided rate instead of constraining it.
+    pub fn auto_resample(self, auto_resample: bool) -> Self {
+        DefaultContext {
+            auto_resample: auto_resample,
+            ..self
+        }
+    }
+}
+impl Default for DefaultContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Context for DefaultContext {
-    fn build(mut self) -> Result<Self, ()> {
+    fn build(mut self) -> Result<Self, Error> {
         self.decide_freq()?;
         Ok(self)
     }
+    fn get_position(&self) -> u64 {
+        self.position
+    }
+    fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
 }
 
 pub trait FreqCtx {
     fn get_freq(&self) -> u32;
+    /// contexts that do not implement this never let elements resample on their
+    /// own.
+    fn get_auto_resample(&self) -> bool {
+        false
+    }
+    /// returns a fresh context for a separate rate domain, such as the input side of
+    /// a resampler. defaults to a `Default` context.
+    fn split_freq(&self) -> Self
+    where
+        Self: Sized + Default,
+    {
+        Self::default()
+    }
     fn set_supported_freq(&mut self, supported_freq: &[u32]);
     fn set_preferred_freq(&mut self, preferred_freq: &[u32]);
-    fn decide_freq(&mut self) -> Result<u32, ()>;
+    fn decide_freq(&mut self) -> Result<u32, Error>;
 }
 impl FreqCtx for DefaultContext {
     fn get_freq(&self) -> u32 {
         self.freq.unwrap()
     }
+    fn get_auto_resample(&self) -> bool {
+        self.auto_resample
+    }
+    fn split_freq(&self) -> Self {
+        DefaultContext::new().auto_resample(self.auto_resample)
+    }
     fn set_supported_freq(&mut self, supported_freq: &[u32]) {
-        let supported_freq = supported_freq.iter().copied().collect();
-        if let Some(ref mut self_supported_freq) = self.supported_freq {
-            self_supported_freq.intersection(&supported_freq);
-        } else {
-            self.supported_freq = Some(supported_freq);
-        }
+        self.supported_freq.push(supported_freq.to_vec());
     }
+    /// earlier preferences win over later ones.
     fn set_preferred_freq(&mut self, preferred_freq: &[u32]) {
-        self.preferred_freq.extend(preferred_freq.iter());
+        for &freq in preferred_freq {
+            if !self.preferred_freq.contains(&freq) {
+                self.preferred_freq.push(freq);
+            }
+        }
     }
-    fn decide_freq(&mut self) -> Result<u32, ()> {
+    fn decide_freq(&mut self) -> Result<u32, Error> {
+        let mut constraints = self.supported_freq.iter();
+        let supported = constraints.next().map(|first| {
+            let first: HashSet<u32> = first.iter().copied().collect();
+            constraints.fold(first, |acc, freqs| {
+                let freqs = freqs.iter().copied().collect();
+                acc.intersection(&freqs).copied().collect()
+            })
+        });
+        if let Some(ref supported) = supported {
+            if supported.is_empty() {
+                return Err(Error::FreqConflict(self.supported_freq.clone()));
+            }
+        }
+        let is_supported = |freq: &u32| match supported {
+            Some(ref supported) => supported.contains(freq),
+            None => true,
+        };
+
         if let Some(freq) = self.freq {
+            if !is_supported(&freq) {
+                let mut supported: Vec<u32> = supported.unwrap().into_iter().collect();
+                supported.sort();
+                return Err(Error::FreqNotSupported(freq, supported));
+            }
             return Ok(freq);
         }
-        if let Some(supported_freq) = &self.supported_freq {
-            self.preferred_freq.intersection(supported_freq);
-            if let Some(max) = self.preferred_freq.iter().copied().max() {
-                self.freq = Some(max);
-            } else if let Some(max) = supported_freq.iter().copied().max() {
-                self.freq = Some(max);
-            }
-        } else {
-            if let Some(max) = self.preferred_freq.iter().copied().max() {
-                self.freq = Some(max);
+        let freq = match self.preferred_freq.iter().copied().find(is_supported) {
+            Some(freq) => Some(freq),
+            None => supported.and_then(|supported| supported.into_iter().max()),
+        };
+        self.freq = freq;
+        freq.ok_or(Error::NoFreq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersects_every_supported_set() {
+        let mut ctx = DefaultContext::new();
+        ctx.set_supported_freq(&[44100, 48000, 96000, 192000]);
+        ctx.set_supported_freq(&[22050, 44100, 48000, 96000]);
+        ctx.set_supported_freq(&[48000, 96000, 44100]);
+        // without a preference the highest common rate wins
+        assert_eq!(ctx.decide_freq().unwrap(), 96000);
+        assert_eq!(ctx.get_freq(), 96000);
+
+        let mut ctx = DefaultContext::new();
+        ctx.set_supported_freq(&[44100, 48000]);
+        ctx.set_supported_freq(&[48000]);
+        ctx.set_preferred_freq(&[44100]);
+        assert_eq!(ctx.decide_freq().unwrap(), 48000);
+    }
+
+    #[test]
+    fn earlier_preferences_win() {
+        let mut ctx = DefaultContext::new();
+        ctx.set_preferred_freq(&[32000, 44100]);
+        ctx.set_preferred_freq(&[48000, 32000]);
+        ctx.set_supported_freq(&[44100, 48000]);
+        // 32000 is unsupported, so the next preference is taken
+        assert_eq!(ctx.decide_freq().unwrap(), 44100);
+
+        let mut ctx = DefaultContext::new();
+        ctx.set_preferred_freq(&[22050, 48000]);
+        assert_eq!(ctx.decide_freq().unwrap(), 22050);
+
+        let mut ctx = DefaultContext::new().freq(96000);
+        ctx.set_preferred_freq(&[44100]);
+        ctx.set_supported_freq(&[44100, 96000]);
+        assert_eq!(ctx.decide_freq().unwrap(), 96000);
+    }
+
+    #[test]
+    fn split_keeps_auto_resample_only() {
+        let ctx = DefaultContext::new();
+        assert!(!ctx.get_auto_resample());
+        assert!(!ctx.split_freq().get_auto_resample());
+
+        let mut ctx = DefaultContext::new().freq(48000).auto_resample(true);
+        ctx.set_supported_freq(&[48000]);
+        ctx.set_preferred_freq(&[48000]);
+        assert!(ctx.get_auto_resample());
+        let mut split = ctx.split_freq();
+        assert!(split.get_auto_resample());
+        // the new domain decides its own rate from scratch
+        split.set_supported_freq(&[44100]);
+        assert_eq!(split.decide_freq().unwrap(), 44100);
+        assert_eq!(ctx.decide_freq().unwrap(), 48000);
+    }
+
+    #[test]
+    fn reports_conflicts() {
+        let mut ctx = DefaultContext::new();
+        ctx.set_supported_freq(&[44100, 48000]);
+        ctx.set_supported_freq(&[96000]);
+        match ctx.decide_freq() {
+            Err(Error::FreqConflict(supported)) => {
+                assert_eq!(supported, vec![vec![44100, 48000], vec![96000]])
             }
+            other => panic!("{:?}", other),
         }
-        if let Some(freq) = self.freq {
-            return Ok(freq);
-        } else {
-            return Err(());
+
+        let mut ctx = DefaultContext::new().freq(32000);
+        ctx.set_supported_freq(&[96000, 44100, 48000]);
+        ctx.set_supported_freq(&[48000, 44100]);
+        match ctx.decide_freq() {
+            Err(Error::FreqNotSupported(32000, supported)) => {
+                assert_eq!(supported, vec![44100, 48000])
+            }
+            other => panic!("{:?}", other),
         }
+
+        let mut ctx = DefaultContext::new();
+        assert!(matches!(ctx.decide_freq(), Err(Error::NoFreq)));
+        assert!(matches!(ctx.build(), Err(Error::NoFreq)));
     }
 }
@@ -0,0 +1,391 @@
+use cpal::{
+    CreationError, Device, EventLoop, Format, SampleFormat, StreamData, StreamId, SupportedFormat,
+};
+
+use crate::context::COMMON_FREQS;
+use crate::sample::*;
+use crate::Error;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+// cpal's `EventLoop::run` never returns, so every stream in the process shares
+// one event loop running on its own thread.
+
+type Callback = Box<dyn FnMut(StreamData) -> bool + Send>;
+
+struct Shared {
+    event_loop: EventLoop,
+    callbacks: Mutex<HashMap<StreamId, Callback>>,
+}
+
+fn shared() -> &'static Shared {
+    static SHARED: OnceLock<Shared> = OnceLock::new();
+    SHARED.get_or_init(|| {
+        thread::spawn(|| {
+            let shared = shared();
+            shared.event_loop.run(move |stream_id, stream_data| {
+                // the callback runs unlocked, so it may start or stop other
+                // streams itself
+                let callback = shared.callbacks.lock().unwrap().remove(&stream_id);
+                match callback {
+                    Some(mut callback) => {
+                        if callback(stream_data) {
+                            let mut callbacks = shared.callbacks.lock().unwrap();
+                            callbacks.insert(stream_id, callback);
+                        } else {
+                            shared.event_loop.destroy_stream(stream_id);
+                        }
+                    }
+                    None => shared.event_loop.destroy_stream(stream_id),
+                }
+            });
+        });
+        Shared {
+            event_loop: EventLoop::new(),
+            callbacks: Mutex::new(HashMap::new()),
+        }
+    })
+}
+
+/// builds an output stream and starts playing it. the stream is destroyed once
+/// `callback` returns false.
+pub(crate) fn play_output(
+    device: &Device,
+    format: &Format,
+    callback: impl FnMut(StreamData) -> bool + Send + 'static,
+) -> Result<StreamId, CreationError> {
+    let stream_id = shared().event_loop.build_output_stream(device, format)?;
+    Ok(play(stream_id, Box::new(callback)))
+}
+
+/// builds an input stream and starts recording from it. the stream is destroyed
+/// once `callback` returns false.
+pub(crate) fn play_input(
+    device: &Device,
+    format: &Format,
+    callback: impl FnMut(StreamData) -> bool + Send + 'static,
+) -> Result<StreamId, CreationError> {
+    let stream_id = shared().event_loop.build_input_stream(device, format)?;
+    Ok(play(stream_id, Box::new(callback)))
+}
+
+fn play(stream_id: StreamId, callback: Callback) -> StreamId {
+    let shared = shared();
+    shared
+        .callbacks
+        .lock()
+        .unwrap()
+        .insert(stream_id.clone(), callback);
+    shared.event_loop.play_stream(stream_id.clone());
+    stream_id
+}
+
+/// an audio device and the stream formats it supports.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub default_input: bool,
+    pub default_output: bool,
+    pub input_formats: Vec<SupportedFormat>,
+    pub output_formats: Vec<SupportedFormat>,
+}
+
+/// lists the devices of the platform's audio host. devices that disappear while
+/// being queried are listed without formats.
+pub fn devices() -> Vec<DeviceInfo> {
+    let default_input = cpal::default_input_device().map(|device| device.name());
+    let default_output = cpal::default_output_device().map(|device| device.name());
+    cpal::devices()
+        .map(|device| {
+            let name = device.name();
+            DeviceInfo {
+                default_input: default_input.as_ref() == Some(&name),
+                default_output: default_output.as_ref() == Some(&name),
+                input_formats: device
+                    .supported_input_formats()
+                    .map(|formats| formats.collect())
+                    .unwrap_or_default(),
+                output_formats: device
+                    .supported_output_formats()
+                    .map(|formats| formats.collect())
+                    .unwrap_or_default(),
+                name: name,
+            }
+        })
+        .collect()
+}
+
+/// the default input device, or the one called `name`.
+pub(crate) fn input_device(name: Option<&str>) -> Result<Device, Error> {
+    match name {
+        Some(name) => cpal::input_devices()
+            .find(|device| device.name() == name)
+            .ok_or_else(|| Error::DeviceNotFound(name.to_string())),
+        None => cpal::default_input_device().ok_or(Error::NoDevice),
+    }
+}
+
+/// the default output device, or the one called `name`.
+pub(crate) fn output_device(name: Option<&str>) -> Result<Device, Error> {
+    match name {
+        Some(name) => cpal::output_devices()
+            .find(|device| device.name() == name)
+            .ok_or_else(|| Error::DeviceNotFound(name.to_string())),
+        None => cpal::default_output_device().ok_or(Error::NoDevice),
+    }
+}
+
+/// picks the channel count and sample format of a stream carrying `channels`
+/// channel frames. the frame's own channel count is preferred, then stereo, mono
+/// and the widest format; `F32` is preferred over `I16` and `U16`.
+pub(crate) fn negotiate(
+    formats: &[SupportedFormat],
+    channels: usize,
+) -> Option<(u16, SampleFormat)> {
+    let widest = formats.iter().map(|f| f.channels).max()?;
+    let channels = [channels as u16, 2, 1, widest]
+        .iter()
+        .copied()
+        .find(|&channels| formats.iter().any(|f| f.channels == channels))?;
+    let data_type = [SampleFormat::F32, SampleFormat::I16, SampleFormat::U16]
+        .iter()
+        .copied()
+        .find(|&data_type| {
+            formats
+                .iter()
+                .any(|f| f.channels == channels && f.data_type == data_type)
+        })?;
+    Some((channels, data_type))
+}
+
+/// common rates within the ranges of the formats matching `channels` and `data_type`.
+pub(crate) fn supported_freqs(
+    formats: &[SupportedFormat],
+    channels: u16,
+    data_type: SampleFormat,
+) -> Vec<u32> {
+    let mut supported: Vec<u32> = formats
+        .iter()
+        .filter(|f| f.channels == channels && f.data_type == data_type)
+        .flat_map(|f| {
+            COMMON_FREQS
+                .iter()
+                .copied()
+                .filter(move |&freq| f.min_sample_rate.0 <= freq && freq <= f.max_sample_rate.0)
+        })
+        .collect();
+    supported.sort();
+    supported.dedup();
+    supported
+}
+
+/// interleaves `frames` into a device buffer of `channels` channels, folding them
+/// to stereo or mono when the channel counts differ. the rest of the buffer is
+/// filled with silence.
+pub(crate) fn write_frames<S, T>(
+    frames: &[S],
+    buffer: &mut [T],
+    channels: usize,
+    raw: &mut [S::Member],
+) where
+    S: Sample,
+    S::Member: IntoSampleType<f32>,
+    T: cpal::Sample,
+{
+    let silence = T::from(&0.0f32);
+    for (i, out) in buffer.chunks_mut(channels).enumerate() {
+        out.iter_mut().for_each(|s| *s = silence);
+        let frame = match frames.get(i) {
+            Some(&frame) => frame,
+            None => continue,
+        };
+        if channels == S::CHANNELS {
+            frame.to_raw(raw);
+            for (s, &member) in out.iter_mut().zip(raw.iter()) {
+                let member: f32 = member.into_sampletype();
+                *s = T::from(&member);
+            }
+        } else if channels == 1 {
+            let Mono(m): Mono<f32> = frame.into_sample();
+            out[0] = T::from(&m);
+        } else {
+            let Stereo { l, r }: Stereo<f32> = frame.into_sample();
+            out[0] = T::from(&l);
+            out[1] = T::from(&r);
+        }
+    }
+}
+
+/// deinterleaves a device buffer of `channels` channels into `frames`, converting
+/// from the first two channels or the only one when the channel counts differ.
+pub(crate) fn read_frames<S, T>(
+    buffer: &[T],
+    frames: &mut Vec<S>,
+    channels: usize,
+    raw: &mut [S::Member],
+) where
+    S: Sample + FromSample<Stereo<f32>> + FromSample<Mono<f32>>,
+    S::Member: FromSampleType<f32>,
+    T: cpal::Sample,
+{
+    frames.clear();
+    for sample in buffer.chunks_exact(channels) {
+        let frame = if channels == S::CHANNELS {
+            for (member, s) in raw.iter_mut().zip(sample.iter()) {
+                *member = s.to_f32().into_sampletype();
+            }
+            S::from_raw(raw)
+        } else if channels == 1 {
+            Some(Mono(sample[0].to_f32()).into_sample())
+        } else {
+            Some(
+                Stereo {
+                    l: sample[0].to_f32(),
+                    r: sample[1].to_f32(),
+                }
+                .into_sample(),
+            )
+        };
+        frames.extend(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::SampleRate;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    fn format(channels: u16, data_type: SampleFormat, min: u32, max: u32) -> SupportedFormat {
+        SupportedFormat {
+            channels: channels,
+            min_sample_rate: SampleRate(min),
+            max_sample_rate: SampleRate(max),
+            data_type: data_type,
+        }
+    }
+
+    fn formats(formats: &[(u16, SampleFormat)]) -> Vec<SupportedFormat> {
+        formats
+            .iter()
+            .map(|&(channels, data_type)| format(channels, data_type, 44100, 48000))
+            .collect()
+    }
+
+    #[test]
+    fn negotiate_prefers_the_frame_then_stereo_mono_and_widest() {
+        use SampleFormat::*;
+        let f = formats(&[(2, F32), (6, I16), (8, F32)]);
+        assert_eq!(negotiate(&f, 6), Some((6, I16)));
+        let f = formats(&[(1, F32), (8, F32), (2, I16)]);
+        assert_eq!(negotiate(&f, 6), Some((2, I16)));
+        let f = formats(&[(8, F32), (1, U16), (4, F32)]);
+        assert_eq!(negotiate(&f, 6), Some((1, U16)));
+        let f = formats(&[(4, F32), (8, I16), (6, F32)]);
+        assert_eq!(negotiate(&f, 3), Some((8, I16)));
+        assert_eq!(negotiate(&[], 2), None);
+    }
+
+    #[test]
+    fn negotiate_prefers_f32_then_i16_then_u16() {
+        use SampleFormat::*;
+        let f = formats(&[(2, U16), (2, I16), (2, F32), (1, F32)]);
+        assert_eq!(negotiate(&f, 2), Some((2, F32)));
+        let f = formats(&[(2, U16), (2, I16), (1, F32)]);
+        assert_eq!(negotiate(&f, 2), Some((2, I16)));
+        let f = formats(&[(2, U16), (1, F32)]);
+        assert_eq!(negotiate(&f, 2), Some((2, U16)));
+    }
+
+    #[test]
+    fn supported_freqs_are_common_rates_in_range() {
+        use SampleFormat::*;
+        let f = [
+            format(2, F32, 44100, 48000),
+            format(2, F32, 8000, 16000),
+            format(2, F32, 40000, 45000),
+            format(2, F32, 50000, 60000),
+            format(2, I16, 96000, 96000),
+            format(1, F32, 192000, 192000),
+        ];
+        assert_eq!(
+            supported_freqs(&f, 2, F32),
+            [8000, 11025, 16000, 44100, 48000]
+        );
+        assert_eq!(supported_freqs(&f, 2, I16), [96000]);
+        assert_eq!(supported_freqs(&f, 1, F32), [192000]);
+        assert!(supported_freqs(&f, 1, I16).is_empty());
+        assert!(supported_freqs(&[format(2, F32, 50000, 60000)], 2, F32).is_empty());
+    }
+
+    #[test]
+    fn write_frames_interleaves_and_folds() {
+        let frames = [Stereo { l: 0.5f32, r: -0.5 }, Stereo { l: 0.25, r: 0.75 }];
+        let mut raw = [0.0; 2];
+        let mut buffer = [1.0f32; 4];
+        write_frames(&frames, &mut buffer, 2, &mut raw);
+        assert_eq!(buffer, [0.5, -0.5, 0.25, 0.75]);
+        let mut buffer = [1.0f32; 2];
+        write_frames(&frames, &mut buffer, 1, &mut raw);
+        assert_eq!(buffer, [0.0, 0.5]);
+
+        // back channels are folded in at -3 dB
+        let frames = [Quad {
+            fl: 0.25f32,
+            fr: 0.5,
+            bl: 0.5,
+            br: 0.0,
+        }];
+        let mut raw = [0.0; 4];
+        let mut buffer = [1.0f32; 2];
+        write_frames(&frames, &mut buffer, 2, &mut raw);
+        assert!((buffer[0] - (0.25 + 0.5 * FRAC_1_SQRT_2)).abs() < 1e-6);
+        assert_eq!(buffer[1], 0.5);
+        let mut buffer = [1.0f32; 1];
+        write_frames(&frames, &mut buffer, 1, &mut raw);
+        assert!((buffer[0] - (0.75 + 0.5 * FRAC_1_SQRT_2) / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn write_frames_pads_with_silence() {
+        let frames = [Stereo { l: 1.0f32, r: -1.0 }];
+        let mut raw = [0.0; 2];
+        let mut buffer = [7i16; 6];
+        write_frames(&frames, &mut buffer, 2, &mut raw);
+        assert_eq!(buffer, [i16::MAX, i16::MIN, 0, 0, 0, 0]);
+        let mut buffer = [7u16; 6];
+        write_frames(&frames, &mut buffer, 3, &mut raw);
+        // a wider stream gets the frame in its first two channels only
+        assert_eq!(buffer[0], u16::MAX);
+        assert_eq!(buffer[2..], [0x8000; 4]);
+    }
+
+    fn pairs(frames: &[Stereo<f64>]) -> Vec<(f64, f64)> {
+        frames.iter().map(|s| (s.l, s.r)).collect()
+    }
+
+    #[test]
+    fn read_frames_deinterleaves() {
+        let mut frames = Vec::new();
+        let mut raw = [0.0; 2];
+        read_frames::<Stereo<f64>, f32>(&[0.5, -0.5, 0.25, 0.75, 1.0], &mut frames, 2, &mut raw);
+        assert_eq!(pairs(&frames), [(0.5, -0.5), (0.25, 0.75)]);
+
+        read_frames::<Stereo<f64>, f32>(&[0.5, -0.5, 0.25], &mut frames, 1, &mut raw);
+        assert_eq!(pairs(&frames), [(0.5, 0.5), (-0.5, -0.5), (0.25, 0.25)]);
+
+        // only the first two channels of a wider stream are read
+        let buffer = [0.5, -0.5, 0.9, 0.25, 0.75, 0.9];
+        read_frames::<Stereo<f64>, f32>(&buffer, &mut frames, 3, &mut raw);
+        assert_eq!(pairs(&frames), [(0.5, -0.5), (0.25, 0.75)]);
+
+        // stereo is folded to mono, and integer samples are scaled
+        let mut frames = Vec::new();
+        let mut raw = [0.0; 1];
+        read_frames::<Mono<f64>, i16>(&[i16::MAX, 0, i16::MIN, i16::MIN], &mut frames, 2, &mut raw);
+        let frames: Vec<f64> = frames.iter().map(|s| s.0).collect();
+        assert_eq!(frames, [0.5, -1.0]);
+    }
+}
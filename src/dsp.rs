@@ -131,6 +131,16 @@ impl BiQuadIIR {
         output
     }
 
+    /// takes over the coefficients of `other` while keeping the filter state.
+    pub fn set_coefficients(&mut self, other: &BiQuadIIR) {
+        self.b0 = other.b0;
+        self.b1 = other.b1;
+        self.b2 = other.b2;
+        self.a0 = other.a0;
+        self.a1 = other.a1;
+        self.a2 = other.a2;
+    }
+
     pub fn new_low_pass_filter(samplerate: f64, freq: f64, q: f64) -> Self {
         let omega = 2.0 * PI * freq / samplerate;
         let alpha = f64::sin(omega) / (2.0 * q);
@@ -230,3 +240,361 @@ impl BiQuadIIR {
         BiQuadIIR::new(b0, b1, b2, a0, a1, a2)
     }
 }
+
+/// one-pole smoothing of a control value, so that steps in the target become
+/// exponential glides.
+pub struct Smoother {
+    value: f64,
+    coef: f64,
+}
+impl Smoother {
+    /// follows the target immediately until `set_time` is called.
+    pub fn new(value: f64) -> Self {
+        Smoother {
+            value: value,
+            coef: 1.0,
+        }
+    }
+    /// `rate` is how often `step` is called per second. after `time` seconds about
+    /// 63% of a step has been covered.
+    pub fn set_time(&mut self, rate: f64, time: f64) {
+        self.coef = if time > 0.0 {
+            1.0 - (-1.0 / (time * rate)).exp()
+        } else {
+            1.0
+        };
+    }
+    pub fn reset(&mut self, value: f64) {
+        self.value = value;
+    }
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+    /// moves one step towards `target`.
+    pub fn step(&mut self, target: f64) -> f64 {
+        self.value += (target - self.value) * self.coef;
+        // snap once inaudibly close so callers can tell the glide has ended
+        if (target - self.value).abs() <= 1e-6 * target.abs().max(1.0) {
+            self.value = target;
+        }
+        self.value
+    }
+}
+
+/// the spectral shape of the noise added when reducing the word length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseShaping {
+    /// flat, white noise.
+    Off,
+    /// first order error feedback, tilting the noise towards high frequencies.
+    FirstOrder,
+    /// the 5 tap E-weighted filter of Lipshitz et al, moving the noise out of the
+    /// range where hearing is most sensitive. designed for 44.1 kHz.
+    Lipshitz,
+}
+impl NoiseShaping {
+    fn coefficients(self) -> &'static [f64] {
+        match self {
+            NoiseShaping::Off => &[],
+            NoiseShaping::FirstOrder => &[1.0],
+            NoiseShaping::Lipshitz => &[2.033, -2.165, 1.959, -1.590, 0.6149],
+        }
+    }
+}
+
+/// reduces one channel to a grid of `bits` bits, with error feedback shaping
+/// the noise. the output stays in floats but converts to integers exactly.
+#[derive(Clone)]
+pub struct Quantizer {
+    scale: f64,
+    coefs: &'static [f64],
+    errors: [f64; 5],
+}
+impl Quantizer {
+    /// `None` unless `bits` is 2 to 32.
+    pub fn new(bits: u32, shaping: NoiseShaping) -> Option<Self> {
+        if !(2..=32).contains(&bits) {
+            return None;
+        }
+        Some(Quantizer {
+            scale: (1u64 << (bits - 1)) as f64,
+            coefs: shaping.coefficients(),
+            errors: [0.0; 5],
+        })
+    }
+    pub fn noise_shaping(self, shaping: NoiseShaping) -> Self {
+        Quantizer {
+            coefs: shaping.coefficients(),
+            ..self
+        }
+    }
+    pub fn reset(&mut self) {
+        self.errors = [0.0; 5];
+    }
+    /// `dither` is added before rounding, in steps of the grid.
+    pub fn quantize(&mut self, x: f64, dither: f64) -> f64 {
+        let mut shaped = x * self.scale;
+        for (c, e) in self.coefs.iter().zip(self.errors.iter()) {
+            shaped -= c * e;
+        }
+        let q = (shaped + dither)
+            .round()
+            .clamp(-self.scale, self.scale - 1.0);
+        // a clipped sample would feed back a huge error and could make the loop
+        // unstable, so the error is limited to what dither and rounding give
+        self.errors.rotate_right(1);
+        self.errors[0] = (q - shaped).clamp(-1.5, 1.5);
+        q / self.scale
+    }
+}
+
+/// converts one channel from one sample rate to another by linear interpolation.
+pub struct LinearResampler {
+    step: f64,
+    pos: f64,
+    prev: f64,
+    cur: f64,
+}
+
+impl LinearResampler {
+    pub fn new(from: f64, to: f64) -> Self {
+        LinearResampler {
+            step: from / to,
+            pos: 2.0,
+            prev: 0.0,
+            cur: 0.0,
+        }
+    }
+
+    /// number of input samples to `push` before the next call to `pop`.
+    pub fn needed(&self) -> usize {
+        self.pos.floor() as usize
+    }
+
+    pub fn push(&mut self, input: f64) {
+        self.prev = self.cur;
+        self.cur = input;
+        self.pos -= 1.0;
+    }
+
+    pub fn pop(&mut self) -> f64 {
+        let output = self.prev + (self.cur - self.prev) * self.pos;
+        self.pos += self.step;
+        output
+    }
+}
+
+/// converts one channel from one sample rate to another with a kaiser-windowed sinc
+/// interpolator. the kernel is tabulated at `phases` points per input sample.
+pub struct SincResampler {
+    step: f64,
+    pos: f64,
+    width: usize,
+    phases: usize,
+    table: Vec<f64>,
+    buf: Vec<f64>,
+    head: usize,
+}
+
+impl SincResampler {
+    /// `zero_crossings` per side sets the kernel length, `beta` the kaiser window
+    /// and `rolloff` the cutoff relative to the lower nyquist frequency.
+    pub fn new(
+        from: f64,
+        to: f64,
+        zero_crossings: usize,
+        phases: usize,
+        beta: f64,
+        rolloff: f64,
+    ) -> Self {
+        let scale = f64::min(1.0, to / from);
+        let cutoff = 0.5 * scale * rolloff;
+        let width = (zero_crossings as f64 / scale).ceil() as usize;
+        let table = (0..width * phases + 2)
+            .map(|i| {
+                let d = i as f64 / phases as f64;
+                2.0 * cutoff * sinc(2.0 * cutoff * d) * kaiser(d / width as f64, beta)
+            })
+            .collect();
+        SincResampler {
+            step: from / to,
+            pos: width as f64 + 1.0,
+            width: width,
+            phases: phases,
+            table: table,
+            buf: vec![0.0; width * 4],
+            head: 0,
+        }
+    }
+
+    /// number of input samples to `push` before the next call to `pop`.
+    pub fn needed(&self) -> usize {
+        self.pos.floor() as usize
+    }
+
+    pub fn push(&mut self, input: f64) {
+        // every sample is stored twice so that the window is always contiguous
+        let len = self.width * 2;
+        self.buf[self.head] = input;
+        self.buf[self.head + len] = input;
+        self.head = (self.head + 1) % len;
+        self.pos -= 1.0;
+    }
+
+    pub fn pop(&mut self) -> f64 {
+        let len = self.width * 2;
+        let window = &self.buf[self.head..self.head + len];
+        // the output lies between window[width - 1] and window[width]
+        let t = (self.width - 1) as f64 + self.pos;
+        let mut output = 0.0;
+        for (i, x) in window.iter().enumerate() {
+            let d = (t - i as f64).abs() * self.phases as f64;
+            let j = d.floor() as usize;
+            let frac = d - j as f64;
+            output += x * (self.table[j] + (self.table[j + 1] - self.table[j]) * frac);
+        }
+        self.pos += self.step;
+        output
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Quality {
+    Linear,
+    Low,
+    Medium,
+    High,
+}
+
+pub enum Resampler {
+    Linear(LinearResampler),
+    Sinc(SincResampler),
+}
+
+impl Resampler {
+    pub fn new(from: f64, to: f64, quality: Quality) -> Self {
+        match quality {
+            Quality::Linear => Resampler::Linear(LinearResampler::new(from, to)),
+            Quality::Low => Resampler::Sinc(SincResampler::new(from, to, 8, 64, 6.0, 0.85)),
+            Quality::Medium => Resampler::Sinc(SincResampler::new(from, to, 16, 256, 8.6, 0.9)),
+            Quality::High => Resampler::Sinc(SincResampler::new(from, to, 32, 1024, 12.0, 0.94)),
+        }
+    }
+
+    pub fn needed(&self) -> usize {
+        match self {
+            Resampler::Linear(r) => r.needed(),
+            Resampler::Sinc(r) => r.needed(),
+        }
+    }
+
+    pub fn push(&mut self, input: f64) {
+        match self {
+            Resampler::Linear(r) => r.push(input),
+            Resampler::Sinc(r) => r.push(input),
+        }
+    }
+
+    pub fn pop(&mut self) -> f64 {
+        match self {
+            Resampler::Linear(r) => r.pop(),
+            Resampler::Sinc(r) => r.pop(),
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn kaiser(x: f64, beta: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    bessel_i0(beta * (1.0 - x * x).sqrt()) / bessel_i0(beta)
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // peak amplitude of a unit sine at `freq` after resampling, past the
+    // kernel's settling time
+    fn gain(from: f64, to: f64, freq: f64, quality: Quality) -> f64 {
+        let mut resampler = Resampler::new(from, to, quality);
+        let mut n = 0;
+        let mut output = Vec::new();
+        while output.len() < 8192 {
+            for _ in 0..resampler.needed() {
+                resampler.push((2.0 * PI * freq * n as f64 / from).sin());
+                n += 1;
+            }
+            output.push(resampler.pop());
+        }
+        let steady = &output[2048..];
+        let power = steady.iter().map(|x| x * x).sum::<f64>() / steady.len() as f64;
+        (2.0 * power).sqrt()
+    }
+
+    fn db(x: f64) -> f64 {
+        20.0 * x.log10()
+    }
+
+    // quality, top of the passband in Hz, max ripple and min stopband rejection
+    // in dB. linear interpolation only gets a token bound on aliasing.
+    const BOUNDS: [(Quality, f64, f64, f64); 4] = [
+        (Quality::Linear, 4000.0, 0.5, 3.0),
+        (Quality::Low, 15000.0, 0.5, 45.0),
+        (Quality::Medium, 17000.0, 0.2, 80.0),
+        (Quality::High, 19000.0, 0.1, 110.0),
+    ];
+
+    #[test]
+    fn resampler_passband_and_stopband() {
+        for &(quality, top, ripple, rejection) in BOUNDS.iter() {
+            // a sweep through the passband, upsampling 44.1 kHz to 48 kHz
+            let mut freq = 100.0;
+            let mut gains = Vec::new();
+            while freq <= top {
+                gains.push(gain(44100.0, 48000.0, freq, quality));
+                freq *= 1.25;
+            }
+            let min = gains.iter().cloned().fold(f64::MAX, f64::min);
+            let max = gains.iter().cloned().fold(0.0, f64::max);
+            // tones above the output nyquist when downsampling must not alias back
+            let stop = [23500.0, 23900.0]
+                .iter()
+                .map(|&f| gain(48000.0, 44100.0, f, quality))
+                .fold(0.0, f64::max);
+            let (measured_ripple, measured_rejection) = (db(max / min), -db(stop));
+            assert!(
+                measured_ripple < ripple,
+                "{:?}: {}",
+                quality,
+                measured_ripple
+            );
+            assert!(
+                measured_rejection > rejection,
+                "{:?}: {}",
+                quality,
+                measured_rejection
+            );
+        }
+    }
+}
@@ -1,79 +1,218 @@
 use crate::context::FreqCtx;
+use crate::device;
 use crate::dsp::*;
+use crate::error::Error;
+use crate::graph::BoxedElement;
+use crate::param::*;
 use crate::sample::*;
 use crate::wav::*;
 use crate::*;
 
 use std::fs::File;
+use std::io::{Read, Seek, Write};
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
 // Input / Output
 
-pub struct WAVSource<Src> {
-    wav: WAV,
-    pos: usize,
+/// reads a WAV stream. a read error ends the stream and is kept in the
+/// source's `WAVSourceStatus`.
+pub struct WAVSource<Src, R = File> {
+    reader: WAVReader<R>,
+    looping: bool,
+    resampler: Option<FrameResampler>,
+    status: WAVSourceStatus,
     src_type: PhantomData<Src>,
 }
 impl<Src> WAVSource<Src> {
     pub fn new(filename: &str) -> Result<Self, wav::Error> {
         let file = File::open(filename)?;
-        let wav = WAV::new(file)?;
-        Ok(Self {
-            wav: wav,
-            pos: 0,
+        Self::from_reader(file)
+    }
+}
+impl<Src, R: Read + Seek> WAVSource<Src, R> {
+    pub fn from_reader(inner: R) -> Result<Self, wav::Error> {
+        Ok(Self::from_wav_reader(WAVReader::new(inner)?))
+    }
+    /// reads from an opened stream, such as one whose header was looked at first.
+    pub fn from_wav_reader(reader: WAVReader<R>) -> Self {
+        Self {
+            reader: reader,
+            looping: false,
+            resampler: None,
+            status: WAVSourceStatus {
+                error: Arc::new(Mutex::new(None)),
+            },
             src_type: PhantomData,
-        })
+        }
+    }
+    pub fn looping(self, looping: bool) -> Self {
+        Self {
+            looping: looping,
+            ..self
+        }
     }
+    pub fn status(&self) -> WAVSourceStatus {
+        self.status.clone()
+    }
+}
+fn read_frame<Src, R>(
+    reader: &mut WAVReader<R>,
+    looping: bool,
+    status: &WAVSourceStatus,
+) -> Option<Src>
+where
+    Src: FromLayout,
+    Src::Member: FromSampleType<i32> + FromSampleType<f64>,
+    R: Read + Seek,
+{
+    let read = match reader.read_sample_as() {
+        Ok(None) if looping && reader.position() > 0 => {
+            reader.seek(0).and_then(|()| reader.read_sample_as())
+        }
+        read => read,
+    };
+    read.unwrap_or_else(|err| {
+        status.fail(err);
+        None
+    })
 }
-impl<Ctx, Src> Element<(), Ctx> for WAVSource<Src>
+impl<Ctx, Src, R> Element<(), Ctx> for WAVSource<Src, R>
 where
     Ctx: FreqCtx,
-    Src: Sample,
-    Src::Member: FromSampleType<i16>,
+    Src: FromLayout,
+    Src::Member: FromSampleType<i32> + FromSampleType<f64> + IntoSampleType<f64>,
+    R: Read + Seek,
 {
     type Src = Src;
-    fn init(&mut self, ctx: &mut Ctx) {
-        ctx.set_supported_freq(&[self.wav.samplerate]);
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        self.reader.check_frame::<Src>()?;
+        if ctx.get_auto_resample() {
+            ctx.set_preferred_freq(&[self.reader.header.samplerate]);
+        } else {
+            ctx.set_supported_freq(&[self.reader.header.samplerate]);
+        }
+        Ok(())
     }
-    fn next(&mut self, _sink: (), _ctx: &Ctx) -> Src {
-        self.pos += 1;
-        match self.wav.get_sample_as::<Src>(self.pos - 1) {
-            Some(s) => s,
-            None => {
-                self.pos = 1;
-                self.wav.get_sample_as::<Src>(self.pos - 1).unwrap()
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        let (from, to) = (self.reader.header.samplerate as f64, ctx.get_freq() as f64);
+        self.resampler = if from != to {
+            Some(FrameResampler::new(
+                Src::CHANNELS,
+                from,
+                to,
+                Quality::Medium,
+            )?)
+        } else {
+            None
+        };
+        Ok(())
+    }
+    fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<Src> {
+        let (reader, looping, status) = (&mut self.reader, self.looping, &self.status);
+        match self.resampler {
+            Some(ref mut resampler) => {
+                resampler.next(|| read_frame::<Src, R>(reader, looping, status))
+            }
+            None => read_frame(reader, looping, status),
+        }
+    }
+    fn process(&mut self, input: &[()], output: &mut [Src], ctx: &Ctx) -> usize {
+        if self.resampler.is_some() {
+            for (i, src) in output.iter_mut().take(input.len()).enumerate() {
+                match self.next((), ctx) {
+                    Some(s) => *src = s,
+                    None => return i,
+                }
+            }
+            return input.len().min(output.len());
+        }
+        let len = input.len().min(output.len());
+        let mut done = 0;
+        loop {
+            match self.reader.read_samples_as(&mut output[done..len]) {
+                Ok(n) => done += n,
+                Err(err) => {
+                    self.status.fail(err);
+                    break;
+                }
+            }
+            if !self.looping || done == len || self.reader.position() == 0 {
+                break;
+            }
+            if let Err(err) = self.reader.seek(0) {
+                self.status.fail(err);
+                break;
             }
         }
+        done
+    }
+}
+
+/// the outcome of a `WAVSource`, readable from any thread.
+#[derive(Clone)]
+pub struct WAVSourceStatus {
+    error: Arc<Mutex<Option<wav::Error>>>,
+}
+impl WAVSourceStatus {
+    /// the first error the source ran into while reading or looping. the
+    /// stream ended early if there was one.
+    pub fn take_error(&self) -> Option<wav::Error> {
+        self.error.lock().unwrap().take()
+    }
+    fn fail(&self, err: wav::Error) {
+        self.error.lock().unwrap().get_or_insert(err);
     }
 }
 
 pub struct SineWave<Src> {
-    freq: f64,
-    pos: usize,
+    freq: Lane,
+    phase: f64,
+    playhead: Playhead,
     src_type: PhantomData<Src>,
 }
 impl<Src> SineWave<Src> {
     pub fn new(freq: f64) -> Self {
         Self {
-            freq: freq,
-            pos: 0,
+            freq: Lane::new(Param::new("freq", 0.0, 96000.0, "Hz", freq)),
+            phase: 0.0,
+            playhead: Playhead::new(),
             src_type: PhantomData,
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        if name != "freq" {
+            return Err(unknown_param("SineWave", name));
+        }
+        self.freq.automation = Some(automation);
+        Ok(self)
+    }
+}
+impl<Src> Params for SineWave<Src> {
+    fn params(&self) -> Vec<Param> {
+        vec![self.freq.param.clone()]
+    }
 }
 impl<Ctx, Src> Element<(), Ctx> for SineWave<Src>
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     Src: FromSample<Mono<f64>>,
 {
     type Src = Src;
-    fn next(&mut self, _sink: (), ctx: &Ctx) -> Src {
-        self.pos += 1;
-        Mono::new(
-            (2.0 * std::f64::consts::PI * self.pos as f64 * self.freq / ctx.get_freq() as f64)
-                .sin(),
-        )
-        .into_sample()
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.phase = 0.0;
+        self.playhead.reset(ctx.get_position());
+        Ok(())
+    }
+    fn next(&mut self, _sink: (), ctx: &Ctx) -> Option<Src> {
+        // the phase is accumulated so frequency changes do not jump
+        let freq = self.freq.value(self.playhead.advance(ctx.get_position()));
+        self.phase += freq / ctx.get_freq() as f64;
+        self.phase -= self.phase.floor();
+        let src = Mono::new((2.0 * std::f64::consts::PI * self.phase).sin());
+        Some(src.into_sample())
     }
 }
 
@@ -92,65 +231,233 @@ where
     Src: FromSample<Mono<f64>>,
 {
     type Src = Src;
-    fn next(&mut self, _sink: (), _ctx: &Ctx) -> Src {
+    fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<Src> {
         use rand::prelude::*;
         let mut rng = rand::thread_rng();
-        Mono::new(rng.gen_range(-1f64, 1.)).into_sample()
+        Some(Mono::new(rng.gen_range(-1f64, 1.)).into_sample())
     }
 }
 
-pub struct DefaultSink;
-impl DefaultSink {
+pub struct DefaultSource<Src> {
+    device_name: Option<String>,
+    device: Option<(cpal::Device, u16, cpal::SampleFormat)>,
+    handle: Option<PipelineHandle>,
+    src_type: PhantomData<Src>,
+}
+impl<Src> DefaultSource<Src> {
     pub fn new() -> Self {
-        DefaultSink {}
+        DefaultSource {
+            device_name: None,
+            device: None,
+            handle: None,
+            src_type: PhantomData,
+        }
+    }
+    /// captures from the input device called `name`, as listed by `device::devices`.
+    pub fn device(self, name: &str) -> Self {
+        DefaultSource {
+            device_name: Some(name.to_string()),
+            ..self
+        }
     }
 }
-impl<S, Ctx> PullElement<S, Ctx> for DefaultSink
+impl<Src> Default for DefaultSource<Src> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<Src, Ctx> PushElement<Ctx> for DefaultSource<Src>
 where
-    S: IntoSample<Stereo<f32>>,
-    Ctx: FreqCtx + Sync,
+    Src: Sample + FromSample<Stereo<f32>> + FromSample<Mono<f32>> + Send + 'static,
+    Src::Member: FromSampleType<f32> + Send,
+    Ctx: Context + FreqCtx + Send + 'static,
 {
-    fn init(&mut self, ctx: &mut Ctx) {
+    type Src = Src;
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        let device = device::input_device(self.device_name.as_deref())?;
+        let formats: Vec<_> = device.supported_input_formats()?.collect();
+        let (channels, data_type) =
+            device::negotiate(&formats, Src::CHANNELS).ok_or(Error::NoFormat)?;
+        let supported = device::supported_freqs(&formats, channels, data_type);
+        if !supported.is_empty() {
+            ctx.set_supported_freq(&supported);
+        }
+        let format = device.default_input_format()?;
+        ctx.set_preferred_freq(&[format.sample_rate.0]);
+        self.device = Some((device, channels, data_type));
+        Ok(())
+    }
+
+    fn start(
+        &mut self,
+        mut src: impl Element<Src, Ctx, Src = ()> + Send + 'static,
+        mut ctx: Ctx,
+        handle: PipelineHandle,
+    ) -> Result<(), Error> {
         use cpal::*;
 
-        let device = default_output_device().expect("no output device available");
-        let format = device
-            .default_output_format()
-            .expect("no output device available");
+        let (device, channels, data_type) = self
+            .device
+            .take()
+            .expect("DefaultSource started before init");
+        let format = Format {
+            channels: channels,
+            sample_rate: SampleRate(ctx.get_freq()),
+            data_type: data_type,
+        };
+        self.handle = Some(handle.clone());
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+        let channels = format.channels as usize;
+        let mut raw = vec![Src::Member::REF_LEVEL; Src::CHANNELS];
+        let mut position = 0;
+        let mut worker = Some(handle.attach());
+        device::play_input(&device, &format, move |stream_data| {
+            // frames captured while paused are dropped
+            if handle.state() == PipelineState::Playing {
+                match stream_data {
+                    StreamData::Input {
+                        buffer: UnknownTypeInputBuffer::U16(buffer),
+                    } => device::read_frames(&buffer, &mut input, channels, &mut raw),
+                    StreamData::Input {
+                        buffer: UnknownTypeInputBuffer::I16(buffer),
+                    } => device::read_frames(&buffer, &mut input, channels, &mut raw),
+                    StreamData::Input {
+                        buffer: UnknownTypeInputBuffer::F32(buffer),
+                    } => device::read_frames(&buffer, &mut input, channels, &mut raw),
+                    StreamData::Output { .. } => input.clear(),
+                }
+                output.resize(input.len(), ());
+                ctx.set_position(position);
+                let n = src.process(&input, &mut output, &ctx);
+                if n < input.len() {
+                    handle.finish();
+                }
+                position += n as u64;
+            }
+            // whichever side ended the pipeline, finish the chain once
+            if handle.is_done() {
+                src.finish(&ctx);
+                worker.take();
+                return false;
+            }
+            true
+        })?;
+        Ok(())
+    }
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.stop();
+        }
+    }
+}
+
+pub struct DefaultSink {
+    device_name: Option<String>,
+    device: Option<(cpal::Device, u16, cpal::SampleFormat)>,
+    handle: Option<PipelineHandle>,
+}
+impl DefaultSink {
+    pub fn new() -> Self {
+        DefaultSink {
+            device_name: None,
+            device: None,
+            handle: None,
+        }
+    }
+    /// plays on the output device called `name`, as listed by `device::devices`.
+    pub fn device(self, name: &str) -> Self {
+        DefaultSink {
+            device_name: Some(name.to_string()),
+            ..self
+        }
+    }
+}
+impl<S, Ctx> PullElement<S, Ctx> for DefaultSink
+where
+    S: Sample + Send + 'static,
+    S::Member: IntoSampleType<f32> + Send,
+    Ctx: Context + FreqCtx + Send + 'static,
+{
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        let device = device::output_device(self.device_name.as_deref())?;
+        let formats: Vec<_> = device.supported_output_formats()?.collect();
+        let (channels, data_type) =
+            device::negotiate(&formats, S::CHANNELS).ok_or(Error::NoFormat)?;
+        let supported = device::supported_freqs(&formats, channels, data_type);
+        if !supported.is_empty() {
+            ctx.set_supported_freq(&supported);
+        }
+        let format = device.default_output_format()?;
         ctx.set_preferred_freq(&[format.sample_rate.0]);
+        self.device = Some((device, channels, data_type));
+        Ok(())
     }
 
-    fn start(&mut self, mut sink: impl Element<(), Ctx, Src = S> + Send, ctx: &Ctx) {
+    fn start(
+        &mut self,
+        mut sink: impl Element<(), Ctx, Src = S> + Send + 'static,
+        mut ctx: Ctx,
+        handle: PipelineHandle,
+    ) -> Result<(), Error> {
         use cpal::*;
 
-        let event_loop = EventLoop::new();
-        let device = default_output_device().expect("no output device available");
+        let (device, channels, data_type) =
+            self.device.take().expect("DefaultSink started before init");
         let format = Format {
-            channels: 2,
+            channels: channels,
             sample_rate: SampleRate(ctx.get_freq()),
-            data_type: SampleFormat::F32,
+            data_type: data_type,
         };
-        let stream_id = event_loop
-            .build_output_stream(&device, &format)
-            .expect("Failed to build stream");
-        event_loop.play_stream(stream_id);
-        event_loop.run(move |_stream_id, stream_data| {
-            match stream_data {
-                StreamData::Output {
-                    buffer: UnknownTypeOutputBuffer::F32(mut buffer),
-                } => {
-                    for sample in buffer.chunks_mut(format.channels as usize) {
-                        let Stereo { l, r } = sink.next((), ctx).into_sample();
-                        sample[0] = l;
-                        sample[1] = r;
+        self.handle = Some(handle.clone());
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+        let channels = format.channels as usize;
+        let mut raw = vec![S::Member::REF_LEVEL; S::CHANNELS];
+        let mut position = 0;
+        let mut worker = Some(handle.attach());
+        device::play_output(&device, &format, move |stream_data| {
+            if let StreamData::Output { buffer } = stream_data {
+                let frames = buffer.len() / channels;
+                // while paused, keep the device fed with silence
+                let mut n = 0;
+                if handle.state() == PipelineState::Playing {
+                    input.resize(frames, ());
+                    output.resize(frames, S::SILENCE);
+                    ctx.set_position(position);
+                    n = sink.process(&input, &mut output, &ctx);
+                    if n < frames {
+                        handle.finish();
                     }
+                    position += n as u64;
                 }
-                _ => panic!(),
-            };
-        });
+                let output = &output[..n];
+                match buffer {
+                    UnknownTypeOutputBuffer::U16(mut buffer) => {
+                        device::write_frames(output, &mut buffer, channels, &mut raw)
+                    }
+                    UnknownTypeOutputBuffer::I16(mut buffer) => {
+                        device::write_frames(output, &mut buffer, channels, &mut raw)
+                    }
+                    UnknownTypeOutputBuffer::F32(mut buffer) => {
+                        device::write_frames(output, &mut buffer, channels, &mut raw)
+                    }
+                }
+            }
+            // whichever side ended the pipeline, finish the chain once
+            if handle.is_done() {
+                sink.finish(&ctx);
+                worker.take();
+                return false;
+            }
+            true
+        })?;
+        Ok(())
     }
     fn stop(&mut self) {
-        unimplemented!();
+        if let Some(handle) = self.handle.take() {
+            handle.stop();
+        }
     }
 }
 
@@ -162,8 +469,9 @@ impl NullSink {
 }
 impl<T, Ctx> Element<T, Ctx> for NullSink {
     type Src = ();
-    fn next(&mut self, _sink: T, _ctx: &Ctx) {
+    fn next(&mut self, _sink: T, _ctx: &Ctx) -> Option<()> {
         // do nothing
+        Some(())
     }
 }
 
@@ -178,8 +486,143 @@ where
     T: std::fmt::Debug,
 {
     type Src = ();
-    fn next(&mut self, sink: T, _ctx: &Ctx) {
+    fn next(&mut self, sink: T, _ctx: &Ctx) -> Option<()> {
         println!("{:?}", sink);
+        Some(())
+    }
+}
+
+/// writes the stream to a WAV file, as many channels as `S` has. a write error
+/// ends the stream and is kept in the sink's `WAVSinkStatus`.
+pub struct WAVSink<S, W: Write + Seek = File> {
+    inner: Option<W>,
+    // set by `new`, the file is created when the sink starts
+    filename: Option<String>,
+    writer: Option<SinkWriter<W>>,
+    format: WAVFormat,
+    status: WAVSinkStatus,
+    sink_type: PhantomData<S>,
+}
+impl<S> WAVSink<S> {
+    /// the file is created when the sink starts.
+    pub fn new(filename: &str) -> Self {
+        Self {
+            filename: Some(filename.to_string()),
+            ..Self::from_writer_opt(None)
+        }
+    }
+}
+impl<S, W: Write + Seek> WAVSink<S, W> {
+    pub fn from_writer(inner: W) -> Self {
+        Self::from_writer_opt(Some(inner))
+    }
+    fn from_writer_opt(inner: Option<W>) -> Self {
+        Self {
+            inner: inner,
+            filename: None,
+            writer: None,
+            format: WAVFormat::PCM16,
+            status: WAVSinkStatus {
+                error: Arc::new(Mutex::new(None)),
+            },
+            sink_type: PhantomData,
+        }
+    }
+    /// defaults to `WAVFormat::PCM16`.
+    pub fn format(self, format: WAVFormat) -> Self {
+        Self {
+            format: format,
+            ..self
+        }
+    }
+    pub fn status(&self) -> WAVSinkStatus {
+        self.status.clone()
+    }
+}
+impl<S, W, Ctx> Element<S, Ctx> for WAVSink<S, W>
+where
+    S: Sample,
+    S::Member: IntoSampleType<f64>,
+    W: Write + Seek,
+    Ctx: FreqCtx,
+{
+    type Src = ();
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        let (format, channels, freq) = (self.format, S::CHANNELS as u16, ctx.get_freq());
+        if let Some(filename) = self.filename.take() {
+            let file = File::create(filename).map_err(wav::Error::Io)?;
+            let writer = WAVWriter::new(file, format, channels, freq)?;
+            self.writer = Some(SinkWriter::File(writer));
+        }
+        if let Some(inner) = self.inner.take() {
+            let writer = WAVWriter::new(inner, format, channels, freq)?;
+            self.writer = Some(SinkWriter::Writer(writer));
+        }
+        Ok(())
+    }
+    fn next(&mut self, sink: S, ctx: &Ctx) -> Option<()> {
+        match self.process(&[sink], &mut [()], ctx) {
+            1 => Some(()),
+            _ => None,
+        }
+    }
+    fn process(&mut self, input: &[S], output: &mut [()], _ctx: &Ctx) -> usize {
+        let len = input.len().min(output.len());
+        match self.writer.as_mut().map(|w| w.write_samples(&input[..len])) {
+            Some(Ok(())) => len,
+            Some(Err(err)) => {
+                self.status.fail(err);
+                0
+            }
+            None => 0,
+        }
+    }
+    fn finish(&mut self, _ctx: &Ctx) {
+        if let Some(writer) = self.writer.take() {
+            if let Err(err) = writer.finalize() {
+                self.status.fail(err);
+            }
+        }
+    }
+}
+
+// the file a sink made with `new` created, or the writer it was given
+enum SinkWriter<W: Write + Seek> {
+    File(WAVWriter<File>),
+    Writer(WAVWriter<W>),
+}
+impl<W: Write + Seek> SinkWriter<W> {
+    fn write_samples<S>(&mut self, samples: &[S]) -> Result<(), wav::Error>
+    where
+        S: Sample,
+        S::Member: IntoSampleType<f64>,
+    {
+        match self {
+            SinkWriter::File(writer) => writer.write_samples(samples),
+            SinkWriter::Writer(writer) => writer.write_samples(samples),
+        }
+    }
+    fn finalize(self) -> Result<(), wav::Error> {
+        match self {
+            SinkWriter::File(writer) => writer.finalize(),
+            SinkWriter::Writer(writer) => writer.finalize(),
+        }
+    }
+}
+
+/// the outcome of a `WAVSink`, readable from any thread.
+#[derive(Clone)]
+pub struct WAVSinkStatus {
+    error: Arc<Mutex<Option<wav::Error>>>,
+}
+impl WAVSinkStatus {
+    /// the first error the sink ran into while writing frames or patching the
+    /// header when finished. the file is incomplete if there was one.
+    pub fn take_error(&self) -> Option<wav::Error> {
+        self.error.lock().unwrap().take()
+    }
+    fn fail(&self, err: wav::Error) {
+        self.error.lock().unwrap().get_or_insert(err);
     }
 }
 
@@ -193,8 +636,8 @@ impl Ident {
 }
 impl<T, Ctx> Element<T, Ctx> for Ident {
     type Src = T;
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        sink
+    fn next(&mut self, sink: T, _ctx: &Ctx) -> Option<T> {
+        Some(sink)
     }
 }
 
@@ -212,9 +655,9 @@ where
     T: Copy,
 {
     type Src = T;
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
+    fn next(&mut self, sink: T, _ctx: &Ctx) -> Option<T> {
         (self.f)(sink);
-        sink
+        Some(sink)
     }
 }
 
@@ -233,377 +676,2314 @@ where
     T2: Copy,
 {
     type Src = T2;
-    fn next(&mut self, sink: T1, _ctx: &Ctx) -> T2 {
-        (self.f)(sink)
+    fn next(&mut self, sink: T1, _ctx: &Ctx) -> Option<T2> {
+        Some((self.f)(sink))
+    }
+}
+
+struct Branch<T, Ctx> {
+    chain: BoxedElement<T, (), Ctx>,
+    ended: bool,
+}
+
+/// feeds the stream to any number of branches, each ending in its own sink, and
+/// passes it on unchanged. each block reaches the branches in the order they were
+/// added before it goes downstream. a branch that ends, such as a `WAVSink` that
+/// failed to write, is no longer fed while the rest of the pipeline goes on.
+pub struct Split<T, Ctx> {
+    branches: Vec<Branch<T, Ctx>>,
+}
+impl<T, Ctx> Split<T, Ctx> {
+    pub fn new() -> Self {
+        Self {
+            branches: Vec::new(),
+        }
+    }
+    pub fn branch<A>(mut self, chain: A) -> Self
+    where
+        A: Element<T, Ctx, Src = ()> + Send + 'static,
+        T: Copy,
+    {
+        self.branches.push(Branch {
+            chain: BoxedElement::new(chain),
+            ended: false,
+        });
+        self
+    }
+}
+impl<T, Ctx> Default for Split<T, Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T, Ctx> Element<T, Ctx> for Split<T, Ctx>
+where
+    T: Copy,
+{
+    type Src = T;
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        for branch in self.branches.iter_mut() {
+            branch.chain.init(ctx)?;
+        }
+        Ok(())
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        for branch in self.branches.iter_mut() {
+            branch.chain.start(ctx)?;
+            branch.ended = false;
+        }
+        Ok(())
+    }
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        for branch in self.branches.iter_mut().filter(|branch| !branch.ended) {
+            branch.ended = branch.chain.next(sink, ctx).is_none();
+        }
+        Some(sink)
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize {
+        let len = input.len().min(output.len());
+        let mut done = [(); BLOCK_SIZE];
+        for input in input[..len].chunks(BLOCK_SIZE) {
+            for branch in self.branches.iter_mut().filter(|branch| !branch.ended) {
+                let n = branch.chain.process(input, &mut done[..input.len()], ctx);
+                branch.ended = n < input.len();
+            }
+        }
+        output[..len].copy_from_slice(&input[..len]);
+        len
+    }
+    fn finish(&mut self, ctx: &Ctx) {
+        for branch in self.branches.iter_mut() {
+            branch.chain.finish(ctx);
+        }
     }
 }
 
 // DSP Element
 
+fn unknown_param(element: &'static str, name: &str) -> Error {
+    Error::UnknownParam {
+        element: element,
+        name: name.to_string(),
+    }
+}
+
 pub struct Gain {
-    mag: f64,
+    gain: Lane,
+    db: f64,
+    target: f64,
+    mag: Smoother,
+    playhead: Playhead,
 }
 impl Gain {
     pub fn new(gain: f64) -> Self {
+        let mag = f64::powf(10.0, gain / 20.0);
         Self {
-            mag: f64::powf(10.0, gain / 20.0),
+            gain: Lane::new(Param::new("gain", -144.0, 48.0, "dB", gain)),
+            db: gain,
+            target: mag,
+            mag: Smoother::new(mag),
+            playhead: Playhead::new(),
+        }
+    }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        if name != "gain" {
+            return Err(unknown_param("Gain", name));
+        }
+        self.gain.automation = Some(automation);
+        Ok(self)
+    }
+    fn magnitude(&mut self, db: f64) -> f64 {
+        if db != self.db {
+            self.db = db;
+            self.target = f64::powf(10.0, db / 20.0);
+        }
+        self.target
+    }
+    fn target(&mut self) -> f64 {
+        let db = self.gain.param.get();
+        self.magnitude(db)
+    }
+    fn start_smoothing(&mut self, samplerate: f64, position: u64) {
+        self.playhead.reset(position);
+        let db = self.gain.value(position);
+        let target = self.magnitude(db);
+        self.mag.reset(target);
+        self.mag.set_time(samplerate, PARAM_SMOOTHING_TIME);
+    }
+    // automation is followed exactly, control changes glide
+    fn next_mag(&mut self, target: f64, position: u64) -> f64 {
+        let frame = self.playhead.advance(position);
+        match self.gain.automated(frame) {
+            Some(db) => {
+                let mag = self.magnitude(db);
+                self.mag.reset(mag);
+                mag
+            }
+            None => self.mag.step(target),
         }
     }
 }
+impl Params for Gain {
+    fn params(&self) -> Vec<Param> {
+        vec![self.gain.param.clone()]
+    }
+}
 impl<T, Ctx> Element<Stereo<T>, Ctx> for Gain
 where
+    Ctx: Context + FreqCtx,
     T: SampleType + IntoSampleType<f64>,
 {
     type Src = Stereo<f64>;
-    fn next(&mut self, sink: Stereo<T>, _ctx: &Ctx) -> Stereo<f64> {
-        sink.map(|x| x.into_sampletype() * self.mag)
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.start_smoothing(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
+    }
+    fn next(&mut self, sink: Stereo<T>, ctx: &Ctx) -> Option<Stereo<f64>> {
+        let target = self.target();
+        let mag = self.next_mag(target, ctx.get_position());
+        Some(sink.map(|x| x.into_sampletype() * mag))
+    }
+    fn process(&mut self, input: &[Stereo<T>], output: &mut [Stereo<f64>], ctx: &Ctx) -> usize {
+        let target = self.target();
+        let position = ctx.get_position();
+        for (sink, src) in input.iter().zip(output.iter_mut()) {
+            let mag = self.next_mag(target, position);
+            *src = sink.map(|x| x.into_sampletype() * mag);
+        }
+        input.len().min(output.len())
     }
 }
 impl<T, Ctx> Element<Mono<T>, Ctx> for Gain
 where
+    Ctx: Context + FreqCtx,
     T: SampleType + IntoSampleType<f64>,
 {
     type Src = Mono<f64>;
-    fn next(&mut self, sink: Mono<T>, _ctx: &Ctx) -> Mono<f64> {
-        sink.map(|x| x.into_sampletype() * self.mag)
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.start_smoothing(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
+    }
+    fn next(&mut self, sink: Mono<T>, ctx: &Ctx) -> Option<Mono<f64>> {
+        let target = self.target();
+        let mag = self.next_mag(target, ctx.get_position());
+        Some(sink.map(|x| x.into_sampletype() * mag))
+    }
+    fn process(&mut self, input: &[Mono<T>], output: &mut [Mono<f64>], ctx: &Ctx) -> usize {
+        let target = self.target();
+        let position = ctx.get_position();
+        for (sink, src) in input.iter().zip(output.iter_mut()) {
+            let mag = self.next_mag(target, position);
+            *src = sink.map(|x| x.into_sampletype() * mag);
+        }
+        input.len().min(output.len())
     }
 }
 
 pub struct Limiter {
-    mag: f64,
+    th: Param,
 }
 impl Limiter {
     pub fn new(th: f64) -> Self {
         Self {
-            mag: f64::powf(10.0, th / 20.0),
+            th: Param::new("threshold", -96.0, 24.0, "dB", th),
         }
     }
+    fn mag(&self) -> f64 {
+        f64::powf(10.0, self.th.get() / 20.0)
+    }
+}
+impl Params for Limiter {
+    fn params(&self) -> Vec<Param> {
+        vec![self.th.clone()]
+    }
 }
 impl<Ctx> Element<Stereo<f64>, Ctx> for Limiter {
     type Src = Stereo<f64>;
-    fn next(&mut self, sink: Stereo<f64>, _ctx: &Ctx) -> Stereo<f64> {
-        sink.map(|x| {
-            if x > self.mag {
-                self.mag
-            } else if -x > self.mag {
-                -self.mag
-            } else {
-                x
-            }
-        })
+    fn next(&mut self, sink: Stereo<f64>, _ctx: &Ctx) -> Option<Stereo<f64>> {
+        let mag = self.mag();
+        Some(sink.map(|x| x.clamp(-mag, mag)))
+    }
+    fn process(&mut self, input: &[Stereo<f64>], output: &mut [Stereo<f64>], _ctx: &Ctx) -> usize {
+        let mag = self.mag();
+        for (sink, src) in input.iter().zip(output.iter_mut()) {
+            *src = sink.map(|x| x.clamp(-mag, mag));
+        }
+        input.len().min(output.len())
     }
 }
 impl<Ctx> Element<Mono<f64>, Ctx> for Limiter {
     type Src = Mono<f64>;
-    fn next(&mut self, sink: Mono<f64>, _ctx: &Ctx) -> Mono<f64> {
-        sink.map(|x| {
-            if x > self.mag {
-                self.mag
-            } else if -x > self.mag {
-                -self.mag
+    fn next(&mut self, sink: Mono<f64>, _ctx: &Ctx) -> Option<Mono<f64>> {
+        let mag = self.mag();
+        Some(sink.map(|x| x.clamp(-mag, mag)))
+    }
+    fn process(&mut self, input: &[Mono<f64>], output: &mut [Mono<f64>], _ctx: &Ctx) -> usize {
+        let mag = self.mag();
+        for (sink, src) in input.iter().zip(output.iter_mut()) {
+            *src = sink.map(|x| x.clamp(-mag, mag));
+        }
+        input.len().min(output.len())
+    }
+}
+
+/// time constant of the glide when a parameter is changed while running.
+const PARAM_SMOOTHING_TIME: f64 = 0.01;
+/// frames between coefficient updates while a biquad parameter glides.
+const BIQUAD_UPDATE_INTERVAL: usize = 16;
+
+fn freq_param(freq: f64) -> Param {
+    Param::new("freq", 1.0, 96000.0, "Hz", freq)
+}
+fn q_param(q: f64) -> Param {
+    Param::new("q", 0.01, 100.0, "", q)
+}
+fn bw_param(bw: f64) -> Param {
+    Param::new("bw", 0.01, 8.0, "oct", bw)
+}
+fn gain_param(gain: f64) -> Param {
+    Param::new("gain", -48.0, 48.0, "dB", gain)
+}
+
+// state shared by the biquad filters. `design` maps the parameter values to
+// coefficients. values changed from a control thread glide, with coefficients
+// recomputed every `BIQUAD_UPDATE_INTERVAL` frames; automated values are
+// followed every frame.
+struct BiQuad {
+    design: fn(f64, &[f64]) -> BiQuadIIR,
+    lanes: Vec<Lane>,
+    smoothers: Vec<Smoother>,
+    values: Vec<f64>,
+    samplerate: f64,
+    countdown: usize,
+    playhead: Playhead,
+    iir_l: BiQuadIIR,
+    iir_r: BiQuadIIR,
+}
+impl BiQuad {
+    fn new(design: fn(f64, &[f64]) -> BiQuadIIR, params: Vec<Param>) -> Self {
+        let values: Vec<f64> = params.iter().map(|param| param.get()).collect();
+        Self {
+            design: design,
+            lanes: params.into_iter().map(Lane::new).collect(),
+            smoothers: values.iter().map(|&value| Smoother::new(value)).collect(),
+            values: values,
+            samplerate: 0.0,
+            countdown: 0,
+            playhead: Playhead::new(),
+            iir_l: Default::default(),
+            iir_r: Default::default(),
+        }
+    }
+    fn params(&self) -> Vec<Param> {
+        self.lanes.iter().map(|lane| lane.param.clone()).collect()
+    }
+    fn automate(
+        &mut self,
+        filter: &'static str,
+        name: &str,
+        automation: Automation,
+    ) -> Result<(), Error> {
+        match self.lanes.iter_mut().find(|lane| lane.param.name() == name) {
+            Some(lane) => lane.automation = Some(automation),
+            None => return Err(unknown_param(filter, name)),
+        }
+        Ok(())
+    }
+    fn start(&mut self, samplerate: f64, position: u64) {
+        self.samplerate = samplerate;
+        self.playhead.reset(position);
+        for ((lane, smoother), value) in self
+            .lanes
+            .iter_mut()
+            .zip(self.smoothers.iter_mut())
+            .zip(self.values.iter_mut())
+        {
+            *value = lane.value(position);
+            smoother.reset(*value);
+            smoother.set_time(
+                samplerate / BIQUAD_UPDATE_INTERVAL as f64,
+                PARAM_SMOOTHING_TIME,
+            );
+        }
+        self.iir_l = self.coefficients();
+        self.iir_r = self.coefficients();
+        self.countdown = BIQUAD_UPDATE_INTERVAL;
+    }
+    fn coefficients(&self) -> BiQuadIIR {
+        // keep the centre frequency below nyquist whatever the parameter says
+        let mut values = self.values.clone();
+        values[0] = values[0].min(self.samplerate * 0.49);
+        (self.design)(self.samplerate, &values)
+    }
+    // returns whether a value changed
+    fn update(&mut self, glide: bool, frame: u64) -> bool {
+        let mut changed = false;
+        for ((lane, smoother), value) in self
+            .lanes
+            .iter_mut()
+            .zip(self.smoothers.iter_mut())
+            .zip(self.values.iter_mut())
+        {
+            let next = match lane.automated(frame) {
+                Some(next) => {
+                    smoother.reset(next);
+                    next
+                }
+                None if glide => smoother.step(lane.param.get()),
+                None => continue,
+            };
+            if next != *value {
+                *value = next;
+                changed = true;
+            }
+        }
+        changed
+    }
+    // moves on to the next frame and retunes the filters if a value changed
+    fn tick(&mut self, position: u64) {
+        let glide = self.countdown == 0;
+        if glide {
+            self.countdown = BIQUAD_UPDATE_INTERVAL;
+        }
+        self.countdown -= 1;
+        let frame = self.playhead.advance(position);
+        if self.update(glide, frame) {
+            let coefficients = self.coefficients();
+            self.iir_l.set_coefficients(&coefficients);
+            self.iir_r.set_coefficients(&coefficients);
+        }
+    }
+    fn next(&mut self, sink: Stereo<f64>, position: u64) -> Stereo<f64> {
+        self.tick(position);
+        Stereo {
+            l: self.iir_l.next(sink.l),
+            r: self.iir_r.next(sink.r),
+        }
+    }
+    fn process<T>(&mut self, input: &[T], output: &mut [T], position: u64) -> usize
+    where
+        T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>> + Copy,
+    {
+        let len = input.len().min(output.len());
+        // automated values change every frame, glides only at the next update
+        let automated = self.lanes.iter().any(|lane| lane.automation.is_some());
+        let mut done = 0;
+        while done < len {
+            self.tick(position);
+            let n = if automated {
+                1
             } else {
-                x
+                1 + self.countdown.min(len - done - 1)
+            };
+            self.countdown -= n - 1;
+            self.playhead.skip(n as u64 - 1);
+            let (iir_l, iir_r) = (&mut self.iir_l, &mut self.iir_r);
+            for (sink, src) in input[done..done + n]
+                .iter()
+                .zip(output[done..done + n].iter_mut())
+            {
+                let Stereo { l, r } = (*sink).into_sample();
+                *src = Stereo {
+                    l: iir_l.next(l),
+                    r: iir_r.next(r),
+                }
+                .into_sample();
             }
-        })
+            done += n;
+        }
+        len
     }
 }
 
 pub struct LowPassFilter {
-    freq: f64,
-    q: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl LowPassFilter {
     pub fn new(freq: f64, q: f64) -> Self {
         Self {
-            freq: freq,
-            q: q,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_low_pass_filter(samplerate, p[0], p[1]),
+                vec![freq_param(freq), q_param(q)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("LowPassFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for LowPassFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for LowPassFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l = BiQuadIIR::new_low_pass_filter(ctx.get_freq() as f64, self.freq, self.q);
-        self.iir_r = BiQuadIIR::new_low_pass_filter(ctx.get_freq() as f64, self.freq, self.q);
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
     }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
-        };
-        src.into_sample()
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
     }
 }
 
 pub struct HighPassFilter {
-    freq: f64,
-    q: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl HighPassFilter {
     pub fn new(freq: f64, q: f64) -> Self {
         Self {
-            freq: freq,
-            q: q,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_high_pass_filter(samplerate, p[0], p[1]),
+                vec![freq_param(freq), q_param(q)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("HighPassFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for HighPassFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for HighPassFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l = BiQuadIIR::new_high_pass_filter(ctx.get_freq() as f64, self.freq, self.q);
-        self.iir_r = BiQuadIIR::new_high_pass_filter(ctx.get_freq() as f64, self.freq, self.q);
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
     }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
-        };
-        src.into_sample()
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
     }
 }
 
 pub struct BandPassFilter {
-    freq: f64,
-    bw: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl BandPassFilter {
     pub fn new(freq: f64, bw: f64) -> Self {
         Self {
-            freq: freq,
-            bw: bw,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_band_pass_filter(samplerate, p[0], p[1]),
+                vec![freq_param(freq), bw_param(bw)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("BandPassFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for BandPassFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for BandPassFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l = BiQuadIIR::new_band_pass_filter(ctx.get_freq() as f64, self.freq, self.bw);
-        self.iir_r = BiQuadIIR::new_band_pass_filter(ctx.get_freq() as f64, self.freq, self.bw);
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
     }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
-        };
-        src.into_sample()
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
     }
 }
 
 pub struct NotchFilter {
-    freq: f64,
-    bw: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl NotchFilter {
     pub fn new(freq: f64, bw: f64) -> Self {
         Self {
-            freq: freq,
-            bw: bw,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_notch_filter(samplerate, p[0], p[1]),
+                vec![freq_param(freq), bw_param(bw)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("NotchFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for NotchFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for NotchFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l = BiQuadIIR::new_notch_filter(ctx.get_freq() as f64, self.freq, self.bw);
-        self.iir_r = BiQuadIIR::new_notch_filter(ctx.get_freq() as f64, self.freq, self.bw);
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
     }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
-        };
-        src.into_sample()
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
     }
 }
 
 pub struct LowShelfFilter {
-    freq: f64,
-    q: f64,
-    gain: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl LowShelfFilter {
     pub fn new(freq: f64, q: f64, gain: f64) -> Self {
         Self {
-            freq: freq,
-            q: q,
-            gain: gain,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_low_shelf_filter(samplerate, p[0], p[1], p[2]),
+                vec![freq_param(freq), q_param(q), gain_param(gain)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("LowShelfFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for LowShelfFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for LowShelfFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l =
-            BiQuadIIR::new_low_shelf_filter(ctx.get_freq() as f64, self.freq, self.q, self.gain);
-        self.iir_r =
-            BiQuadIIR::new_low_shelf_filter(ctx.get_freq() as f64, self.freq, self.q, self.gain);
-    }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
-        };
-        src.into_sample()
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
+    }
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
     }
 }
 
 pub struct HighShelfFilter {
-    freq: f64,
-    q: f64,
-    gain: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl HighShelfFilter {
     pub fn new(freq: f64, q: f64, gain: f64) -> Self {
         Self {
-            freq: freq,
-            q: q,
-            gain: gain,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_high_shelf_filter(samplerate, p[0], p[1], p[2]),
+                vec![freq_param(freq), q_param(q), gain_param(gain)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("HighShelfFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for HighShelfFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for HighShelfFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l =
-            BiQuadIIR::new_high_shelf_filter(ctx.get_freq() as f64, self.freq, self.q, self.gain);
-        self.iir_r =
-            BiQuadIIR::new_high_shelf_filter(ctx.get_freq() as f64, self.freq, self.q, self.gain);
-    }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
-        };
-        src.into_sample()
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
+    }
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
     }
 }
 
 pub struct PeakingFilter {
-    freq: f64,
-    bw: f64,
-    gain: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl PeakingFilter {
     pub fn new(freq: f64, bw: f64, gain: f64) -> Self {
         Self {
-            freq: freq,
-            bw: bw,
-            gain: gain,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_peaking_filter(samplerate, p[0], p[1], p[2]),
+                vec![freq_param(freq), bw_param(bw), gain_param(gain)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("PeakingFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for PeakingFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for PeakingFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l =
-            BiQuadIIR::new_peaking_filter(ctx.get_freq() as f64, self.freq, self.bw, self.gain);
-        self.iir_r =
-            BiQuadIIR::new_peaking_filter(ctx.get_freq() as f64, self.freq, self.bw, self.gain);
-    }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
-        };
-        src.into_sample()
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
+    }
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
     }
 }
 
 pub struct AllPassFilter {
-    freq: f64,
-    q: f64,
-    iir_l: BiQuadIIR,
-    iir_r: BiQuadIIR,
+    biquad: BiQuad,
 }
 impl AllPassFilter {
     pub fn new(freq: f64, q: f64) -> Self {
         Self {
-            freq: freq,
-            q: q,
-            iir_l: Default::default(),
-            iir_r: Default::default(),
+            biquad: BiQuad::new(
+                |samplerate, p| BiQuadIIR::new_all_pass_filter(samplerate, p[0], p[1]),
+                vec![freq_param(freq), q_param(q)],
+            ),
         }
     }
+    pub fn automate(mut self, name: &str, automation: Automation) -> Result<Self, Error> {
+        self.biquad.automate("AllPassFilter", name, automation)?;
+        Ok(self)
+    }
+}
+impl Params for AllPassFilter {
+    fn params(&self) -> Vec<Param> {
+        self.biquad.params()
+    }
 }
 impl<T, Ctx> Element<T, Ctx> for AllPassFilter
 where
-    Ctx: FreqCtx,
+    Ctx: Context + FreqCtx,
     T: IntoSample<Stereo<f64>> + FromSample<Stereo<f64>>,
 {
     type Src = T;
-    fn start(&mut self, ctx: &Ctx) {
-        self.iir_l = BiQuadIIR::new_all_pass_filter(ctx.get_freq() as f64, self.freq, self.q);
-        self.iir_r = BiQuadIIR::new_all_pass_filter(ctx.get_freq() as f64, self.freq, self.q);
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.biquad.start(ctx.get_freq() as f64, ctx.get_position());
+        Ok(())
     }
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
-        let sink = sink.into_sample();
-        let src = Stereo {
-            l: self.iir_l.next(sink.l),
-            r: self.iir_r.next(sink.r),
+    fn next(&mut self, sink: T, ctx: &Ctx) -> Option<T> {
+        let src = self.biquad.next(sink.into_sample(), ctx.get_position());
+        Some(src.into_sample())
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        self.biquad.process(input, output, ctx.get_position())
+    }
+}
+
+/// reduces the word length to `bits` bits with TPDF dither, so that a following
+/// integer conversion, as in `WAVSink` or a device, is exact and the rounding
+/// error becomes uncorrelated noise.
+pub struct Dither {
+    quantizer: Quantizer,
+    quantizers: Vec<Quantizer>,
+    rng: rand::rngs::SmallRng,
+}
+impl Dither {
+    /// fails with `Error::UnsupportedBits` unless `bits` is 2 to 32.
+    pub fn new(bits: u32) -> Result<Self, Error> {
+        use rand::prelude::*;
+        let quantizer =
+            Quantizer::new(bits, NoiseShaping::Off).ok_or(Error::UnsupportedBits(bits))?;
+        Ok(Self {
+            quantizer: quantizer,
+            quantizers: Vec::new(),
+            rng: SmallRng::from_entropy(),
+        })
+    }
+    pub fn noise_shaping(self, shaping: NoiseShaping) -> Self {
+        Self {
+            quantizer: self.quantizer.noise_shaping(shaping),
+            ..self
+        }
+    }
+    // one quantizer and one noise value per channel, converted through a stack
+    // buffer as in `FrameResampler`
+    fn dither<T>(&mut self, sink: T) -> Option<T>
+    where
+        T: Sample,
+        T::Member: IntoSampleType<f64> + FromSampleType<f64>,
+    {
+        let mut raw = [T::Member::REF_LEVEL; MAX_CHANNELS as usize];
+        sink.to_raw(&mut raw);
+        let rng = &mut self.rng;
+        for (q, member) in self.quantizers.iter_mut().zip(raw.iter_mut()) {
+            *member = q
+                .quantize((*member).into_sampletype(), tpdf(rng))
+                .into_sampletype();
+        }
+        T::from_raw(&raw[..T::CHANNELS])
+    }
+}
+// triangular noise of one step peak, the sum of two uniform ones
+fn tpdf(rng: &mut rand::rngs::SmallRng) -> f64 {
+    use rand::prelude::*;
+    rng.gen::<f64>() - rng.gen::<f64>()
+}
+impl<T, Ctx> Element<T, Ctx> for Dither
+where
+    T: Sample,
+    T::Member: IntoSampleType<f64> + FromSampleType<f64>,
+{
+    type Src = T;
+    fn start(&mut self, _ctx: &Ctx) -> Result<(), Error> {
+        if T::CHANNELS > MAX_CHANNELS as usize {
+            return Err(Error::TooManyChannels {
+                channels: T::CHANNELS,
+                max: MAX_CHANNELS as usize,
+            });
+        }
+        let mut quantizer = self.quantizer.clone();
+        quantizer.reset();
+        self.quantizers = vec![quantizer; T::CHANNELS];
+        Ok(())
+    }
+    fn next(&mut self, sink: T, _ctx: &Ctx) -> Option<T> {
+        self.dither(sink)
+    }
+    fn process(&mut self, input: &[T], output: &mut [T], _ctx: &Ctx) -> usize
+    where
+        T: Copy,
+    {
+        for (i, (sink, src)) in input.iter().zip(output.iter_mut()).enumerate() {
+            match self.dither(*sink) {
+                Some(s) => *src = s,
+                None => return i,
+            }
+        }
+        input.len().min(output.len())
+    }
+}
+
+// one resampler per channel, so that frames keep their layout. frames are
+// converted through a stack buffer, as in `WAVReader`.
+struct FrameResampler {
+    channels: Vec<Resampler>,
+    ratio: f64,
+    // frames in and out, to tell where the converted input ends
+    pushed: u64,
+    popped: u64,
+    draining: bool,
+}
+impl FrameResampler {
+    fn new(channels: usize, from: f64, to: f64, quality: Quality) -> Result<Self, Error> {
+        if channels > MAX_CHANNELS as usize {
+            return Err(Error::TooManyChannels {
+                channels: channels,
+                max: MAX_CHANNELS as usize,
+            });
+        }
+        Ok(Self {
+            channels: (0..channels)
+                .map(|_| Resampler::new(from, to, quality))
+                .collect(),
+            ratio: to / from,
+            pushed: 0,
+            popped: 0,
+            draining: false,
+        })
+    }
+    // converts frames pulled from `input`. once `input` ends, silence is pushed to
+    // flush the frames still in the kernel, up to the converted input length.
+    fn next<S>(&mut self, mut input: impl FnMut() -> Option<S>) -> Option<S>
+    where
+        S: Sample,
+        S::Member: IntoSampleType<f64> + FromSampleType<f64>,
+    {
+        // every channel needs the same number of frames
+        let needed = self.channels.first().map_or(0, |r| r.needed());
+        for _ in 0..needed {
+            let s = if self.draining { None } else { input() };
+            match s {
+                Some(s) => self.push(s),
+                None => {
+                    self.draining = true;
+                    self.channels.iter_mut().for_each(|r| r.push(0.0));
+                }
+            }
+        }
+        if self.draining && self.popped as f64 >= (self.pushed as f64 * self.ratio).ceil() {
+            return None;
+        }
+        self.popped += 1;
+        self.pop()
+    }
+    // converts one frame of a pushed stream, handing each frame it completes to
+    // `output` for as long as that returns true
+    fn convert<S>(&mut self, s: S, mut output: impl FnMut(S) -> bool) -> bool
+    where
+        S: Sample,
+        S::Member: IntoSampleType<f64> + FromSampleType<f64>,
+    {
+        self.push(s);
+        while self.channels.first().map_or(1, |r| r.needed()) == 0 {
+            self.popped += 1;
+            match self.pop() {
+                Some(s) if output(s) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+    // hands the frames still in the kernel to `output` once a pushed stream ends
+    fn flush<S>(&mut self, mut output: impl FnMut(S) -> bool)
+    where
+        S: Sample,
+        S::Member: IntoSampleType<f64> + FromSampleType<f64>,
+    {
+        while let Some(s) = self.next(|| None) {
+            if !output(s) {
+                break;
+            }
+        }
+    }
+    fn push<S>(&mut self, s: S)
+    where
+        S: Sample,
+        S::Member: IntoSampleType<f64>,
+    {
+        let mut raw = [S::Member::REF_LEVEL; MAX_CHANNELS as usize];
+        s.to_raw(&mut raw);
+        for (r, &member) in self.channels.iter_mut().zip(raw.iter()) {
+            r.push(member.into_sampletype());
+        }
+        self.pushed += 1;
+    }
+    fn pop<S>(&mut self) -> Option<S>
+    where
+        S: Sample,
+        S::Member: FromSampleType<f64>,
+    {
+        let mut raw = [S::Member::REF_LEVEL; MAX_CHANNELS as usize];
+        for (r, member) in self.channels.iter_mut().zip(raw.iter_mut()) {
+            *member = r.pop().into_sampletype();
+        }
+        S::from_raw(&raw[..S::CHANNELS])
+    }
+}
+
+/// converts between a chain running in its own rate domain and the rest of the
+/// pipeline. wrapping a source chain converts its output to the pipeline rate;
+/// wrapping a sink chain, such as one fed by a capture `PushElement` or a
+/// device that only runs at some rates, converts the frames pushed into it.
+pub struct Resample<A, Ctx> {
+    inner: A,
+    inner_ctx: Option<Ctx>,
+    quality: Quality,
+    freq: Option<u32>,
+    resampler: Option<FrameResampler>,
+}
+impl<A, Ctx> Resample<A, Ctx> {
+    pub fn new(inner: A, quality: Quality) -> Self {
+        Self {
+            inner: inner,
+            inner_ctx: None,
+            quality: quality,
+            freq: None,
+            resampler: None,
+        }
+    }
+    /// fixes the rate on the outside of the wrapped chain.
+    pub fn freq(self, freq: u32) -> Self {
+        Self {
+            freq: Some(freq),
+            ..self
+        }
+    }
+}
+impl<A, Ctx> Resample<A, Ctx>
+where
+    Ctx: Context + FreqCtx + Default,
+{
+    fn init_inner<F>(&mut self, ctx: &mut Ctx, init: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut A, &mut Ctx) -> Result<(), Error>,
+    {
+        let mut inner_ctx = ctx.split_freq();
+        init(&mut self.inner, &mut inner_ctx)?;
+        // prefer passing the frames through unconverted when the inner rate is known
+        if let Ok(freq) = inner_ctx.decide_freq() {
+            ctx.set_preferred_freq(&[freq]);
+        }
+        if let Some(freq) = self.freq {
+            ctx.set_supported_freq(&[freq]);
+        }
+        self.inner_ctx = Some(inner_ctx);
+        Ok(())
+    }
+    // starts the inner chain and returns its rate
+    fn start_inner<F>(&mut self, ctx: &Ctx, start: F) -> Result<f64, Error>
+    where
+        F: FnOnce(&mut A, &Ctx) -> Result<(), Error>,
+    {
+        let mut inner_ctx = self.inner_ctx.take().expect("Resample started before init");
+        inner_ctx.set_preferred_freq(&[ctx.get_freq()]);
+        let inner_ctx = self.inner_ctx.get_or_insert(inner_ctx.build()?);
+        start(&mut self.inner, inner_ctx)?;
+        Ok(inner_ctx.get_freq() as f64)
+    }
+    fn resampler(&mut self, channels: usize, from: f64, to: f64) -> Result<(), Error> {
+        self.resampler = if from != to {
+            Some(FrameResampler::new(channels, from, to, self.quality)?)
+        } else {
+            None
         };
-        src.into_sample()
+        Ok(())
+    }
+    // the pipeline position in frames of the inner rate
+    fn follow_position(&mut self, ctx: &Ctx) {
+        if let Some(ref mut inner_ctx) = self.inner_ctx {
+            let ratio = inner_ctx.get_freq() as f64 / ctx.get_freq() as f64;
+            inner_ctx.set_position((ctx.get_position() as f64 * ratio).round() as u64);
+        }
+    }
+}
+impl<A, Ctx> Element<(), Ctx> for Resample<A, Ctx>
+where
+    A: Element<(), Ctx>,
+    A::Src: Sample,
+    <A::Src as Sample>::Member: IntoSampleType<f64> + FromSampleType<f64>,
+    Ctx: Context + FreqCtx + Default,
+{
+    type Src = A::Src;
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        self.init_inner(ctx, |inner, inner_ctx| inner.init(inner_ctx))
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        let from = self.start_inner(ctx, |inner, inner_ctx| inner.start(inner_ctx))?;
+        self.resampler(A::Src::CHANNELS, from, ctx.get_freq() as f64)
+    }
+    fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<A::Src> {
+        let inner_ctx = self.inner_ctx.as_ref()?;
+        match self.resampler {
+            Some(ref mut resampler) => {
+                let inner = &mut self.inner;
+                resampler.next(|| inner.next((), inner_ctx))
+            }
+            None => self.inner.next((), inner_ctx),
+        }
+    }
+    fn process(&mut self, input: &[()], output: &mut [A::Src], ctx: &Ctx) -> usize {
+        self.follow_position(ctx);
+        match (self.resampler.is_some(), self.inner_ctx.as_ref()) {
+            (false, Some(inner_ctx)) => self.inner.process(input, output, inner_ctx),
+            _ => {
+                for (i, src) in output.iter_mut().take(input.len()).enumerate() {
+                    match self.next((), ctx) {
+                        Some(s) => *src = s,
+                        None => return i,
+                    }
+                }
+                input.len().min(output.len())
+            }
+        }
+    }
+    fn finish(&mut self, _ctx: &Ctx) {
+        if let Some(ref inner_ctx) = self.inner_ctx {
+            self.inner.finish(inner_ctx);
+        }
+    }
+}
+impl<S, A, Ctx> Element<S, Ctx> for Resample<A, Ctx>
+where
+    S: Sample,
+    S::Member: IntoSampleType<f64> + FromSampleType<f64>,
+    A: Element<S, Ctx, Src = ()>,
+    Ctx: Context + FreqCtx + Default,
+{
+    type Src = ();
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        self.init_inner(ctx, |inner, inner_ctx| inner.init(inner_ctx))
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        let to = self.start_inner(ctx, |inner, inner_ctx| inner.start(inner_ctx))?;
+        self.resampler(S::CHANNELS, ctx.get_freq() as f64, to)
+    }
+    fn next(&mut self, sink: S, _ctx: &Ctx) -> Option<()> {
+        let inner_ctx = self.inner_ctx.as_ref()?;
+        let inner = &mut self.inner;
+        match self.resampler {
+            Some(ref mut resampler) => {
+                if resampler.convert(sink, |s| inner.next(s, inner_ctx).is_some()) {
+                    Some(())
+                } else {
+                    None
+                }
+            }
+            None => inner.next(sink, inner_ctx),
+        }
+    }
+    fn process(&mut self, input: &[S], output: &mut [()], ctx: &Ctx) -> usize {
+        self.follow_position(ctx);
+        match (self.resampler.is_some(), self.inner_ctx.as_ref()) {
+            (false, Some(inner_ctx)) => self.inner.process(input, output, inner_ctx),
+            _ => {
+                let len = input.len().min(output.len());
+                for (i, &sink) in input[..len].iter().enumerate() {
+                    if self.next(sink, ctx).is_none() {
+                        return i;
+                    }
+                }
+                len
+            }
+        }
+    }
+    fn finish(&mut self, _ctx: &Ctx) {
+        if let Some(ref inner_ctx) = self.inner_ctx {
+            let inner = &mut self.inner;
+            if let Some(ref mut resampler) = self.resampler {
+                resampler.flush(|s| inner.next(s, inner_ctx).is_some());
+            }
+            inner.finish(inner_ctx);
+        }
+    }
+}
+
+/// runs `inner` on its own thread, ahead of the rest of the pipeline, through a
+/// ring buffer. when the buffer runs dry the queue outputs silence rather than
+/// blocking the caller.
+pub struct Queue<A, Src> {
+    inner: Option<A>,
+    capacity: usize,
+    low_water: Option<usize>,
+    consumer: Option<ring::Consumer<Src>>,
+    thread: Option<thread::JoinHandle<()>>,
+    stats: QueueStats,
+}
+impl<A, Src> Queue<A, Src> {
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner: Some(inner),
+            capacity: 4096,
+            low_water: None,
+            consumer: None,
+            thread: None,
+            stats: QueueStats::new(),
+        }
+    }
+    /// frames buffered ahead at most. defaults to 4096.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(BLOCK_SIZE);
+        self
+    }
+    /// the producer thread refills the buffer once it drains down to `low_water`
+    /// frames. defaults to half the capacity.
+    pub fn low_water(mut self, low_water: usize) -> Self {
+        self.low_water = Some(low_water);
+        self
+    }
+    pub fn stats(&self) -> QueueStats {
+        self.stats.clone()
+    }
+    fn wake(&self) {
+        if let Some(ref thread) = self.thread {
+            thread.thread().unpark();
+        }
+    }
+    // stops the producer and waits for it to finish `inner`
+    fn close(&mut self) -> thread::Result<()> {
+        self.stats.shared.closed.store(true, Ordering::Release);
+        self.wake();
+        match self.thread.take() {
+            Some(thread) => thread.join(),
+            None => Ok(()),
+        }
+    }
+}
+impl<A, Ctx> Element<(), Ctx> for Queue<A, A::Src>
+where
+    A: Element<(), Ctx> + Send + 'static,
+    A::Src: Sample + Default + Send + 'static,
+    Ctx: Context + Clone + Send + 'static,
+{
+    type Src = A::Src;
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        match self.inner {
+            Some(ref mut inner) => inner.init(ctx),
+            None => Ok(()),
+        }
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        let mut inner = match self.inner.take() {
+            Some(inner) => inner,
+            None => return Ok(()),
+        };
+        inner.start(ctx)?;
+        let (mut producer, consumer) = ring::channel(self.capacity);
+        let shared = self.stats.shared.clone();
+        let mut ctx = ctx.clone();
+        let thread = thread::spawn(move || {
+            let _finished = Finished(shared.clone());
+            let input = [(); BLOCK_SIZE];
+            let mut output = [A::Src::SILENCE; BLOCK_SIZE];
+            // frames are played after everything pushed before them and any
+            // silence the consumer has filled in so far
+            let mut position = ctx.get_position();
+            while !shared.closed.load(Ordering::Acquire) {
+                if producer.capacity() - producer.len() < BLOCK_SIZE {
+                    thread::park();
+                    continue;
+                }
+                let silence = shared.silence.load(Ordering::Acquire) as u64;
+                ctx.set_position(position + silence);
+                let n = inner.process(&input, &mut output, &ctx);
+                position += n as u64;
+                producer.push_slice(&output[..n]);
+                shared.fill.store(producer.len(), Ordering::Relaxed);
+                if n < BLOCK_SIZE {
+                    shared.finished.store(true, Ordering::Release);
+                }
+                shared.notify();
+                if n < BLOCK_SIZE {
+                    break;
+                }
+            }
+            inner.finish(&ctx);
+        });
+        self.thread = Some(thread);
+        // fill up before the first frame is pulled
+        let low_water = self.low_water.unwrap_or(self.capacity / 2);
+        let low_water = low_water.min(self.capacity - BLOCK_SIZE);
+        self.low_water = Some(low_water);
+        let shared = self.stats.shared.clone();
+        let mut lock = shared.lock.lock().unwrap();
+        while consumer.len() < low_water && !shared.finished.load(Ordering::Acquire) {
+            lock = shared.filled.wait(lock).unwrap();
+        }
+        drop(lock);
+        if shared.finished.load(Ordering::Acquire) {
+            self.close().map_err(|_| Error::Panicked)?;
+        }
+        self.consumer = Some(consumer);
+        Ok(())
+    }
+    fn next(&mut self, _sink: (), ctx: &Ctx) -> Option<A::Src> {
+        let mut output = [A::Src::SILENCE];
+        match self.process(&[()], &mut output, ctx) {
+            1 => Some(output[0]),
+            _ => None,
+        }
+    }
+    fn process(&mut self, input: &[()], output: &mut [A::Src], _ctx: &Ctx) -> usize {
+        let consumer = match self.consumer {
+            Some(ref mut consumer) => consumer,
+            None => return 0,
+        };
+        let shared = &self.stats.shared;
+        let len = input.len().min(output.len());
+        let mut n = consumer.pop_slice(&mut output[..len]);
+        if n < len {
+            if shared.finished.load(Ordering::Acquire) {
+                // the last frames may have been pushed after the first pop
+                n += consumer.pop_slice(&mut output[n..len]);
+                return n;
+            }
+            shared.underruns.fetch_add(1, Ordering::Relaxed);
+            shared.silence.fetch_add(len - n, Ordering::Release);
+            output[n..len].iter_mut().for_each(|s| *s = A::Src::SILENCE);
+        }
+        let fill = consumer.len();
+        shared.fill.store(fill, Ordering::Relaxed);
+        if fill <= self.low_water.unwrap_or(0) {
+            self.wake();
+        }
+        len
+    }
+    fn finish(&mut self, _ctx: &Ctx) {
+        let _ = self.close();
+    }
+}
+impl<A, Src> Drop for Queue<A, Src> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+// ends the stream when the producer returns or panics, so that the consumer
+// never waits for frames that will not come
+struct Finished(Arc<QueueShared>);
+impl Drop for Finished {
+    fn drop(&mut self) {
+        self.0.finished.store(true, Ordering::Release);
+        self.0.notify();
+    }
+}
+
+/// counters of a `Queue`, readable from any thread.
+#[derive(Clone)]
+pub struct QueueStats {
+    shared: Arc<QueueShared>,
+}
+#[derive(Default)]
+struct QueueShared {
+    underruns: AtomicUsize,
+    // frames of silence filled in on underruns
+    silence: AtomicUsize,
+    fill: AtomicUsize,
+    finished: AtomicBool,
+    closed: AtomicBool,
+    // signalled whenever frames are pushed or the stream ends
+    lock: Mutex<()>,
+    filled: Condvar,
+}
+impl QueueShared {
+    fn notify(&self) {
+        // taking the lock orders the notification after a waiter's check
+        drop(self.lock.lock());
+        self.filled.notify_all();
+    }
+}
+impl QueueStats {
+    fn new() -> Self {
+        Self {
+            shared: Arc::new(QueueShared::default()),
+        }
+    }
+    /// times the consumer found the buffer empty before end of stream.
+    pub fn underruns(&self) -> usize {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+    /// frames currently buffered.
+    pub fn fill(&self) -> usize {
+        self.shared.fill.load(Ordering::Relaxed)
+    }
+}
+
+// Mixing
+
+/// how a mixer input is spread over the two channels. `pan` goes from -1 (left)
+/// to 1 (right).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanLaw {
+    /// balance: the centre is at unity, panning only attenuates the other side.
+    Linear,
+    /// constant power, -3 dB per channel at the centre.
+    Minus3dB,
+    /// constant amplitude, -6 dB per channel at the centre.
+    Minus6dB,
+}
+impl PanLaw {
+    pub fn gains(self, pan: f64) -> (f64, f64) {
+        let pan = pan.clamp(-1.0, 1.0);
+        match self {
+            PanLaw::Linear => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+            PanLaw::Minus3dB => {
+                let theta = (pan + 1.0) * std::f64::consts::FRAC_PI_4;
+                (theta.cos(), theta.sin())
+            }
+            PanLaw::Minus6dB => ((1.0 - pan) / 2.0, (1.0 + pan) / 2.0),
+        }
+    }
+}
+
+/// one input of a `Mixer`. take its parameters before adding it to the mixer.
+pub struct MixerInput<Sink, Ctx> {
+    chain: BoxedElement<Sink, Stereo<f64>, Ctx>,
+    gain: Param,
+    pan: Param,
+    mute: Param,
+    solo: Param,
+    mag_l: Smoother,
+    mag_r: Smoother,
+    ended: bool,
+}
+impl<Sink: Copy, Ctx> MixerInput<Sink, Ctx> {
+    pub fn new<A>(chain: A) -> Self
+    where
+        A: Element<Sink, Ctx> + Send + 'static,
+        A::Src: IntoSample<Stereo<f64>> + Copy + Default + 'static,
+    {
+        let to_stereo = FnElement::new(|s: A::Src| -> Stereo<f64> { s.into_sample() });
+        Self {
+            chain: BoxedElement::new(Pipe::new(chain, to_stereo)),
+            gain: Param::new("gain", -144.0, 24.0, "dB", 0.0),
+            pan: Param::new("pan", -1.0, 1.0, "", 0.0),
+            mute: Param::new("mute", 0.0, 1.0, "", 0.0),
+            solo: Param::new("solo", 0.0, 1.0, "", 0.0),
+            mag_l: Smoother::new(0.0),
+            mag_r: Smoother::new(0.0),
+            ended: false,
+        }
+    }
+}
+impl<Sink, Ctx> MixerInput<Sink, Ctx> {
+    pub fn gain(self, gain: f64) -> Self {
+        self.gain.set(gain);
+        self
+    }
+    pub fn pan(self, pan: f64) -> Self {
+        self.pan.set(pan);
+        self
+    }
+    pub fn mute(self, mute: bool) -> Self {
+        self.mute.set(if mute { 1.0 } else { 0.0 });
+        self
+    }
+    pub fn solo(self, solo: bool) -> Self {
+        self.solo.set(if solo { 1.0 } else { 0.0 });
+        self
+    }
+    fn is_soloed(&self) -> bool {
+        self.solo.get() >= 0.5
+    }
+    fn target(&self, pan_law: PanLaw, any_soloed: bool) -> (f64, f64) {
+        if self.mute.get() >= 0.5 || (any_soloed && !self.is_soloed()) {
+            return (0.0, 0.0);
+        }
+        let mag = f64::powf(10.0, self.gain.get() / 20.0);
+        let (l, r) = pan_law.gains(self.pan.get());
+        (l * mag, r * mag)
+    }
+}
+impl<Sink, Ctx> Params for MixerInput<Sink, Ctx> {
+    /// mute and solo are on at 1 and off at 0.
+    fn params(&self) -> Vec<Param> {
+        vec![
+            self.gain.clone(),
+            self.pan.clone(),
+            self.mute.clone(),
+            self.solo.clone(),
+        ]
+    }
+}
+
+/// sums any number of input chains, all fed the same sink frames. the mix ends
+/// once every input has ended; ended inputs are silent until then.
+pub struct Mixer<Sink, Ctx> {
+    inputs: Vec<MixerInput<Sink, Ctx>>,
+    pan_law: PanLaw,
+    master: Param,
+    headroom: f64,
+    master_mag: Smoother,
+}
+impl<Sink, Ctx> Mixer<Sink, Ctx> {
+    pub fn new() -> Self {
+        Self {
+            inputs: Vec::new(),
+            pan_law: PanLaw::Minus3dB,
+            master: Param::new("master", -144.0, 24.0, "dB", 0.0),
+            headroom: 0.0,
+            master_mag: Smoother::new(1.0),
+        }
+    }
+    pub fn input(mut self, input: MixerInput<Sink, Ctx>) -> Self {
+        self.inputs.push(input);
+        self
+    }
+    /// defaults to `PanLaw::Minus3dB`.
+    pub fn pan_law(self, pan_law: PanLaw) -> Self {
+        Self {
+            pan_law: pan_law,
+            ..self
+        }
+    }
+    /// master gain in dB.
+    pub fn master(self, master: f64) -> Self {
+        self.master.set(master);
+        self
+    }
+    /// fixed attenuation of the master bus in dB, so that summing many inputs
+    /// does not clip. defaults to 0.
+    pub fn headroom(self, headroom: f64) -> Self {
+        Self {
+            headroom: headroom,
+            ..self
+        }
+    }
+    fn master_target(&self) -> f64 {
+        f64::powf(10.0, (self.master.get() - self.headroom) / 20.0)
+    }
+}
+impl<Sink, Ctx> Default for Mixer<Sink, Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<Sink, Ctx> Params for Mixer<Sink, Ctx> {
+    fn params(&self) -> Vec<Param> {
+        vec![self.master.clone()]
+    }
+}
+impl<Sink, Ctx> Element<Sink, Ctx> for Mixer<Sink, Ctx>
+where
+    Sink: Copy,
+    Ctx: FreqCtx,
+{
+    type Src = Stereo<f64>;
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        for input in self.inputs.iter_mut() {
+            input.chain.init(ctx)?;
+        }
+        Ok(())
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        let samplerate = ctx.get_freq() as f64;
+        let any_soloed = self.inputs.iter().any(|input| input.is_soloed());
+        for input in self.inputs.iter_mut() {
+            input.chain.start(ctx)?;
+            let (l, r) = input.target(self.pan_law, any_soloed);
+            input.mag_l.reset(l);
+            input.mag_r.reset(r);
+            input.mag_l.set_time(samplerate, PARAM_SMOOTHING_TIME);
+            input.mag_r.set_time(samplerate, PARAM_SMOOTHING_TIME);
+            input.ended = false;
+        }
+        let master = self.master_target();
+        self.master_mag.reset(master);
+        self.master_mag.set_time(samplerate, PARAM_SMOOTHING_TIME);
+        Ok(())
+    }
+    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Option<Stereo<f64>> {
+        let mut output = [Stereo::default()];
+        match self.process(&[sink], &mut output, ctx) {
+            1 => Some(output[0]),
+            _ => None,
+        }
+    }
+    fn process(&mut self, input: &[Sink], output: &mut [Stereo<f64>], ctx: &Ctx) -> usize {
+        let mut mid = [Stereo::default(); BLOCK_SIZE];
+        let mut done = 0;
+        for (input, output) in input.chunks(BLOCK_SIZE).zip(output.chunks_mut(BLOCK_SIZE)) {
+            let len = input.len().min(output.len());
+            for src in output[..len].iter_mut() {
+                *src = Stereo::default();
+            }
+            let any_soloed = self.inputs.iter().any(|input| input.is_soloed());
+            let mut mixed = 0;
+            for track in self.inputs.iter_mut().filter(|input| !input.ended) {
+                let n = track.chain.process(&input[..len], &mut mid[..len], ctx);
+                track.ended = n < len;
+                mixed = mixed.max(n);
+                let (l, r) = track.target(self.pan_law, any_soloed);
+                for (src, mid) in output[..n].iter_mut().zip(mid[..n].iter()) {
+                    src.l += mid.l * track.mag_l.step(l);
+                    src.r += mid.r * track.mag_r.step(r);
+                }
+            }
+            let master = self.master_target();
+            for src in output[..mixed].iter_mut() {
+                let mag = self.master_mag.step(master);
+                *src *= mag;
+            }
+            done += mixed;
+            if mixed < len {
+                break;
+            }
+        }
+        done
+    }
+    fn finish(&mut self, ctx: &Ctx) {
+        for input in self.inputs.iter_mut() {
+            input.chain.finish(ctx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::DefaultContext;
+    use std::io::{self, Cursor, SeekFrom};
+    use std::sync::{Arc, Mutex};
+
+    // a 16-bit PCM stream holding `frames`, interleaved
+    fn wav(channels: u16, samplerate: u32, frames: &[Vec<i16>]) -> Cursor<Vec<u8>> {
+        let data: Vec<u8> = frames
+            .iter()
+            .flat_map(|frame| frame.iter().flat_map(|s| s.to_le_bytes().to_vec()))
+            .collect();
+        let mut v = Vec::new();
+        v.extend_from_slice(b"RIFF");
+        v.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        v.extend_from_slice(b"WAVEfmt ");
+        v.extend_from_slice(&16u32.to_le_bytes());
+        v.extend_from_slice(&1u16.to_le_bytes());
+        v.extend_from_slice(&channels.to_le_bytes());
+        v.extend_from_slice(&samplerate.to_le_bytes());
+        v.extend_from_slice(&(samplerate * channels as u32 * 2).to_le_bytes());
+        v.extend_from_slice(&(channels * 2).to_le_bytes());
+        v.extend_from_slice(&16u16.to_le_bytes());
+        v.extend_from_slice(b"data");
+        v.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        v.extend_from_slice(&data);
+        Cursor::new(v)
+    }
+
+    // a constant source that only runs at one rate, like a file
+    struct FixedRateSource {
+        freq: u32,
+        level: f64,
+        len: usize,
+    }
+    impl<Ctx: FreqCtx> Element<(), Ctx> for FixedRateSource {
+        type Src = Mono<f64>;
+        fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+            ctx.set_supported_freq(&[self.freq]);
+            Ok(())
+        }
+        fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<Mono<f64>> {
+            if self.len == 0 {
+                return None;
+            }
+            self.len -= 1;
+            Some(Mono(self.level))
+        }
+    }
+
+    // a sink that only runs at one rate, like some output devices
+    struct FixedRateSink {
+        freq: u32,
+        frames: Arc<Mutex<Vec<f64>>>,
+    }
+    impl<Ctx: FreqCtx> Element<Mono<f64>, Ctx> for FixedRateSink {
+        type Src = ();
+        fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+            ctx.set_supported_freq(&[self.freq]);
+            Ok(())
+        }
+        fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+            assert_eq!(ctx.get_freq(), self.freq);
+            Ok(())
+        }
+        fn next(&mut self, sink: Mono<f64>, _ctx: &Ctx) -> Option<()> {
+            self.frames.lock().unwrap().push(sink.0);
+            Some(())
+        }
+    }
+
+    // runs a whole chain until its source ends and returns the frames it pulled
+    fn run<A>(mut chain: A, mut ctx: DefaultContext) -> Result<usize, Error>
+    where
+        A: Element<(), DefaultContext, Src = ()>,
+    {
+        chain.init(&mut ctx)?;
+        let ctx = ctx.build()?;
+        chain.start(&ctx)?;
+        let mut n = 0;
+        while chain.next((), &ctx).is_some() {
+            n += 1;
+        }
+        chain.finish(&ctx);
+        Ok(n)
+    }
+
+    #[test]
+    fn biquad_glides_to_a_new_freq() {
+        let mut ctx = DefaultContext::new().freq(48000);
+        let mut filter = LowPassFilter::new(1000.0, 0.7);
+        let freq = filter.param("freq").unwrap();
+        Element::<Stereo<f64>, _>::init(&mut filter, &mut ctx).unwrap();
+        let ctx = ctx.build().unwrap();
+        Element::<Stereo<f64>, _>::start(&mut filter, &ctx).unwrap();
+
+        // a 4 kHz tone is well into the stopband at 1 kHz and passes at 8 kHz
+        let mut t = 0;
+        let mut tone = |filter: &mut LowPassFilter| {
+            let x = (2.0 * std::f64::consts::PI * 4000.0 * t as f64 / 48000.0).sin();
+            t += 1;
+            filter.next(Stereo::new(x), &ctx).unwrap().l
+        };
+        // the peak of every 4 periods, and the cutoff the coefficients follow
+        let mut windows = |filter: &mut LowPassFilter, n: usize| {
+            let mut peaks = Vec::new();
+            let mut cutoffs = Vec::new();
+            for _ in 0..n {
+                let mut peak: f64 = 0.0;
+                for _ in 0..48 {
+                    peak = peak.max(tone(filter).abs());
+                    cutoffs.push(filter.biquad.values[0]);
+                }
+                peaks.push(peak);
+            }
+            (peaks, cutoffs)
+        };
+        let (before, _) = windows(&mut filter, 20);
+        let stopband = before[19];
+        assert!(stopband < 0.1);
+
+        freq.set(8000.0);
+        let (peaks, cutoffs) = windows(&mut filter, 200);
+        // coefficients are only recomputed once per update interval
+        assert!(cutoffs[0] - 1000.0 < 7000.0 * 0.05);
+        for (i, pair) in cutoffs.windows(2).enumerate() {
+            assert!(pair[1] >= pair[0]);
+            if pair[1] != pair[0] {
+                assert_eq!((i + 1) % BIQUAD_UPDATE_INTERVAL, 0);
+                assert!(pair[1] - pair[0] < 7000.0 * 0.05, "{:?}", pair);
+            }
+        }
+        // about 63% of the way after the smoothing time, and there in the end
+        let smoothed = cutoffs[(PARAM_SMOOTHING_TIME * 48000.0) as usize];
+        assert!((smoothed - (1000.0 + 7000.0 * 0.632)).abs() < 7000.0 * 0.05);
+        assert_eq!(*cutoffs.last().unwrap(), 8000.0);
+
+        let passband = peaks[199];
+        assert!(passband > 0.9);
+        // the output rises over several windows instead of in one step
+        assert!(peaks[0] < stopband + (passband - stopband) * 0.25);
+        for pair in peaks.windows(2) {
+            assert!((pair[1] - pair[0]).abs() < (passband - stopband) * 0.25);
+        }
+    }
+
+    #[test]
+    fn wav_source_resamples_every_channel() {
+        let frame: Vec<i16> = (0..6).map(|c| c * 4000).collect();
+        let source =
+            WAVSource::<Surround51<f64>, _>::from_reader(wav(6, 44100, &vec![frame; 4410]));
+        let ctx = DefaultContext::new().freq(48000).auto_resample(true);
+        let out = Offline::new(source.unwrap()).render(ctx).unwrap();
+        assert!((out.len() as i64 - 4800).abs() < 64);
+        let s = out[out.len() / 2];
+        let expected = [s.fl, s.fr, s.c, s.lfe, s.bl, s.br];
+        for (c, &x) in expected.iter().enumerate() {
+            assert!(
+                (x - c as f64 * 4000.0 / 32768.0).abs() < 1e-3,
+                "{} {}",
+                c,
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn resample_keeps_the_channel_layout() {
+        let frame: Vec<i16> = (0..6).map(|c| c * 4000).collect();
+        let source =
+            WAVSource::<Surround51<f64>, _>::from_reader(wav(6, 44100, &vec![frame; 4410]));
+        let resample = Resample::new(source.unwrap(), Quality::Low);
+        let out = Offline::new(resample)
+            .render(DefaultContext::new().freq(32000))
+            .unwrap();
+        assert!((out.len() as i64 - 3200).abs() < 64);
+        let s = out[out.len() / 2];
+        let expected = [s.fl, s.fr, s.c, s.lfe, s.bl, s.br];
+        for (c, &x) in expected.iter().enumerate() {
+            assert!(
+                (x - c as f64 * 4000.0 / 32768.0).abs() < 1e-3,
+                "{} {}",
+                c,
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn resample_flushes_the_kernel_at_the_end() {
+        let source = FixedRateSource {
+            freq: 44100,
+            level: 0.5,
+            len: 1000,
+        };
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let sink = FixedRateSink {
+            freq: 48000,
+            frames: frames.clone(),
+        };
+        let resample = Resample::new(source, Quality::Medium);
+        run(Pipe::new(resample, sink), DefaultContext::new()).unwrap();
+        let out = frames.lock().unwrap();
+        // as long as the input, up to its last frame
+        assert_eq!(out.len(), (1000.0 * 48000.0 / 44100.0_f64).ceil() as usize);
+        assert!(out[100..out.len() - 20]
+            .iter()
+            .all(|s| (s - 0.5).abs() < 1e-2));
+        let last = *out.last().unwrap();
+        assert!(last > 0.1 && last < 0.5, "{}", last);
+    }
+
+    #[test]
+    fn resample_converts_the_input_of_a_sink_chain() {
+        let source_chain = || {
+            let source = FixedRateSource {
+                freq: 44100,
+                level: 0.25,
+                len: 4410,
+            };
+            Pipe::new(source, FnElement::new(|s: Mono<f64>| Mono(s.0 * 2.0)))
+        };
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let sink = || FixedRateSink {
+            freq: 48000,
+            frames: frames.clone(),
+        };
+        assert!(matches!(
+            run(Pipe::new(source_chain(), sink()), DefaultContext::new()),
+            Err(Error::FreqConflict(_))
+        ));
+
+        let resample = Resample::new(sink(), Quality::High);
+        let pulled = run(Pipe::new(source_chain(), resample), DefaultContext::new()).unwrap();
+        assert_eq!(pulled, 4410);
+        let frames = frames.lock().unwrap();
+        // the tail left in the kernel reaches the sink when the stream finishes
+        assert_eq!(frames.len(), 4800);
+        assert!(frames[100..4780].iter().all(|s| (s - 0.5).abs() < 1e-2));
+    }
+
+    #[test]
+    fn resample_limits_the_channels() {
+        assert!(matches!(
+            FrameResampler::new(33, 44100.0, 48000.0, Quality::Low),
+            Err(Error::TooManyChannels {
+                channels: 33,
+                max: 32
+            })
+        ));
+    }
+
+    // pulls blocks from a source until it ends short
+    fn render<A>(mut source: A, mut ctx: DefaultContext) -> Vec<A::Src>
+    where
+        A: Element<(), DefaultContext>,
+        A::Src: Copy + Default,
+    {
+        source.init(&mut ctx).unwrap();
+        let ctx = ctx.build().unwrap();
+        source.start(&ctx).unwrap();
+        let input = [(); BLOCK_SIZE];
+        let mut output = [A::Src::default(); BLOCK_SIZE];
+        let mut frames = Vec::new();
+        loop {
+            let n = source.process(&input, &mut output, &ctx);
+            frames.extend_from_slice(&output[..n]);
+            if n < BLOCK_SIZE {
+                return frames;
+            }
+        }
+    }
+
+    // a file whose bytes from `fail_at` on can't be read
+    struct BadSector {
+        inner: Cursor<Vec<u8>>,
+        fail_at: u64,
+    }
+    impl Read for BadSector {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let left = self.fail_at.saturating_sub(self.inner.position());
+            if left == 0 {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            let n = buf.len().min(left as usize);
+            self.inner.read(&mut buf[..n])
+        }
+    }
+    impl Seek for BadSector {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn wav_source_keeps_read_errors() {
+        let frames: Vec<Vec<i16>> = (0..20000).map(|i| vec![i as i16, -i as i16]).collect();
+        // frames straight from the reader, then one at a time through the resampler
+        for &(freq, looping) in &[(44100, false), (48000, false), (44100, true)] {
+            let source = WAVSource::<Stereo<f64>, _>::from_reader(BadSector {
+                inner: wav(2, 44100, &frames),
+                fail_at: 44 + 4 * 10000,
+            })
+            .unwrap()
+            .looping(looping);
+            let status = source.status();
+            let ctx = DefaultContext::new().freq(freq).auto_resample(true);
+            let out = render(source, ctx);
+            assert!(out.len() < 10000 * freq as usize / 44100 + 64);
+            assert!(matches!(status.take_error(), Some(wav::Error::Io(_))));
+            assert!(status.take_error().is_none());
+        }
+
+        // reaching the end of the data is no error
+        let source = WAVSource::<Stereo<f64>, _>::from_reader(wav(2, 44100, &frames)).unwrap();
+        let status = source.status();
+        let out = render(source, DefaultContext::new().freq(44100));
+        assert_eq!(out.len(), 20000);
+        assert!(status.take_error().is_none());
+    }
+
+    // a file that fills up after `limit` bytes and may refuse to seek back to
+    // patch the header
+    struct FullDisk {
+        inner: Cursor<Vec<u8>>,
+        limit: u64,
+        seekable: bool,
+    }
+    impl Write for FullDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.inner.position() + buf.len() as u64 > self.limit {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FullDisk {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            match pos {
+                SeekFrom::Start(n) if n > 0 && !self.seekable => {
+                    Err(io::ErrorKind::Unsupported.into())
+                }
+                pos => self.inner.seek(pos),
+            }
+        }
+    }
+
+    // writes up to 20000 frames of a sine and returns how many the sink took
+    fn render_to_disk(limit: u64, seekable: bool) -> (usize, WAVSinkStatus) {
+        let mut sink = WAVSink::<Stereo<f64>, _>::from_writer(FullDisk {
+            inner: Cursor::new(Vec::new()),
+            limit: limit,
+            seekable: seekable,
+        });
+        let status = sink.status();
+        let mut source = SineWave::<Stereo<f64>>::new(440.0);
+        let mut ctx = DefaultContext::new().freq(44100);
+        Element::<Stereo<f64>, _>::init(&mut sink, &mut ctx).unwrap();
+        let ctx = ctx.build().unwrap();
+        source.start(&ctx).unwrap();
+        Element::<Stereo<f64>, _>::start(&mut sink, &ctx).unwrap();
+        let mut written = 0;
+        while written < 20000 {
+            let s = source.next((), &ctx).unwrap();
+            if sink.next(s, &ctx).is_none() {
+                break;
+            }
+            written += 1;
+        }
+        Element::<Stereo<f64>, _>::finish(&mut sink, &ctx);
+        (written, status)
+    }
+
+    #[test]
+    fn wav_sink_keeps_write_errors() {
+        let (written, status) = render_to_disk(u64::MAX, true);
+        assert_eq!(written, 20000);
+        assert!(status.take_error().is_none());
+
+        // a full disk ends the stream early instead of passing for its end
+        let (written, status) = render_to_disk(44 + 10000, true);
+        assert!(written < 20000);
+        assert!(matches!(status.take_error(), Some(wav::Error::Io(_))));
+        assert!(status.take_error().is_none());
+
+        // every frame fits, but the header can't be patched
+        let (written, status) = render_to_disk(u64::MAX, false);
+        assert_eq!(written, 20000);
+        assert!(matches!(status.take_error(), Some(wav::Error::Io(_))));
+    }
+
+    #[test]
+    fn wav_source_converts_other_layouts() {
+        let frame: Vec<i16> = (1..7).map(|c| c * 4000).collect();
+        let source = WAVSource::<Stereo<f64>, _>::from_reader(wav(6, 44100, &vec![frame; 100]));
+        let out = render(source.unwrap(), DefaultContext::new());
+        let s51 = Surround51::from_raw(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let expected: Stereo<f64> = s51.map(|s| s * 4000.0 / 32768.0).into_sample();
+        assert_eq!(out.len(), 100);
+        // integer samples scale by their maximum, so allow for a one step error
+        assert!(out
+            .iter()
+            .all(|s| (s.l - expected.l).abs() < 1e-4 && (s.r - expected.r).abs() < 1e-4));
+
+        let mut reader = WAVReader::new(wav(1, 44100, &[vec![8000]])).unwrap();
+        let s: Quad<f32> = reader.read_sample_as().unwrap().unwrap();
+        // mono goes to the front pair
+        assert!((s.fl - 8000.0 / 32768.0).abs() < 1e-4);
+        assert_eq!((s.fl, s.bl, s.br), (s.fr, 0.0, 0.0));
+    }
+
+    #[test]
+    fn wav_source_rejects_unmapped_layouts() {
+        let mut source = WAVSource::<Stereo<f64>, _>::from_reader(wav(3, 44100, &[vec![0; 3]]));
+        match source.as_mut().unwrap().init(&mut DefaultContext::new()) {
+            Err(Error::WAV(wav::Error::ChannelMismatch {
+                channels: 3,
+                frame_channels: 2,
+            })) => {}
+            other => panic!("{:?}", other.err()),
+        }
+        let mut reader = WAVReader::new(wav(3, 44100, &[vec![1, 2, 3]])).unwrap();
+        let s: [i16; 3] = reader.read_sample_as().unwrap().unwrap();
+        assert_eq!(s, [1, 2, 3]);
+    }
+
+    struct Counter {
+        n: usize,
+        finished: Arc<AtomicBool>,
+    }
+    impl<Ctx> Element<(), Ctx> for Counter {
+        type Src = Mono<f64>;
+        fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<Mono<f64>> {
+            assert!(self.n < 1000, "out of frames");
+            self.n += 1;
+            Some(Mono(self.n as f64))
+        }
+        fn finish(&mut self, _ctx: &Ctx) {
+            thread::sleep(std::time::Duration::from_millis(20));
+            self.finished.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn queue_joins_the_producer_in_finish() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let counter = Counter {
+            n: 0,
+            finished: finished.clone(),
+        };
+        let mut queue = Queue::new(counter).capacity(256);
+        let mut ctx = DefaultContext::new().freq(44100);
+        queue.init(&mut ctx).unwrap();
+        let ctx = ctx.build().unwrap();
+        queue.start(&ctx).unwrap();
+        assert_eq!(queue.next((), &ctx).map(|s| s.0), Some(1.0));
+        queue.finish(&ctx);
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn queue_fails_if_the_producer_panics() {
+        let counter = Counter {
+            n: 999,
+            finished: Arc::new(AtomicBool::new(false)),
+        };
+        let mut queue = Queue::new(counter);
+        let mut ctx = DefaultContext::new().freq(44100);
+        queue.init(&mut ctx).unwrap();
+        match queue.start(&ctx.build().unwrap()) {
+            Err(Error::Panicked) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn automation_follows_the_pipeline_position() {
+        let automation =
+            Automation::new()
+                .point(0, -144.0, Curve::Hold)
+                .point(1000, 0.0, Curve::Hold);
+        let mut gain = Gain::new(0.0).automate("gain", automation).unwrap();
+        let mut ctx = DefaultContext::new().freq(48000).build().unwrap();
+        Element::<Mono<f64>, _>::start(&mut gain, &ctx).unwrap();
+        let input = [Mono(1.0); 64];
+        let mut output = [Mono(0.0); 64];
+        ctx.set_position(968);
+        assert_eq!(gain.process(&input, &mut output, &ctx), 64);
+        assert!(output[31].0 < 1e-6);
+        assert_eq!(output[32].0, 1.0);
+        // a jump, such as after a queue underrun
+        ctx.set_position(500);
+        gain.process(&input, &mut output, &ctx);
+        assert!(output[63].0 < 1e-6);
+        assert!(Gain::new(0.0).automate("freq", Automation::new()).is_err());
+    }
+
+    #[test]
+    fn dither_quantizes_every_channel() {
+        let mut dither = Dither::new(8).unwrap();
+        Element::<Surround51<f64>, ()>::start(&mut dither, &()).unwrap();
+        let sink = Surround51 {
+            fl: 0.1,
+            fr: -0.2,
+            c: 0.3,
+            lfe: -0.4,
+            bl: 0.5,
+            br: -0.6,
+        };
+        for _ in 0..100 {
+            let src = dither.next(sink, &()).unwrap();
+            let (mut raw, mut orig) = ([0.0f64; 6], [0.0f64; 6]);
+            src.to_raw(&mut raw);
+            sink.to_raw(&mut orig);
+            for (x, o) in raw.iter().zip(orig.iter()) {
+                assert_eq!((x * 128.0).fract(), 0.0);
+                assert!((x - o).abs() <= 1.5 / 128.0);
+            }
+        }
+        assert!(Dither::new(1).is_err());
+        assert!(Dither::new(33).is_err());
+        let mut dither = Dither::new(16).unwrap();
+        match Element::<[f64; 33], ()>::start(&mut dither, &()) {
+            Err(Error::TooManyChannels {
+                channels: 33,
+                max: 32,
+            }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    // `frames` frames of `value`, counted again from every `start`
+    struct Constant {
+        value: f64,
+        frames: usize,
+        n: usize,
+    }
+    fn constant(value: f64, frames: usize) -> Constant {
+        Constant {
+            value: value,
+            frames: frames,
+            n: 0,
+        }
+    }
+    impl<Ctx> Element<(), Ctx> for Constant {
+        type Src = Mono<f64>;
+        fn start(&mut self, _ctx: &Ctx) -> Result<(), Error> {
+            self.n = 0;
+            Ok(())
+        }
+        fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<Mono<f64>> {
+            if self.n == self.frames {
+                return None;
+            }
+            self.n += 1;
+            Some(Mono(self.value))
+        }
+    }
+
+    fn mix(mixer: Mixer<(), DefaultContext>) -> Vec<Stereo<f64>> {
+        Offline::new(mixer)
+            .render(DefaultContext::new().freq(48000))
+            .unwrap()
+    }
+
+    fn db(db: f64) -> f64 {
+        f64::powf(10.0, db / 20.0)
+    }
+
+    #[test]
+    fn pan_laws() {
+        let (l, r) = PanLaw::Minus3dB.gains(0.0);
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        assert!((l - half).abs() < 1e-12 && (r - half).abs() < 1e-12);
+        assert_eq!(PanLaw::Minus6dB.gains(0.0), (0.5, 0.5));
+        assert_eq!(PanLaw::Linear.gains(0.0), (1.0, 1.0));
+        assert_eq!(PanLaw::Linear.gains(0.5), (0.5, 1.0));
+        assert_eq!(PanLaw::Minus6dB.gains(-1.0), (1.0, 0.0));
+        let (l, r) = PanLaw::Minus3dB.gains(2.0);
+        assert!(l.abs() < 1e-12 && (r - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mixer_applies_gain_and_pan() {
+        let out = mix(Mixer::new()
+            .pan_law(PanLaw::Minus6dB)
+            .input(MixerInput::new(constant(1.0, 100)).gain(-6.0))
+            .input(MixerInput::new(constant(0.5, 100)).pan(1.0)));
+        assert_eq!(out.len(), 100);
+        for s in out.iter() {
+            assert!((s.l - 0.5 * db(-6.0)).abs() < 1e-12);
+            assert!((s.r - (0.5 * db(-6.0) + 0.5)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn mixer_mutes_and_solos() {
+        let out = mix(Mixer::new()
+            .pan_law(PanLaw::Linear)
+            .input(MixerInput::new(constant(1.0, 10)))
+            .input(MixerInput::new(constant(2.0, 10)).mute(true))
+            .input(MixerInput::new(constant(4.0, 10))));
+        assert!(out.iter().all(|s| (s.l, s.r) == (5.0, 5.0)));
+
+        let out = mix(Mixer::new()
+            .pan_law(PanLaw::Linear)
+            .input(MixerInput::new(constant(1.0, 10)))
+            .input(MixerInput::new(constant(2.0, 10)).solo(true))
+            .input(MixerInput::new(constant(4.0, 10)).solo(true).mute(true)));
+        assert!(out.iter().all(|s| (s.l, s.r) == (2.0, 2.0)));
+    }
+
+    #[test]
+    fn mixer_attenuates_the_master() {
+        let out = mix(Mixer::new()
+            .pan_law(PanLaw::Linear)
+            .master(-6.0)
+            .headroom(6.0)
+            .input(MixerInput::new(constant(1.0, 10)))
+            .input(MixerInput::new(constant(1.0, 10))));
+        for s in out.iter() {
+            assert!((s.l - 2.0 * db(-12.0)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn mixer_ends_with_its_last_input() {
+        let out = mix(Mixer::new()
+            .pan_law(PanLaw::Linear)
+            .input(MixerInput::new(constant(1.0, 100)))
+            .input(MixerInput::new(constant(2.0, 300))));
+        assert_eq!(out.len(), 300);
+        assert_eq!((out[99].l, out[99].r), (3.0, 3.0));
+        assert_eq!((out[100].l, out[100].r), (2.0, 2.0));
+        assert_eq!((out[299].l, out[299].r), (2.0, 2.0));
+    }
+
+    #[test]
+    fn mixer_start_resets_the_smoothers_of_ended_inputs() {
+        let input = MixerInput::new(constant(1.0, 10));
+        let gain = input.params()[0].clone();
+        let mut mixer = Mixer::new().pan_law(PanLaw::Linear).input(input);
+        let ctx = DefaultContext::new().freq(48000).build().unwrap();
+        let sink = [(); 64];
+        let mut output = [Stereo::default(); 64];
+        mixer.start(&ctx).unwrap();
+        gain.set(-20.0);
+        // the input ends halfway through the glide
+        assert_eq!(mixer.process(&sink, &mut output, &ctx), 10);
+        assert!(output[9].l > 0.5);
+        mixer.start(&ctx).unwrap();
+        assert_eq!(mixer.process(&sink, &mut output, &ctx), 10);
+        assert!((output[0].l - 0.1).abs() < 1e-12);
+    }
+
+    type Log = Arc<Mutex<Vec<(&'static str, f64)>>>;
+
+    // logs every frame under `name` and ends after `frames` frames
+    struct Recorder {
+        name: &'static str,
+        log: Log,
+        frames: usize,
+    }
+    impl<Ctx> Element<Mono<f64>, Ctx> for Recorder {
+        type Src = ();
+        fn next(&mut self, sink: Mono<f64>, _ctx: &Ctx) -> Option<()> {
+            if self.frames == 0 {
+                return None;
+            }
+            self.frames -= 1;
+            self.log.lock().unwrap().push((self.name, sink.0));
+            Some(())
+        }
+    }
+
+    #[test]
+    fn split_feeds_every_branch_before_going_downstream() {
+        let log = Log::default();
+        let (a, down) = (log.clone(), log.clone());
+        let mut n = 0.0;
+        let source = FnElement::new(move |_: ()| {
+            n += 1.0;
+            Mono(n)
+        });
+        let split = Split::new()
+            .branch(Pipe::new(
+                Tee::new(move |s: Mono<f64>| a.lock().unwrap().push(("a", s.0))),
+                NullSink::new(),
+            ))
+            .branch(Recorder {
+                name: "b",
+                log: log.clone(),
+                frames: 100,
+            });
+        let chain = Pipe::new(
+            Pipe::new(source, split),
+            Tee::new(move |s: Mono<f64>| down.lock().unwrap().push(("down", s.0))),
+        );
+        let out = Offline::new(chain)
+            .frames(300)
+            .render(DefaultContext::new().freq(48000))
+            .unwrap();
+        assert_eq!(out.len(), 300);
+
+        let log = log.lock().unwrap();
+        let frames = |name| -> Vec<f64> {
+            log.iter()
+                .filter(|(n, _)| *n == name)
+                .map(|(_, s)| *s)
+                .collect()
+        };
+        let all: Vec<f64> = (1..=300).map(|n| n as f64).collect();
+        assert_eq!(frames("a"), all);
+        assert_eq!(frames("down"), all);
+        // the ended branch is no longer fed, the stream goes on
+        assert_eq!(frames("b"), &all[..100]);
+
+        let first: Vec<f64> = (1..=BLOCK_SIZE).map(|n| n as f64).collect();
+        for (i, name) in ["a", "b", "down"].iter().enumerate() {
+            let block = &log[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE];
+            assert!(block.iter().all(|(n, _)| n == name));
+            let block: Vec<f64> = block.iter().map(|(_, s)| *s).collect();
+            assert_eq!(block, first);
+        }
     }
 }
@@ -0,0 +1,137 @@
+use std::error;
+use std::fmt;
+
+use crate::launch;
+use crate::wav;
+
+#[derive(Debug)]
+pub enum Error {
+    NoDevice,
+    /// no device has the requested name.
+    DeviceNotFound(String),
+    /// the device has no stream format the element can use.
+    NoFormat,
+    DefaultFormat(cpal::DefaultFormatError),
+    FormatsEnumeration(cpal::FormatsEnumerationError),
+    StreamCreation(cpal::CreationError),
+    /// no element constrained or preferred a sample rate.
+    NoFreq,
+    /// the supported rates of the elements have no rate in common.
+    FreqConflict(Vec<Vec<u32>>),
+    /// the requested rate is not in the supported rates.
+    FreqNotSupported(u32, Vec<u32>),
+    WAV(wav::Error),
+    /// no graph node has this name.
+    NodeNotFound(String),
+    /// a graph node with this name already exists.
+    DuplicateNode(String),
+    /// the graph has not been started or has already finished.
+    NotRunning,
+    /// an element running on its own thread panicked.
+    Panicked,
+    /// the word length is outside 2 to 32 bits.
+    UnsupportedBits(u32),
+    /// the element handles frames of at most this many channels.
+    TooManyChannels {
+        channels: usize,
+        max: usize,
+    },
+    /// the element has no parameter with this name.
+    UnknownParam {
+        element: &'static str,
+        name: String,
+    },
+    Launch(launch::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::NoDevice => write!(f, "no audio device available"),
+            Error::DeviceNotFound(ref name) => write!(f, "audio device \"{}\" not found", name),
+            Error::NoFormat => write!(f, "no usable stream format"),
+            Error::DefaultFormat(ref err) => write!(f, "default format error: {}", err),
+            Error::FormatsEnumeration(ref err) => write!(f, "formats enumeration error: {}", err),
+            Error::StreamCreation(ref err) => write!(f, "stream creation error: {}", err),
+            Error::NoFreq => write!(f, "no element decided a sample rate"),
+            Error::FreqConflict(ref supported) => write!(
+                f,
+                "no sample rate is supported by every element (supported rates: {:?})",
+                supported
+            ),
+            Error::FreqNotSupported(freq, ref supported) => write!(
+                f,
+                "sample rate {} is not supported (supported rates: {:?})",
+                freq, supported
+            ),
+            Error::WAV(ref err) => write!(f, "WAV error: {}", err),
+            Error::NodeNotFound(ref name) => write!(f, "graph node \"{}\" not found", name),
+            Error::DuplicateNode(ref name) => write!(f, "graph node \"{}\" already exists", name),
+            Error::NotRunning => write!(f, "graph is not running"),
+            Error::Panicked => write!(f, "an element thread panicked"),
+            Error::UnsupportedBits(bits) => write!(f, "unsupported word length of {} bits", bits),
+            Error::TooManyChannels { channels, max } => {
+                write!(f, "{} channels, at most {} are supported", channels, max)
+            }
+            Error::UnknownParam { element, ref name } => {
+                write!(f, "{} has no parameter \"{}\"", element, name)
+            }
+            Error::Launch(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::DefaultFormat(ref err) => Some(err),
+            Error::FormatsEnumeration(ref err) => Some(err),
+            Error::StreamCreation(ref err) => Some(err),
+            Error::WAV(ref err) => Some(err),
+            Error::Launch(ref err) => Some(err),
+            Error::NoDevice
+            | Error::DeviceNotFound(_)
+            | Error::NoFormat
+            | Error::NoFreq
+            | Error::FreqConflict(_)
+            | Error::FreqNotSupported(_, _)
+            | Error::NodeNotFound(_)
+            | Error::DuplicateNode(_)
+            | Error::NotRunning
+            | Error::Panicked
+            | Error::UnsupportedBits(_)
+            | Error::TooManyChannels { .. }
+            | Error::UnknownParam { .. } => None,
+        }
+    }
+}
+
+impl From<cpal::DefaultFormatError> for Error {
+    fn from(err: cpal::DefaultFormatError) -> Error {
+        Error::DefaultFormat(err)
+    }
+}
+
+impl From<cpal::FormatsEnumerationError> for Error {
+    fn from(err: cpal::FormatsEnumerationError) -> Error {
+        Error::FormatsEnumeration(err)
+    }
+}
+
+impl From<cpal::CreationError> for Error {
+    fn from(err: cpal::CreationError) -> Error {
+        Error::StreamCreation(err)
+    }
+}
+
+impl From<wav::Error> for Error {
+    fn from(err: wav::Error) -> Error {
+        Error::WAV(err)
+    }
+}
+
+impl From<launch::Error> for Error {
+    fn from(err: launch::Error) -> Error {
+        Error::Launch(err)
+    }
+}
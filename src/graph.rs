@@ -0,0 +1,549 @@
+use crate::sample::*;
+use crate::*;
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// the frame type elements in a `Graph` share.
+pub type Frame = Stereo<f64>;
+
+/// frames over which a node is faded in, out or bypassed.
+const CROSSFADE_FRAMES: usize = 256;
+
+// object-safe counterpart of `Element`, implemented for every element
+trait DynElement<Sink, Src, Ctx>: Send {
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error>;
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error>;
+    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Option<Src>;
+    fn process(&mut self, input: &[Sink], output: &mut [Src], ctx: &Ctx) -> usize;
+    fn finish(&mut self, ctx: &Ctx);
+}
+impl<E, Sink, Ctx> DynElement<Sink, E::Src, Ctx> for E
+where
+    E: Element<Sink, Ctx> + Send,
+    Sink: Copy,
+{
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        Element::init(self, ctx)
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        Element::start(self, ctx)
+    }
+    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Option<E::Src> {
+        Element::next(self, sink, ctx)
+    }
+    fn process(&mut self, input: &[Sink], output: &mut [E::Src], ctx: &Ctx) -> usize {
+        Element::process(self, input, output, ctx)
+    }
+    fn finish(&mut self, ctx: &Ctx) {
+        Element::finish(self, ctx)
+    }
+}
+
+/// an element whose type is only known at runtime.
+pub struct BoxedElement<Sink, Src, Ctx> {
+    inner: Box<dyn DynElement<Sink, Src, Ctx>>,
+}
+impl<Sink, Src, Ctx> BoxedElement<Sink, Src, Ctx> {
+    pub fn new<E>(element: E) -> Self
+    where
+        E: Element<Sink, Ctx, Src = Src> + Send + 'static,
+        Sink: Copy,
+    {
+        Self {
+            inner: Box::new(element),
+        }
+    }
+}
+impl<Sink, Src, Ctx> Element<Sink, Ctx> for BoxedElement<Sink, Src, Ctx> {
+    type Src = Src;
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        self.inner.init(ctx)
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.inner.start(ctx)
+    }
+    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Option<Src> {
+        self.inner.next(sink, ctx)
+    }
+    fn process(&mut self, input: &[Sink], output: &mut [Src], ctx: &Ctx) -> usize
+    where
+        Sink: Copy,
+    {
+        self.inner.process(input, output, ctx)
+    }
+    fn finish(&mut self, ctx: &Ctx) {
+        self.inner.finish(ctx)
+    }
+}
+
+/// a boxed element processing `Frame`s, as held by a `Graph`.
+pub type Node<Ctx> = BoxedElement<Frame, Frame, Ctx>;
+
+struct Slot<Ctx> {
+    name: String,
+    element: Node<Ctx>,
+    // amount of processed signal in the output, moving towards `wet`
+    mix: f64,
+    wet: f64,
+    removing: bool,
+}
+
+enum Command<Ctx> {
+    Insert {
+        before: Option<String>,
+        name: String,
+        element: Node<Ctx>,
+    },
+    Remove(String),
+    Bypass(String, bool),
+}
+
+struct State<Ctx> {
+    // set while the graph is running
+    ctx: Option<Ctx>,
+    names: Vec<String>,
+    removed: Receiver<Node<Ctx>>,
+}
+
+/// a chain of named `Frame` elements that can be changed while the pipeline runs
+/// through a `GraphHandle`. changes are applied between blocks and crossfaded.
+pub struct Graph<Ctx> {
+    slots: Vec<Slot<Ctx>>,
+    commands: Receiver<Command<Ctx>>,
+    removed: Sender<Node<Ctx>>,
+    handle: GraphHandle<Ctx>,
+}
+impl<Ctx> Graph<Ctx> {
+    pub fn new() -> Self {
+        let (commands_tx, commands_rx) = channel();
+        let (removed_tx, removed_rx) = channel();
+        Self {
+            slots: Vec::new(),
+            commands: commands_rx,
+            removed: removed_tx,
+            handle: GraphHandle {
+                commands: commands_tx,
+                state: Arc::new(Mutex::new(State {
+                    ctx: None,
+                    names: Vec::new(),
+                    removed: removed_rx,
+                })),
+            },
+        }
+    }
+    /// appends a node. fails with `Error::DuplicateNode` if the name is taken.
+    pub fn node<E>(mut self, name: &str, element: E) -> Result<Self, Error>
+    where
+        E: Element<Frame, Ctx, Src = Frame> + Send + 'static,
+    {
+        {
+            let mut state = self.handle.state.lock().unwrap();
+            if state.names.iter().any(|n| n == name) {
+                return Err(Error::DuplicateNode(name.to_string()));
+            }
+            state.names.push(name.to_string());
+        }
+        self.slots.push(Slot {
+            name: name.to_string(),
+            element: BoxedElement::new(element),
+            mix: 1.0,
+            wet: 1.0,
+            removing: false,
+        });
+        Ok(self)
+    }
+    pub fn handle(&self) -> GraphHandle<Ctx> {
+        self.handle.clone()
+    }
+    fn apply(&mut self, command: Command<Ctx>) {
+        match command {
+            Command::Insert {
+                before,
+                name,
+                element,
+            } => {
+                let index = before
+                    .and_then(|before| {
+                        self.slots
+                            .iter()
+                            .position(|s| !s.removing && s.name == before)
+                    })
+                    .unwrap_or(self.slots.len());
+                self.slots.insert(
+                    index,
+                    Slot {
+                        name: name,
+                        element: element,
+                        mix: 0.0,
+                        wet: 1.0,
+                        removing: false,
+                    },
+                );
+            }
+            Command::Remove(name) => {
+                if let Some(slot) = self.slot(&name) {
+                    slot.wet = 0.0;
+                    slot.removing = true;
+                }
+            }
+            Command::Bypass(name, bypass) => {
+                if let Some(slot) = self.slot(&name) {
+                    slot.wet = if bypass { 0.0 } else { 1.0 };
+                }
+            }
+        }
+    }
+    // nodes being removed keep their name until faded out, so look at live ones only
+    fn slot(&mut self, name: &str) -> Option<&mut Slot<Ctx>> {
+        self.slots
+            .iter_mut()
+            .find(|slot| !slot.removing && slot.name == name)
+    }
+}
+impl<Ctx> Default for Graph<Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<Ctx> Element<Frame, Ctx> for Graph<Ctx>
+where
+    Ctx: Clone,
+{
+    type Src = Frame;
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        for slot in self.slots.iter_mut() {
+            Element::init(&mut slot.element, ctx)?;
+        }
+        Ok(())
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        for slot in self.slots.iter_mut() {
+            Element::start(&mut slot.element, ctx)?;
+        }
+        self.handle.state.lock().unwrap().ctx = Some(ctx.clone());
+        Ok(())
+    }
+    fn next(&mut self, sink: Frame, ctx: &Ctx) -> Option<Frame> {
+        let mut output = [Frame::default()];
+        match self.process(&[sink], &mut output, ctx) {
+            1 => Some(output[0]),
+            _ => None,
+        }
+    }
+    fn process(&mut self, input: &[Frame], output: &mut [Frame], ctx: &Ctx) -> usize {
+        while let Ok(command) = self.commands.try_recv() {
+            self.apply(command);
+        }
+        let step = 1.0 / CROSSFADE_FRAMES as f64;
+        let mut wet = [Frame::default(); BLOCK_SIZE];
+        let mut done = 0;
+        for (input, output) in input.chunks(BLOCK_SIZE).zip(output.chunks_mut(BLOCK_SIZE)) {
+            let mut len = input.len().min(output.len());
+            output[..len].copy_from_slice(&input[..len]);
+            for slot in self.slots.iter_mut() {
+                if slot.mix == 0.0 && slot.wet == 0.0 {
+                    continue;
+                }
+                len = Element::process(&mut slot.element, &output[..len], &mut wet[..len], ctx);
+                for (dry, wet) in output[..len].iter_mut().zip(wet[..len].iter()) {
+                    if slot.mix < slot.wet {
+                        slot.mix = (slot.mix + step).min(slot.wet);
+                    } else if slot.mix > slot.wet {
+                        slot.mix = (slot.mix - step).max(slot.wet);
+                    }
+                    *dry += (*wet - *dry) * slot.mix;
+                }
+            }
+            done += len;
+            if len < input.len().min(output.len()) {
+                break;
+            }
+        }
+        // hand faded out nodes back so they are dropped off the audio thread
+        let mut i = 0;
+        while i < self.slots.len() {
+            if self.slots[i].removing && self.slots[i].mix == 0.0 {
+                let _ = self.removed.send(self.slots.remove(i).element);
+            } else {
+                i += 1;
+            }
+        }
+        done
+    }
+    fn finish(&mut self, ctx: &Ctx) {
+        for slot in self.slots.iter_mut() {
+            Element::finish(&mut slot.element, ctx);
+        }
+        let mut state = self.handle.state.lock().unwrap();
+        // nodes faded out since the last handle call are not finished yet
+        while let Ok(mut element) = state.removed.try_recv() {
+            Element::finish(&mut element, ctx);
+        }
+        state.ctx = None;
+    }
+}
+
+/// changes a running `Graph` from any thread.
+pub struct GraphHandle<Ctx> {
+    commands: Sender<Command<Ctx>>,
+    state: Arc<Mutex<State<Ctx>>>,
+}
+impl<Ctx> Clone for GraphHandle<Ctx> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+impl<Ctx: Context + Clone> GraphHandle<Ctx> {
+    /// names of the nodes in processing order.
+    pub fn nodes(&self) -> Vec<String> {
+        self.state.lock().unwrap().names.clone()
+    }
+    /// appends a node to the end of the graph.
+    pub fn push<E>(&self, name: &str, element: E) -> Result<(), Error>
+    where
+        E: Element<Frame, Ctx, Src = Frame> + Send + 'static,
+    {
+        self.insert(None, name, element)
+    }
+    /// inserts a node in front of the node named `before`, or at the end. the
+    /// element is initialized and started on the calling thread, then faded in.
+    pub fn insert<E>(&self, before: Option<&str>, name: &str, element: E) -> Result<(), Error>
+    where
+        E: Element<Frame, Ctx, Src = Frame> + Send + 'static,
+    {
+        let mut state = self.lock();
+        if state.names.iter().any(|n| n == name) {
+            return Err(Error::DuplicateNode(name.to_string()));
+        }
+        let index = match before {
+            Some(before) => match state.names.iter().position(|n| n == before) {
+                Some(index) => index,
+                None => return Err(Error::NodeNotFound(before.to_string())),
+            },
+            None => state.names.len(),
+        };
+        let mut ctx = state.ctx.clone().ok_or(Error::NotRunning)?;
+        let mut element = element;
+        // checks the element against the rate the graph is already running at
+        Element::init(&mut element, &mut ctx)?;
+        let ctx = ctx.build()?;
+        Element::start(&mut element, &ctx)?;
+        state.names.insert(index, name.to_string());
+        self.send(Command::Insert {
+            before: before.map(|before| before.to_string()),
+            name: name.to_string(),
+            element: BoxedElement::new(element),
+        });
+        Ok(())
+    }
+    /// fades the node out and removes it.
+    pub fn remove(&self, name: &str) -> Result<(), Error> {
+        let mut state = self.lock();
+        match state.names.iter().position(|n| n == name) {
+            Some(index) => state.names.remove(index),
+            None => return Err(Error::NodeNotFound(name.to_string())),
+        };
+        self.send(Command::Remove(name.to_string()));
+        Ok(())
+    }
+    /// crossfades between the output of the node and its input.
+    pub fn bypass(&self, name: &str, bypass: bool) -> Result<(), Error> {
+        let state = self.lock();
+        if !state.names.iter().any(|n| n == name) {
+            return Err(Error::NodeNotFound(name.to_string()));
+        }
+        self.send(Command::Bypass(name.to_string(), bypass));
+        Ok(())
+    }
+    // finishes and drops the nodes the graph has let go of
+    fn lock(&self) -> std::sync::MutexGuard<'_, State<Ctx>> {
+        let state = self.state.lock().unwrap();
+        while let Ok(mut element) = state.removed.try_recv() {
+            if let Some(ref ctx) = state.ctx {
+                Element::finish(&mut element, ctx);
+            }
+        }
+        state
+    }
+    fn send(&self, command: Command<Ctx>) {
+        // the graph is gone once the pipeline has been dropped; nothing to change
+        let _ = self.commands.send(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::DefaultContext;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // scales its input and records whether it has been finished
+    struct Scale {
+        gain: f64,
+        finished: Arc<AtomicBool>,
+    }
+    impl Scale {
+        fn new(gain: f64) -> (Self, Arc<AtomicBool>) {
+            let finished = Arc::new(AtomicBool::new(false));
+            let scale = Self {
+                gain: gain,
+                finished: finished.clone(),
+            };
+            (scale, finished)
+        }
+    }
+    impl<Ctx> Element<Frame, Ctx> for Scale {
+        type Src = Frame;
+        fn next(&mut self, sink: Frame, _ctx: &Ctx) -> Option<Frame> {
+            let gain = self.gain;
+            Some(sink.map(|x| x * gain))
+        }
+        fn finish(&mut self, _ctx: &Ctx) {
+            self.finished.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn started(graph: &mut Graph<DefaultContext>) -> DefaultContext {
+        let mut ctx = DefaultContext::new().freq(48000);
+        Element::init(graph, &mut ctx).unwrap();
+        let ctx = ctx.build().unwrap();
+        Element::start(graph, &ctx).unwrap();
+        ctx
+    }
+
+    // runs `frames` frames of 1.0 through the graph and returns the left channel
+    fn run(graph: &mut Graph<DefaultContext>, ctx: &DefaultContext, frames: usize) -> Vec<f64> {
+        let input = [Stereo::new(1.0); BLOCK_SIZE];
+        let mut output = [Frame::default(); BLOCK_SIZE];
+        let mut out = Vec::new();
+        while out.len() < frames {
+            assert_eq!(
+                Element::process(graph, &input, &mut output, ctx),
+                BLOCK_SIZE
+            );
+            out.extend(output.iter().map(|frame| frame.l));
+        }
+        out
+    }
+
+    // checks that `out` fades linearly from `from` to `to` over `CROSSFADE_FRAMES`
+    fn assert_crossfade(out: &[f64], from: f64, to: f64) {
+        for (i, &x) in out.iter().enumerate() {
+            let mix = ((i + 1) as f64 / CROSSFADE_FRAMES as f64).min(1.0);
+            let expected = from + (to - from) * mix;
+            assert!(
+                (x - expected).abs() < 1e-9,
+                "frame {}: {} != {}",
+                i,
+                x,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn handle_changes_a_running_graph_with_crossfades() {
+        let (a, _) = Scale::new(2.0);
+        let mut graph = Graph::new().node("a", a).unwrap();
+        let handle = graph.handle();
+        let ctx = started(&mut graph);
+        assert!(run(&mut graph, &ctx, BLOCK_SIZE).iter().all(|&x| x == 2.0));
+
+        let (b, _) = Scale::new(3.0);
+        handle.push("b", b).unwrap();
+        let (c, _) = Scale::new(5.0);
+        handle.insert(Some("b"), "c", c).unwrap();
+        assert_eq!(handle.nodes(), ["a", "c", "b"]);
+        // c and b fade in together, so the ramp is the product of both
+        let out = run(&mut graph, &ctx, CROSSFADE_FRAMES + BLOCK_SIZE);
+        for (i, &x) in out.iter().enumerate() {
+            let mix = ((i + 1) as f64 / CROSSFADE_FRAMES as f64).min(1.0);
+            let after_c = 2.0 + (10.0 - 2.0) * mix;
+            let expected = after_c + (after_c * 3.0 - after_c) * mix;
+            assert!(
+                (x - expected).abs() < 1e-9,
+                "frame {}: {} != {}",
+                i,
+                x,
+                expected
+            );
+        }
+
+        handle.bypass("c", true).unwrap();
+        assert_crossfade(
+            &run(&mut graph, &ctx, CROSSFADE_FRAMES + BLOCK_SIZE),
+            30.0,
+            6.0,
+        );
+        handle.bypass("c", false).unwrap();
+        assert_crossfade(
+            &run(&mut graph, &ctx, CROSSFADE_FRAMES + BLOCK_SIZE),
+            6.0,
+            30.0,
+        );
+
+        handle.remove("a").unwrap();
+        assert_eq!(handle.nodes(), ["c", "b"]);
+        assert_crossfade(
+            &run(&mut graph, &ctx, CROSSFADE_FRAMES + BLOCK_SIZE),
+            30.0,
+            15.0,
+        );
+        assert_eq!(graph.slots.len(), 2);
+    }
+
+    #[test]
+    fn removed_nodes_are_finished() {
+        let (a, a_finished) = Scale::new(2.0);
+        let (b, b_finished) = Scale::new(3.0);
+        let mut graph = Graph::new().node("a", a).unwrap().node("b", b).unwrap();
+        let handle = graph.handle();
+        let ctx = started(&mut graph);
+
+        handle.remove("a").unwrap();
+        run(&mut graph, &ctx, CROSSFADE_FRAMES);
+        // faded out and handed back, but only finished by the next handle call
+        assert!(!a_finished.load(Ordering::SeqCst));
+        handle.bypass("b", false).unwrap();
+        assert!(a_finished.load(Ordering::SeqCst));
+
+        handle.remove("b").unwrap();
+        run(&mut graph, &ctx, CROSSFADE_FRAMES);
+        assert!(!b_finished.load(Ordering::SeqCst));
+        Element::finish(&mut graph, &ctx);
+        assert!(b_finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn handle_checks_state_and_names() {
+        let (a, _) = Scale::new(2.0);
+        let mut graph = Graph::new().node("a", a).unwrap();
+        let handle = graph.handle();
+
+        let (b, _) = Scale::new(3.0);
+        assert!(matches!(handle.push("b", b), Err(Error::NotRunning)));
+        assert_eq!(handle.nodes(), ["a"]);
+
+        let ctx = started(&mut graph);
+        let (b, _) = Scale::new(3.0);
+        match handle.insert(Some("x"), "b", b) {
+            Err(Error::NodeNotFound(ref name)) if name == "x" => {}
+            other => panic!("{:?}", other),
+        }
+        let (a, _) = Scale::new(3.0);
+        assert!(matches!(handle.push("a", a), Err(Error::DuplicateNode(_))));
+        assert!(matches!(handle.remove("x"), Err(Error::NodeNotFound(_))));
+        assert!(matches!(
+            handle.bypass("x", true),
+            Err(Error::NodeNotFound(_))
+        ));
+
+        Element::finish(&mut graph, &ctx);
+        let (b, _) = Scale::new(3.0);
+        assert!(matches!(handle.push("b", b), Err(Error::NotRunning)));
+    }
+}
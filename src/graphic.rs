@@ -59,7 +59,7 @@ where
     T: IntoSample<Stereo<f64>> + Copy,
 {
     type Src = T;
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
+    fn next(&mut self, sink: T, _ctx: &Ctx) -> Option<T> {
         self.local_data[self.ptr] = sink.into_sample().l;
         self.ptr += 1;
         if self.ptr >= self.local_data.len() {
@@ -69,7 +69,7 @@ where
                 .unwrap()
                 .copy_from_slice(&self.local_data);
         }
-        sink
+        Some(sink)
     }
 }
 
@@ -133,7 +133,7 @@ where
     T: IntoSample<Stereo<f64>> + Copy,
 {
     type Src = T;
-    fn next(&mut self, sink: T, _ctx: &Ctx) -> T {
+    fn next(&mut self, sink: T, _ctx: &Ctx) -> Option<T> {
         self.local_data[self.ptr] = sink.into_sample().l;
         self.ptr += 1;
         if self.ptr >= self.local_data.len() {
@@ -143,6 +143,6 @@ where
                 .unwrap()
                 .copy_from_slice(&self.local_data);
         }
-        sink
+        Some(sink)
     }
 }
@@ -0,0 +1,1063 @@
+use crate::context::DefaultContext;
+use crate::dsp::{NoiseShaping, Quality};
+use crate::element::*;
+use crate::graph::*;
+use crate::sample::*;
+use crate::wav::{self, WAVFormat, WAVReader};
+use crate::*;
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::mem;
+use std::str::FromStr;
+
+// gst-launch style descriptions: elements separated by `!`, each an element name
+// followed by `property=value` pairs. values may be double quoted.
+//
+//     wavsrc location=a.wav ! lowpass freq=1000 q=0.7 ! gain db=-6 ! wavsink location=b.wav
+//
+// the first element produces the stream, the last consumes it, and the elements
+// in between become nodes of a `Graph`, named `name=` or `<element><n>`. a
+// `queue` runs everything before it on its own thread, and a source given
+// `resample=<quality>` runs at its own rate, converted to the pipeline's.
+
+/// an element built from a description, sorted by where it can go in a pipeline.
+pub enum Instance {
+    Source(BoxedElement<(), Frame, DefaultContext>),
+    Filter(Node<DefaultContext>),
+    Sink(BoxedElement<Frame, (), DefaultContext>),
+    DeviceSource(DefaultSource<Frame>),
+    DeviceSink(DefaultSink),
+    /// a `Queue` of this capacity around everything before it.
+    Queue(usize),
+}
+impl Instance {
+    pub fn source<E>(element: E) -> Self
+    where
+        E: Element<(), DefaultContext, Src = Frame> + Send + 'static,
+    {
+        Instance::Source(BoxedElement::new(element))
+    }
+    pub fn filter<E>(element: E) -> Self
+    where
+        E: Element<Frame, DefaultContext, Src = Frame> + Send + 'static,
+    {
+        Instance::Filter(BoxedElement::new(element))
+    }
+    pub fn sink<E>(element: E) -> Self
+    where
+        E: Element<Frame, DefaultContext, Src = ()> + Send + 'static,
+    {
+        Instance::Sink(BoxedElement::new(element))
+    }
+}
+
+/// the properties given to one element. every property must be read by the
+/// factory, anything left over is reported as unknown.
+pub struct Props {
+    element: String,
+    props: Vec<(String, String, bool)>,
+}
+impl Props {
+    pub fn get<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, Error> {
+        let element = &self.element;
+        match self.props.iter_mut().find(|(n, _, _)| n == name) {
+            Some((_, value, used)) => {
+                *used = true;
+                match value.parse() {
+                    Ok(value) => Ok(Some(value)),
+                    Err(_) => Err(Error::InvalidValue {
+                        element: element.clone(),
+                        property: name.to_string(),
+                        value: value.clone(),
+                    }),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+    pub fn get_or<T: FromStr>(&mut self, name: &str, default: T) -> Result<T, Error> {
+        Ok(self.get(name)?.unwrap_or(default))
+    }
+    pub fn require<T: FromStr>(&mut self, name: &str) -> Result<T, Error> {
+        match self.get(name)? {
+            Some(value) => Ok(value),
+            None => Err(Error::MissingProperty {
+                element: self.element.clone(),
+                property: name.to_string(),
+            }),
+        }
+    }
+    fn check_unused(&self) -> Result<(), Error> {
+        match self.props.iter().find(|(_, _, used)| !used) {
+            Some((name, _, _)) => Err(Error::UnknownProperty {
+                element: self.element.clone(),
+                property: name.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// builds one kind of element from its properties.
+#[derive(Clone)]
+pub struct Factory {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// property names with a short description, for listings.
+    pub properties: &'static [(&'static str, &'static str)],
+    pub build: fn(&mut Props) -> Result<Instance, crate::Error>,
+}
+
+/// maps element names to factories.
+pub struct Registry {
+    factories: Vec<Factory>,
+}
+impl Registry {
+    /// a registry holding the elements of the `element` module. `Mixer` and
+    /// `Split` are left out until descriptions can name branches (gst-launch's
+    /// `name.` references) for the chains they join or fork.
+    pub fn new() -> Self {
+        let mut registry = Registry {
+            factories: Vec::new(),
+        };
+        for factory in builtin_factories() {
+            registry.register(factory);
+        }
+        registry
+    }
+    /// adds a factory, replacing any factory of the same name.
+    pub fn register(&mut self, factory: Factory) {
+        self.factories.retain(|f| f.name != factory.name);
+        self.factories.push(factory);
+    }
+    pub fn factories(&self) -> &[Factory] {
+        &self.factories
+    }
+    pub fn factory(&self, name: &str) -> Option<&Factory> {
+        self.factories.iter().find(|f| f.name == name)
+    }
+    /// builds the pipeline a description stands for.
+    pub fn parse(&self, description: &str) -> Result<Launch, crate::Error> {
+        self.build(parse(tokenize(description)?)?)
+    }
+    /// builds a pipeline from a description already split into words, such as
+    /// command line arguments. quotes are not special and `!` must be a word of
+    /// its own.
+    pub fn parse_args<I, S>(&self, args: I) -> Result<Launch, crate::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let tokens = args.into_iter().map(|arg| match arg.into() {
+            ref link if link == "!" => Token::Link,
+            word => Token::Word(word),
+        });
+        self.build(parse(tokens.collect())?)
+    }
+    fn build(&self, descs: Vec<ElementDesc>) -> Result<Launch, crate::Error> {
+        if descs.len() < 2 {
+            return Err(Error::TooShort.into());
+        }
+        let last = descs.len() - 1;
+        let mut source = None;
+        let mut sink = None;
+        let mut graph = Graph::new();
+        let mut upstream: Vec<GraphHandle<DefaultContext>> = Vec::new();
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for (i, ElementDesc { element, props }) in descs.into_iter().enumerate() {
+            let factory = match self.factory(&element) {
+                Some(factory) => factory,
+                None => return Err(Error::UnknownElement(element).into()),
+            };
+            let mut props = Props {
+                element: element.clone(),
+                props: props
+                    .into_iter()
+                    .map(|(name, value)| (name, value, false))
+                    .collect(),
+            };
+            let instance = (factory.build)(&mut props)?;
+            // only graph nodes have a name, and only pulled sources a rate of their own
+            let name: Option<String> = match instance {
+                Instance::Filter(_) => props.get("name")?,
+                _ => None,
+            };
+            let instance = match instance {
+                Instance::Source(e) => match props.get::<String>("resample")? {
+                    Some(quality) => {
+                        Instance::source(Resample::new(e, parse_quality(&element, &quality)?))
+                    }
+                    None => Instance::Source(e),
+                },
+                instance => instance,
+            };
+            props.check_unused()?;
+            match instance {
+                Instance::Source(e) if i == 0 => source = Some(Source::Element(e)),
+                Instance::DeviceSource(e) if i == 0 => source = Some(Source::Device(e)),
+                Instance::Sink(e) if i == last => sink = Some(Sink::Element(e)),
+                Instance::DeviceSink(e) if i == last => sink = Some(Sink::Device(e)),
+                Instance::Filter(e) if i != 0 && i != last => {
+                    let name = match name {
+                        Some(name) => name,
+                        None => {
+                            let n = match counts.iter_mut().find(|(n, _)| *n == element) {
+                                Some((_, count)) => {
+                                    *count += 1;
+                                    *count
+                                }
+                                None => {
+                                    counts.push((element.clone(), 0));
+                                    0
+                                }
+                            };
+                            format!("{}{}", element, n)
+                        }
+                    };
+                    if upstream.iter().any(|handle| handle.nodes().contains(&name)) {
+                        return Err(crate::Error::DuplicateNode(name));
+                    }
+                    graph = graph.node(&name, e)?;
+                }
+                Instance::Queue(capacity) if i != 0 && i != last => match source.take() {
+                    Some(Source::Element(e)) => {
+                        upstream.push(graph.handle());
+                        let chain = Pipe::new(e, mem::take(&mut graph));
+                        let queue = Queue::new(chain).capacity(capacity);
+                        source = Some(Source::Element(BoxedElement::new(queue)));
+                    }
+                    _ => {
+                        return Err(Error::Misplaced {
+                            element: element,
+                            position: "after a capture device",
+                        }
+                        .into())
+                    }
+                },
+                _ => {
+                    let position = match i {
+                        0 => "first",
+                        i if i == last => "last",
+                        _ => "in the middle",
+                    };
+                    return Err(Error::Misplaced {
+                        element: element,
+                        position: position,
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(Launch {
+            source: source.unwrap(),
+            upstream: upstream,
+            graph: graph,
+            sink: sink.unwrap(),
+        })
+    }
+}
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// builds a pipeline from a description using the built-in elements.
+pub fn parse_launch(description: &str) -> Result<Launch, crate::Error> {
+    Registry::new().parse(description)
+}
+
+enum Source {
+    Element(BoxedElement<(), Frame, DefaultContext>),
+    Device(DefaultSource<Frame>),
+}
+enum Sink {
+    Element(BoxedElement<Frame, (), DefaultContext>),
+    Device(DefaultSink),
+}
+
+/// a pipeline built from a description. the elements between the first and the
+/// last can be changed while running through `graph`.
+pub struct Launch {
+    source: Source,
+    // the graphs that went into queues, in order
+    upstream: Vec<GraphHandle<DefaultContext>>,
+    graph: Graph<DefaultContext>,
+    sink: Sink,
+}
+impl Launch {
+    /// the elements after the last `queue`.
+    pub fn graph(&self) -> GraphHandle<DefaultContext> {
+        self.graph.handle()
+    }
+    /// the elements between each pair of queues, from the source to the sink.
+    pub fn graphs(&self) -> Vec<GraphHandle<DefaultContext>> {
+        let mut graphs = self.upstream.clone();
+        graphs.push(self.graph.handle());
+        graphs
+    }
+}
+impl Pipeline<DefaultContext> for Launch {
+    fn start(self, ctx: DefaultContext) -> Result<PipelineHandle, crate::Error> {
+        let graph = self.graph;
+        match (self.source, self.sink) {
+            (Source::Element(src), Sink::Element(sink)) => {
+                Pipeline::start(Pipe::new(src, Pipe::new(graph, sink)), ctx)
+            }
+            (Source::Element(src), Sink::Device(sink)) => {
+                SinkPipeline::start(Pipe::new(Pipe::new(src, graph), sink), ctx)
+            }
+            (Source::Device(src), Sink::Element(sink)) => {
+                SrcPipeline::start(Pipe::new(src, Pipe::new(graph, sink)), ctx)
+            }
+            (Source::Device(src), Sink::Device(sink)) => {
+                Pipeline::start(Duplex::new(src, graph, sink), ctx)
+            }
+        }
+    }
+}
+
+// parser
+
+struct ElementDesc {
+    element: String,
+    props: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Link,
+    Word(String),
+}
+
+fn tokenize(description: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = description.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '!' {
+            chars.next();
+            tokens.push(Token::Link);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '!' {
+                    break;
+                }
+                chars.next();
+                if c == '"' {
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some(c) => word.push(c),
+                                None => return Err(Error::Syntax("unterminated quote".into())),
+                            },
+                            Some(c) => word.push(c),
+                            None => return Err(Error::Syntax("unterminated quote".into())),
+                        }
+                    }
+                } else {
+                    word.push(c);
+                }
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse(tokens: Vec<Token>) -> Result<Vec<ElementDesc>, Error> {
+    let mut descs = Vec::new();
+    for part in tokens.split(|token| *token == Token::Link) {
+        let mut words = part.iter().map(|token| match token {
+            Token::Word(word) => word,
+            Token::Link => unreachable!(),
+        });
+        let element = match words.next() {
+            Some(element) if !element.contains('=') => element.clone(),
+            _ => return Err(Error::Syntax("expected an element name".into())),
+        };
+        let mut props = Vec::new();
+        for word in words {
+            let mut split = word.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some(name), Some(value)) if !name.is_empty() => {
+                    props.push((name.to_string(), value.to_string()))
+                }
+                _ => {
+                    return Err(Error::Syntax(format!(
+                        "expected property=value after {}, found \"{}\"",
+                        element, word
+                    )))
+                }
+            }
+        }
+        descs.push(ElementDesc {
+            element: element,
+            props: props,
+        });
+    }
+    Ok(descs)
+}
+
+// built-in elements
+
+fn builtin_factories() -> Vec<Factory> {
+    vec![
+        Factory {
+            name: "wavsrc",
+            description: "reads a WAV file",
+            properties: &[
+                ("location", "path of the file"),
+                ("loop", "restart at the end, default false"),
+            ],
+            build: |props| {
+                let location: String = props.require("location")?;
+                let looping = props.get_or("loop", false)?;
+                let file = File::open(&location).map_err(wav::Error::Io)?;
+                let reader = WAVReader::new(file)?;
+                match reader.header.channels {
+                    1 => wav_source::<Mono<f64>>(reader, looping),
+                    2 => wav_source::<Stereo<f64>>(reader, looping),
+                    4 => wav_source::<Quad<f64>>(reader, looping),
+                    6 => wav_source::<Surround51<f64>>(reader, looping),
+                    8 => wav_source::<Surround71<f64>>(reader, looping),
+                    channels => Err(wav::Error::ChannelMismatch {
+                        channels: channels,
+                        frame_channels: Frame::CHANNELS,
+                    }
+                    .into()),
+                }
+            },
+        },
+        Factory {
+            name: "sinewave",
+            description: "sine wave oscillator",
+            properties: &[("freq", "frequency in Hz, default 440")],
+            build: |props| {
+                Ok(Instance::source(SineWave::<Frame>::new(
+                    props.get_or("freq", 440.0)?,
+                )))
+            },
+        },
+        Factory {
+            name: "whitenoise",
+            description: "white noise",
+            properties: &[],
+            build: |_| Ok(Instance::source(WhiteNoise::<Frame>::new())),
+        },
+        Factory {
+            name: "defaultsrc",
+            description: "captures from an audio input device",
+            properties: &[("device", "device name, default the default device")],
+            build: |props| {
+                let source = DefaultSource::new();
+                let source = match props.get::<String>("device")? {
+                    Some(device) => source.device(&device),
+                    None => source,
+                };
+                Ok(Instance::DeviceSource(source))
+            },
+        },
+        Factory {
+            name: "defaultsink",
+            description: "plays on an audio output device",
+            properties: &[("device", "device name, default the default device")],
+            build: |props| {
+                let sink = DefaultSink::new();
+                let sink = match props.get::<String>("device")? {
+                    Some(device) => sink.device(&device),
+                    None => sink,
+                };
+                Ok(Instance::DeviceSink(sink))
+            },
+        },
+        Factory {
+            name: "wavsink",
+            description: "writes a WAV file",
+            properties: &[
+                ("location", "path of the file"),
+                (
+                    "format",
+                    "pcm8, pcm16, pcm24, pcm32, float32 or float64, default pcm16",
+                ),
+            ],
+            build: |props| {
+                let location: String = props.require("location")?;
+                let format = match props.get::<String>("format")?.as_ref().map(|f| &f[..]) {
+                    None | Some("pcm16") => WAVFormat::PCM16,
+                    Some("pcm8") => WAVFormat::PCM8,
+                    Some("pcm24") => WAVFormat::PCM24,
+                    Some("pcm32") => WAVFormat::PCM32,
+                    Some("float32") => WAVFormat::Float32,
+                    Some("float64") => WAVFormat::Float64,
+                    Some(format) => {
+                        return Err(Error::InvalidValue {
+                            element: "wavsink".to_string(),
+                            property: "format".to_string(),
+                            value: format.to_string(),
+                        }
+                        .into())
+                    }
+                };
+                Ok(Instance::sink(
+                    WAVSink::<Frame>::new(&location).format(format),
+                ))
+            },
+        },
+        Factory {
+            name: "nullsink",
+            description: "discards the stream",
+            properties: &[],
+            build: |_| Ok(Instance::sink(NullSink::new())),
+        },
+        Factory {
+            name: "printsink",
+            description: "prints every frame",
+            properties: &[],
+            build: |_| Ok(Instance::sink(PrintSink::new())),
+        },
+        Factory {
+            name: "queue",
+            description: "runs everything before it on its own thread",
+            properties: &[("capacity", "frames buffered ahead, default 4096")],
+            build: |props| Ok(Instance::Queue(props.get_or("capacity", 4096)?)),
+        },
+        Factory {
+            name: "identity",
+            description: "passes the stream through",
+            properties: &[],
+            build: |_| Ok(Instance::filter(Ident::new())),
+        },
+        Factory {
+            name: "gain",
+            description: "amplifies by a fixed amount",
+            properties: &[("db", "gain in dB, default 0")],
+            build: |props| Ok(Instance::filter(Gain::new(props.get_or("db", 0.0)?))),
+        },
+        Factory {
+            name: "limiter",
+            description: "clips at a threshold",
+            properties: &[("threshold", "threshold in dB, default 0")],
+            build: |props| {
+                Ok(Instance::filter(Limiter::new(
+                    props.get_or("threshold", 0.0)?,
+                )))
+            },
+        },
+        Factory {
+            name: "dither",
+            description: "reduces the word length with TPDF dither",
+            properties: &[
+                ("bits", "target bits, default 16"),
+                ("shaping", "off, firstorder or lipshitz, default off"),
+            ],
+            build: |props| {
+                let bits: u32 = props.get_or("bits", 16)?;
+                let shaping = match props.get::<String>("shaping")?.as_ref().map(|s| &s[..]) {
+                    None | Some("off") => NoiseShaping::Off,
+                    Some("firstorder") => NoiseShaping::FirstOrder,
+                    Some("lipshitz") => NoiseShaping::Lipshitz,
+                    Some(shaping) => {
+                        return Err(Error::InvalidValue {
+                            element: "dither".to_string(),
+                            property: "shaping".to_string(),
+                            value: shaping.to_string(),
+                        }
+                        .into())
+                    }
+                };
+                let dither = Dither::new(bits).map_err(|_| Error::InvalidValue {
+                    element: "dither".to_string(),
+                    property: "bits".to_string(),
+                    value: bits.to_string(),
+                })?;
+                Ok(Instance::filter(dither.noise_shaping(shaping)))
+            },
+        },
+        Factory {
+            name: "lowpass",
+            description: "biquad low-pass filter",
+            properties: &[("freq", "cutoff in Hz"), ("q", "default 0.707")],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let q = props.get_or("q", DEFAULT_Q)?;
+                Ok(Instance::filter(LowPassFilter::new(freq, q)))
+            },
+        },
+        Factory {
+            name: "highpass",
+            description: "biquad high-pass filter",
+            properties: &[("freq", "cutoff in Hz"), ("q", "default 0.707")],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let q = props.get_or("q", DEFAULT_Q)?;
+                Ok(Instance::filter(HighPassFilter::new(freq, q)))
+            },
+        },
+        Factory {
+            name: "bandpass",
+            description: "biquad band-pass filter",
+            properties: &[
+                ("freq", "centre in Hz"),
+                ("bw", "bandwidth in octaves, default 1"),
+            ],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let bw = props.get_or("bw", 1.0)?;
+                Ok(Instance::filter(BandPassFilter::new(freq, bw)))
+            },
+        },
+        Factory {
+            name: "notch",
+            description: "biquad notch filter",
+            properties: &[
+                ("freq", "centre in Hz"),
+                ("bw", "bandwidth in octaves, default 1"),
+            ],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let bw = props.get_or("bw", 1.0)?;
+                Ok(Instance::filter(NotchFilter::new(freq, bw)))
+            },
+        },
+        Factory {
+            name: "lowshelf",
+            description: "biquad low-shelf filter",
+            properties: &[
+                ("freq", "corner in Hz"),
+                ("q", "default 0.707"),
+                ("gain", "shelf gain in dB, default 0"),
+            ],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let q = props.get_or("q", DEFAULT_Q)?;
+                let gain = props.get_or("gain", 0.0)?;
+                Ok(Instance::filter(LowShelfFilter::new(freq, q, gain)))
+            },
+        },
+        Factory {
+            name: "highshelf",
+            description: "biquad high-shelf filter",
+            properties: &[
+                ("freq", "corner in Hz"),
+                ("q", "default 0.707"),
+                ("gain", "shelf gain in dB, default 0"),
+            ],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let q = props.get_or("q", DEFAULT_Q)?;
+                let gain = props.get_or("gain", 0.0)?;
+                Ok(Instance::filter(HighShelfFilter::new(freq, q, gain)))
+            },
+        },
+        Factory {
+            name: "peaking",
+            description: "biquad peaking filter",
+            properties: &[
+                ("freq", "centre in Hz"),
+                ("bw", "bandwidth in octaves, default 1"),
+                ("gain", "peak gain in dB, default 0"),
+            ],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let bw = props.get_or("bw", 1.0)?;
+                let gain = props.get_or("gain", 0.0)?;
+                Ok(Instance::filter(PeakingFilter::new(freq, bw, gain)))
+            },
+        },
+        Factory {
+            name: "allpass",
+            description: "biquad all-pass filter",
+            properties: &[("freq", "centre in Hz"), ("q", "default 0.707")],
+            build: |props| {
+                let freq = props.require("freq")?;
+                let q = props.get_or("q", DEFAULT_Q)?;
+                Ok(Instance::filter(AllPassFilter::new(freq, q)))
+            },
+        },
+    ]
+}
+
+const DEFAULT_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+fn parse_quality(element: &str, quality: &str) -> Result<Quality, Error> {
+    match quality {
+        "linear" => Ok(Quality::Linear),
+        "low" => Ok(Quality::Low),
+        "medium" => Ok(Quality::Medium),
+        "high" => Ok(Quality::High),
+        _ => Err(Error::InvalidValue {
+            element: element.to_string(),
+            property: "resample".to_string(),
+            value: quality.to_string(),
+        }),
+    }
+}
+
+// reads the file in its own layout and mixes it to the frames of the graph
+fn wav_source<S>(reader: WAVReader<File>, looping: bool) -> Result<Instance, crate::Error>
+where
+    S: FromLayout + IntoSample<Frame> + Default + Send + 'static,
+    S::Member: FromSampleType<i32> + FromSampleType<f64> + IntoSampleType<f64>,
+{
+    let source = WAVSource::<S>::from_wav_reader(reader).looping(looping);
+    let to_frame = FnElement::new(|s: S| -> Frame { s.into_sample() });
+    Ok(Instance::source(Pipe::new(source, to_frame)))
+}
+
+// errors
+
+#[derive(Debug)]
+pub enum Error {
+    Syntax(String),
+    /// a description needs at least a first and a last element.
+    TooShort,
+    UnknownElement(String),
+    UnknownProperty {
+        element: String,
+        property: String,
+    },
+    MissingProperty {
+        element: String,
+        property: String,
+    },
+    InvalidValue {
+        element: String,
+        property: String,
+        value: String,
+    },
+    /// the element cannot go at this position, such as a sink first.
+    Misplaced {
+        element: String,
+        position: &'static str,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::Syntax(ref msg) => write!(f, "syntax error: {}", msg),
+            Error::TooShort => write!(f, "a pipeline needs a source and a sink"),
+            Error::UnknownElement(ref element) => write!(f, "no element \"{}\"", element),
+            Error::UnknownProperty {
+                ref element,
+                ref property,
+            } => write!(f, "{} has no property \"{}\"", element, property),
+            Error::MissingProperty {
+                ref element,
+                ref property,
+            } => write!(f, "{} needs property \"{}\"", element, property),
+            Error::InvalidValue {
+                ref element,
+                ref property,
+                ref value,
+            } => write!(
+                f,
+                "invalid value \"{}\" for property \"{}\" of {}",
+                value, property, element
+            ),
+            Error::Misplaced {
+                ref element,
+                position,
+            } => write!(f, "{} cannot go {} in a pipeline", element, position),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::WAVWriter;
+    use std::path::Path;
+
+    fn words(description: &str) -> Vec<Token> {
+        tokenize(description).unwrap()
+    }
+
+    fn word(word: &str) -> Token {
+        Token::Word(word.to_string())
+    }
+
+    fn descs(description: &str) -> Result<Vec<ElementDesc>, Error> {
+        parse(tokenize(description)?)
+    }
+
+    fn error(description: &str) -> crate::Error {
+        match Registry::new().parse(description) {
+            Ok(_) => panic!("\"{}\" parsed", description),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn tokenize_words_links_and_quotes() {
+        assert_eq!(
+            words("a  b=1!c d=\"x y\" ! e=\"q\\\"uote\"!"),
+            vec![
+                word("a"),
+                word("b=1"),
+                Token::Link,
+                word("c"),
+                word("d=x y"),
+                Token::Link,
+                word("e=q\"uote"),
+                Token::Link,
+            ]
+        );
+        assert_eq!(words(" \t"), vec![]);
+        assert!(matches!(tokenize("a b=\"x"), Err(Error::Syntax(_))));
+        assert!(matches!(tokenize("a b=\"x\\"), Err(Error::Syntax(_))));
+    }
+
+    #[test]
+    fn parse_elements_and_properties() {
+        let parsed = descs("wavsrc location=\"a b.wav\" loop=true ! nullsink").unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].element, "wavsrc");
+        assert_eq!(
+            parsed[0].props,
+            vec![
+                ("location".to_string(), "a b.wav".to_string()),
+                ("loop".to_string(), "true".to_string()),
+            ]
+        );
+        assert_eq!(parsed[1].element, "nullsink");
+        assert!(parsed[1].props.is_empty());
+        // values may contain `=`
+        assert_eq!(descs("a b=c=d").unwrap()[0].props[0].1, "c=d");
+
+        for description in &["", "a ! ! b", "x=1 ! b", "a b ! c", "a =1 ! c"] {
+            assert!(
+                matches!(descs(description), Err(Error::Syntax(_))),
+                "{}",
+                description
+            );
+        }
+    }
+
+    #[test]
+    fn registry_names_graph_nodes() {
+        let launch = Registry::new()
+            .parse("sinewave freq=1000 ! gain db=-6 ! lowpass freq=500 ! gain ! nullsink")
+            .unwrap();
+        assert_eq!(launch.graph().nodes(), vec!["gain0", "lowpass0", "gain1"]);
+        let launch = Registry::new()
+            .parse("sinewave ! gain name=pre ! gain ! nullsink")
+            .unwrap();
+        assert_eq!(launch.graph().nodes(), vec!["pre", "gain0"]);
+        assert!(matches!(
+            error("sinewave ! gain name=a ! identity name=a ! nullsink"),
+            crate::Error::DuplicateNode(ref name) if name == "a"
+        ));
+    }
+
+    #[test]
+    fn registry_errors() {
+        assert!(matches!(
+            error("sinewave"),
+            crate::Error::Launch(Error::TooShort)
+        ));
+        assert!(matches!(
+            error("sinewave ! nosuchsink"),
+            crate::Error::Launch(Error::UnknownElement(ref e)) if e == "nosuchsink"
+        ));
+        assert!(matches!(
+            error("sinewave ! gain gain=1 ! nullsink"),
+            crate::Error::Launch(Error::UnknownProperty { ref property, .. }) if property == "gain"
+        ));
+        assert!(matches!(
+            error("sinewave ! lowpass ! nullsink"),
+            crate::Error::Launch(Error::MissingProperty { ref property, .. }) if property == "freq"
+        ));
+        assert!(matches!(
+            error("sinewave ! gain db=loud ! nullsink"),
+            crate::Error::Launch(Error::InvalidValue { ref value, .. }) if value == "loud"
+        ));
+        assert!(matches!(
+            error("sinewave ! dither bits=1 ! nullsink"),
+            crate::Error::Launch(Error::InvalidValue { ref property, .. }) if property == "bits"
+        ));
+        assert!(matches!(
+            error("nullsink ! sinewave"),
+            crate::Error::Launch(Error::Misplaced {
+                position: "first",
+                ..
+            })
+        ));
+        assert!(matches!(
+            error("sinewave ! nullsink ! nullsink"),
+            crate::Error::Launch(Error::Misplaced {
+                position: "in the middle",
+                ..
+            })
+        ));
+        assert!(matches!(
+            error("sinewave name=src ! nullsink"),
+            crate::Error::Launch(Error::UnknownProperty { ref property, .. }) if property == "name"
+        ));
+        assert!(matches!(
+            error("sinewave ! gain ! nullsink name=out"),
+            crate::Error::Launch(Error::UnknownProperty { ref property, .. }) if property == "name"
+        ));
+    }
+
+    fn temp_path(name: &str) -> String {
+        let file = format!("rust-streamer-launch-{}-{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    // a mono ramp of `frames` frames at 44.1 kHz
+    fn write_ramp(path: &str, frames: usize) {
+        let file = File::create(path).unwrap();
+        let mut writer = WAVWriter::new(file, WAVFormat::PCM16, 1, 44100).unwrap();
+        for i in 0..frames {
+            writer.write_sample(Mono(i as i16 * 16)).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn read_stereo(path: &str) -> (u32, Vec<Stereo<i16>>) {
+        let mut reader = WAVReader::new(File::open(path).unwrap()).unwrap();
+        let mut out = Vec::new();
+        while let Some(s) = reader.read_sample_as::<Stereo<i16>>().unwrap() {
+            out.push(s);
+        }
+        assert_eq!(reader.header.channels, 2);
+        (reader.header.samplerate, out)
+    }
+
+    #[test]
+    fn wavsink_creates_the_file_when_started() {
+        let path = temp_path("created.wav");
+        assert!(matches!(
+            error(&format!("sinewave ! wavsink location={} ! nullsink", path)),
+            crate::Error::Launch(Error::Misplaced { .. })
+        ));
+        assert!(!Path::new(&path).exists());
+
+        let description = format!("sinewave ! wavsink location={}", path);
+        let launch = Registry::new().parse(&description).unwrap();
+        assert!(!Path::new(&path).exists());
+        let handle = Pipeline::start(launch, DefaultContext::new().freq(44100)).unwrap();
+        handle.stop();
+        handle.join();
+        assert!(Path::new(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wavsrc_reads_the_file_layout() {
+        let (src, dst) = (temp_path("src.wav"), temp_path("dst.wav"));
+        write_ramp(&src, 1000);
+        let description = format!("wavsrc location={} ! wavsink location={}", src, dst);
+        let launch = Registry::new().parse(&description).unwrap();
+        Pipeline::start(launch, DefaultContext::new())
+            .unwrap()
+            .join();
+        let (_, out) = read_stereo(&dst);
+        std::fs::remove_file(src).unwrap();
+        std::fs::remove_file(dst).unwrap();
+        assert_eq!(out.len(), 1000);
+        for (i, s) in out.iter().enumerate() {
+            assert_eq!((s.l, s.r), (i as i16 * 16, i as i16 * 16));
+        }
+    }
+
+    #[test]
+    fn parse_args_takes_words_verbatim() {
+        // as a shell passes `location='a "b\c" ! d.wav'`
+        let (src, dst) = (temp_path("a \"b\\c\" ! d.wav"), temp_path("args.wav"));
+        write_ramp(&src, 100);
+        let args = vec![
+            "wavsrc".to_string(),
+            format!("location={}", src),
+            "!".to_string(),
+            "wavsink".to_string(),
+            format!("location={}", dst),
+        ];
+        let launch = Registry::new().parse_args(args).unwrap();
+        Pipeline::start(launch, DefaultContext::new())
+            .unwrap()
+            .join();
+        let (_, out) = read_stereo(&dst);
+        std::fs::remove_file(src).unwrap();
+        std::fs::remove_file(dst).unwrap();
+        assert_eq!(out.len(), 100);
+    }
+
+    #[test]
+    fn sources_take_a_resample_quality() {
+        let (src, dst) = (temp_path("44k.wav"), temp_path("48k.wav"));
+        write_ramp(&src, 1000);
+        let description = format!(
+            "wavsrc location={} resample=high ! wavsink location={}",
+            src, dst
+        );
+        let launch = Registry::new().parse(&description).unwrap();
+        Pipeline::start(launch, DefaultContext::new().freq(48000))
+            .unwrap()
+            .join();
+        let (samplerate, out) = read_stereo(&dst);
+        std::fs::remove_file(src).unwrap();
+        std::fs::remove_file(dst).unwrap();
+        assert_eq!(samplerate, 48000);
+        assert_eq!(out.len(), (1000.0 * 48000.0 / 44100.0_f64).ceil() as usize);
+
+        assert!(matches!(
+            error("sinewave resample=best ! nullsink"),
+            crate::Error::Launch(Error::InvalidValue { ref property, .. }) if property == "resample"
+        ));
+        for description in &[
+            "sinewave ! gain resample=high ! nullsink",
+            "sinewave ! nullsink resample=high",
+        ] {
+            assert!(matches!(
+                error(description),
+                crate::Error::Launch(Error::UnknownProperty { ref property, .. }) if property == "resample"
+            ));
+        }
+    }
+
+    #[test]
+    fn queue_takes_the_graph_before_it() {
+        let launch = Registry::new()
+            .parse("sinewave ! gain name=pre ! queue capacity=256 ! gain ! nullsink")
+            .unwrap();
+        let graphs: Vec<Vec<String>> = launch.graphs().iter().map(|g| g.nodes()).collect();
+        assert_eq!(graphs, vec![vec!["pre"], vec!["gain0"]]);
+        assert_eq!(launch.graph().nodes(), vec!["gain0"]);
+        let handle = Pipeline::start(launch, DefaultContext::new().freq(44100)).unwrap();
+        handle.stop();
+        handle.join();
+
+        assert!(matches!(
+            error("sinewave ! gain name=a ! queue ! gain name=a ! nullsink"),
+            crate::Error::DuplicateNode(ref name) if name == "a"
+        ));
+        assert!(matches!(
+            error("defaultsrc ! queue ! nullsink"),
+            crate::Error::Launch(Error::Misplaced {
+                position: "after a capture device",
+                ..
+            })
+        ));
+        assert!(matches!(
+            error("sinewave ! queue"),
+            crate::Error::Launch(Error::Misplaced {
+                position: "last",
+                ..
+            })
+        ));
+    }
+}
@@ -1,43 +1,238 @@
 use std::ops::Add;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
 
 pub mod context;
+pub mod device;
 pub mod dsp;
 pub mod element;
+pub mod error;
+pub mod graph;
 #[cfg(feature = "graphic")]
 pub mod graphic;
+pub mod launch;
+pub mod param;
+pub mod ring;
 pub mod sample;
 pub mod wav;
 
+pub use crate::error::Error;
+
+use crate::context::FreqCtx;
+use crate::sample::*;
+
 // core traits
 
+/// number of frames `Pipe` and `Fork` forward per `process` call.
+pub const BLOCK_SIZE: usize = 64;
+
 pub trait Element<Sink, Ctx> {
     type Src;
-    fn init(&mut self, _ctx: &mut Ctx) {}
-    fn start(&mut self, _ctx: &Ctx) {}
-    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Self::Src;
+    fn init(&mut self, _ctx: &mut Ctx) -> Result<(), Error> {
+        Ok(())
+    }
+    fn start(&mut self, _ctx: &Ctx) -> Result<(), Error> {
+        Ok(())
+    }
+    /// returns `None` once the stream has ended.
+    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Option<Self::Src>;
+    /// processes a block of frames and returns how many were written to `output`.
+    /// a return value smaller than `input.len()` means the stream has ended.
+    fn process(&mut self, input: &[Sink], output: &mut [Self::Src], ctx: &Ctx) -> usize
+    where
+        Sink: Copy,
+    {
+        for (i, (sink, src)) in input.iter().zip(output.iter_mut()).enumerate() {
+            match self.next(*sink, ctx) {
+                Some(s) => *src = s,
+                None => return i,
+            }
+        }
+        input.len().min(output.len())
+    }
+    /// called once after the stream has ended or the pipeline was stopped.
+    fn finish(&mut self, _ctx: &Ctx) {}
 }
+/// drives a chain from its own clock. `start` must not block; the element reports
+/// end of stream through `handle` and stops running once `handle` is stopped.
 pub trait PullElement<Sink, Ctx> {
-    fn init(&mut self, _ctx: &mut Ctx) {}
-    fn start(&mut self, sink: impl Element<(), Ctx, Src = Sink> + Send, ctx: &Ctx);
+    fn init(&mut self, _ctx: &mut Ctx) -> Result<(), Error> {
+        Ok(())
+    }
+    fn start(
+        &mut self,
+        sink: impl Element<(), Ctx, Src = Sink> + Send + 'static,
+        ctx: Ctx,
+        handle: PipelineHandle,
+    ) -> Result<(), Error>;
     fn stop(&mut self);
 }
+/// same as `PullElement`, for elements that push frames into a chain.
 pub trait PushElement<Ctx> {
     type Src;
-    fn init(&mut self, _ctx: &mut Ctx) {}
-    fn start(&mut self, src: impl Element<Self::Src, Ctx, Src = ()> + Send, ctx: &Ctx);
+    fn init(&mut self, _ctx: &mut Ctx) -> Result<(), Error> {
+        Ok(())
+    }
+    fn start(
+        &mut self,
+        src: impl Element<Self::Src, Ctx, Src = ()> + Send + 'static,
+        ctx: Ctx,
+        handle: PipelineHandle,
+    ) -> Result<(), Error>;
     fn stop(&mut self);
 }
 pub trait Pipeline<Ctx: Context> {
-    fn start(self, ctx: Ctx);
+    fn start(self, ctx: Ctx) -> Result<PipelineHandle, Error>;
 }
 pub trait SinkPipeline<Ctx: Context> {
-    fn start(self, ctx: Ctx);
+    fn start(self, ctx: Ctx) -> Result<PipelineHandle, Error>;
 }
 pub trait SrcPipeline<Ctx: Context> {
-    fn start(self, ctx: Ctx);
+    fn start(self, ctx: Ctx) -> Result<PipelineHandle, Error>;
 }
 pub trait Context: Sized {
-    fn build(self) -> Result<Self, ()>;
+    fn build(self) -> Result<Self, Error>;
+    /// frames the pipeline has run through before the current block, in the rate
+    /// of this context. automation follows it. contexts that do not track it stay
+    /// at 0, and elements then count their own frames.
+    fn get_position(&self) -> u64 {
+        0
+    }
+    /// called by whatever drives the pipeline before each block.
+    fn set_position(&mut self, _position: u64) {}
+}
+
+// handle
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PipelineState {
+    Playing,
+    Paused,
+    /// stopped through `PipelineHandle::stop`.
+    Stopped,
+    /// reached end of stream.
+    Finished,
+}
+
+/// controls a running pipeline from any thread.
+#[derive(Clone)]
+pub struct PipelineHandle {
+    shared: Arc<(Mutex<Status>, Condvar)>,
+}
+struct Status {
+    state: PipelineState,
+    // threads and device callbacks that have not torn down their elements yet
+    workers: usize,
+}
+impl PipelineHandle {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new((
+                Mutex::new(Status {
+                    state: PipelineState::Playing,
+                    workers: 0,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+    pub fn state(&self) -> PipelineState {
+        self.shared.0.lock().unwrap().state
+    }
+    pub fn is_done(&self) -> bool {
+        self.state().is_done()
+    }
+    /// whether the pipeline is done and every worker has finished its elements.
+    pub fn is_torn_down(&self) -> bool {
+        let status = self.shared.0.lock().unwrap();
+        status.state.is_done() && status.workers == 0
+    }
+    pub fn pause(&self) {
+        self.transition(|state| match state {
+            PipelineState::Playing => PipelineState::Paused,
+            state => state,
+        });
+    }
+    pub fn resume(&self) {
+        self.transition(|state| match state {
+            PipelineState::Paused => PipelineState::Playing,
+            state => state,
+        });
+    }
+    pub fn stop(&self) {
+        self.transition(|state| match state {
+            PipelineState::Playing | PipelineState::Paused => PipelineState::Stopped,
+            state => state,
+        });
+    }
+    /// marks the pipeline as having reached end of stream.
+    pub fn finish(&self) {
+        self.transition(|state| match state {
+            PipelineState::Playing | PipelineState::Paused => PipelineState::Finished,
+            state => state,
+        });
+    }
+    /// registers a thread or device callback running elements of the pipeline.
+    /// drop the guard once `Element::finish` has returned; `join` waits for it.
+    pub fn attach(&self) -> WorkerGuard {
+        self.shared.0.lock().unwrap().workers += 1;
+        WorkerGuard {
+            handle: self.clone(),
+        }
+    }
+    /// blocks until the pipeline is stopped or finished and every worker has
+    /// finished its elements, so that sinks have flushed their output.
+    pub fn join(&self) {
+        let (ref status, ref cvar) = *self.shared;
+        let mut status = status.lock().unwrap();
+        while !status.state.is_done() || status.workers > 0 {
+            status = cvar.wait(status).unwrap();
+        }
+    }
+    /// blocks while paused. returns false once the pipeline should not run any further.
+    pub fn wait_playing(&self) -> bool {
+        let (ref status, ref cvar) = *self.shared;
+        let mut status = status.lock().unwrap();
+        while let PipelineState::Paused = status.state {
+            status = cvar.wait(status).unwrap();
+        }
+        status.state == PipelineState::Playing
+    }
+    fn transition(&self, f: impl FnOnce(PipelineState) -> PipelineState) {
+        let (ref status, ref cvar) = *self.shared;
+        let mut status = status.lock().unwrap();
+        status.state = f(status.state);
+        cvar.notify_all();
+    }
+}
+impl Default for PipelineHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl PipelineState {
+    fn is_done(self) -> bool {
+        matches!(self, PipelineState::Stopped | PipelineState::Finished)
+    }
+}
+
+/// keeps `PipelineHandle::join` waiting until dropped. a worker panicking with
+/// the guard alive stops the pipeline.
+pub struct WorkerGuard {
+    handle: PipelineHandle,
+}
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.handle.stop();
+        }
+        let (ref status, ref cvar) = *self.handle.shared;
+        let mut status = status.lock().unwrap();
+        status.workers -= 1;
+        cvar.notify_all();
+    }
 }
 
 // pipe
@@ -55,57 +250,103 @@ impl<A, B, Sink, Ctx> Element<Sink, Ctx> for Pipe<A, B>
 where
     A: Element<Sink, Ctx>,
     B: Element<A::Src, Ctx>,
+    A::Src: Copy + Default,
 {
     type Src = B::Src;
-    fn init(&mut self, ctx: &mut Ctx) {
-        self.a.init(ctx);
-        self.b.init(ctx);
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        self.a.init(ctx)?;
+        self.b.init(ctx)
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.a.start(ctx)?;
+        self.b.start(ctx)
     }
-    fn start(&mut self, ctx: &Ctx) {
-        self.a.start(ctx);
-        self.b.start(ctx);
+    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Option<Self::Src> {
+        self.b.next(self.a.next(sink, ctx)?, ctx)
     }
-    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Self::Src {
-        self.b.next(self.a.next(sink, ctx), ctx)
+    fn finish(&mut self, ctx: &Ctx) {
+        self.a.finish(ctx);
+        self.b.finish(ctx);
+    }
+    fn process(&mut self, input: &[Sink], output: &mut [Self::Src], ctx: &Ctx) -> usize
+    where
+        Sink: Copy,
+    {
+        let mut mid = [A::Src::default(); BLOCK_SIZE];
+        let mut done = 0;
+        for (input, output) in input.chunks(BLOCK_SIZE).zip(output.chunks_mut(BLOCK_SIZE)) {
+            let len = input.len().min(output.len());
+            let n = self.a.process(&input[..len], &mut mid[..len], ctx);
+            let n = self.b.process(&mid[..n], &mut output[..n], ctx);
+            done += n;
+            if n < len {
+                break;
+            }
+        }
+        done
     }
 }
-impl<A, B, Ctx: Context> Pipeline<Ctx> for Pipe<A, B>
+impl<A, B, Ctx> Pipeline<Ctx> for Pipe<A, B>
 where
-    Self: Element<(), Ctx, Src = ()>,
+    Self: Element<(), Ctx, Src = ()> + Send + 'static,
+    Ctx: Context + Send + 'static,
 {
-    fn start(mut self, mut ctx: Ctx) {
-        self.init(&mut ctx);
-        let ctx = ctx.build().unwrap();
-        Element::start(&mut self, &ctx);
-        loop {
-            self.next((), &ctx);
-        }
+    fn start(mut self, mut ctx: Ctx) -> Result<PipelineHandle, Error> {
+        self.init(&mut ctx)?;
+        let mut ctx = ctx.build()?;
+        Element::start(&mut self, &ctx)?;
+        let handle = PipelineHandle::new();
+        let h = handle.clone();
+        let worker = handle.attach();
+        thread::spawn(move || {
+            let input = [(); BLOCK_SIZE];
+            let mut output = [(); BLOCK_SIZE];
+            let mut position = 0;
+            while h.wait_playing() {
+                ctx.set_position(position);
+                let n = self.process(&input, &mut output, &ctx);
+                if n < BLOCK_SIZE {
+                    break;
+                }
+                position += n as u64;
+            }
+            Element::finish(&mut self, &ctx);
+            h.finish();
+            drop(worker);
+        });
+        Ok(handle)
     }
 }
-impl<A, B, Ctx: Context> SinkPipeline<Ctx> for Pipe<A, B>
+impl<A, B, Ctx> SinkPipeline<Ctx> for Pipe<A, B>
 where
-    A: Element<(), Ctx> + Send,
+    A: Element<(), Ctx> + Send + 'static,
     B: PullElement<A::Src, Ctx>,
+    Ctx: Context + Send + 'static,
 {
-    fn start(mut self, mut ctx: Ctx) {
-        self.a.init(&mut ctx);
-        self.b.init(&mut ctx);
-        let ctx = ctx.build().unwrap();
-        self.a.start(&ctx);
-        self.b.start(self.a, &ctx);
+    fn start(mut self, mut ctx: Ctx) -> Result<PipelineHandle, Error> {
+        self.a.init(&mut ctx)?;
+        self.b.init(&mut ctx)?;
+        let ctx = ctx.build()?;
+        self.a.start(&ctx)?;
+        let handle = PipelineHandle::new();
+        self.b.start(self.a, ctx, handle.clone())?;
+        Ok(handle)
     }
 }
-impl<A, B, Ctx: Context> SrcPipeline<Ctx> for Pipe<A, B>
+impl<A, B, Ctx> SrcPipeline<Ctx> for Pipe<A, B>
 where
     A: PushElement<Ctx>,
-    B: Element<A::Src, Ctx, Src = ()> + Send,
+    B: Element<A::Src, Ctx, Src = ()> + Send + 'static,
+    Ctx: Context + Send + 'static,
 {
-    fn start(mut self, mut ctx: Ctx) {
-        self.a.init(&mut ctx);
-        self.b.init(&mut ctx);
-        let ctx = ctx.build().unwrap();
-        self.b.start(&ctx);
-        self.a.start(self.b, &ctx);
+    fn start(mut self, mut ctx: Ctx) -> Result<PipelineHandle, Error> {
+        self.a.init(&mut ctx)?;
+        self.b.init(&mut ctx)?;
+        let ctx = ctx.build()?;
+        self.b.start(&ctx)?;
+        let handle = PipelineHandle::new();
+        self.a.start(self.b, ctx, handle.clone())?;
+        Ok(handle)
     }
 }
 
@@ -138,20 +379,47 @@ impl<A, B, Sink, Ctx> Element<Sink, Ctx> for Fork<A, B>
 where
     A: Element<Sink, Ctx>,
     B: Element<Sink, Ctx>,
-    A::Src: Add<B::Src>,
+    A::Src: Add<B::Src> + Copy + Default,
+    B::Src: Copy + Default,
     Sink: Copy,
 {
     type Src = <A::Src as Add<B::Src>>::Output;
-    fn init(&mut self, ctx: &mut Ctx) {
-        self.a.init(ctx);
-        self.b.init(ctx);
+    fn init(&mut self, ctx: &mut Ctx) -> Result<(), Error> {
+        self.a.init(ctx)?;
+        self.b.init(ctx)
+    }
+    fn start(&mut self, ctx: &Ctx) -> Result<(), Error> {
+        self.a.start(ctx)?;
+        self.b.start(ctx)
     }
-    fn start(&mut self, ctx: &Ctx) {
-        self.a.start(ctx);
-        self.b.start(ctx);
+    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Option<Self::Src> {
+        // pull both branches so they stay in step, end when either ends
+        let a = self.a.next(sink, ctx);
+        let b = self.b.next(sink, ctx);
+        Some(a? + b?)
     }
-    fn next(&mut self, sink: Sink, ctx: &Ctx) -> Self::Src {
-        self.a.next(sink, ctx) + self.b.next(sink, ctx)
+    fn finish(&mut self, ctx: &Ctx) {
+        self.a.finish(ctx);
+        self.b.finish(ctx);
+    }
+    fn process(&mut self, input: &[Sink], output: &mut [Self::Src], ctx: &Ctx) -> usize {
+        let mut mid_a = [A::Src::default(); BLOCK_SIZE];
+        let mut mid_b = [B::Src::default(); BLOCK_SIZE];
+        let mut done = 0;
+        for (input, output) in input.chunks(BLOCK_SIZE).zip(output.chunks_mut(BLOCK_SIZE)) {
+            let len = input.len().min(output.len());
+            let n_a = self.a.process(&input[..len], &mut mid_a[..len], ctx);
+            let n_b = self.b.process(&input[..len], &mut mid_b[..len], ctx);
+            let n = n_a.min(n_b);
+            for i in 0..n {
+                output[i] = mid_a[i] + mid_b[i];
+            }
+            done += n;
+            if n < len {
+                break;
+            }
+        }
+        done
     }
 }
 
@@ -168,3 +436,555 @@ macro_rules! fork {
         f
     }}
 }
+
+// offline
+
+/// runs a chain as fast as possible on the calling thread, without an audio
+/// device, until the stream ends or the frame limit is reached.
+pub struct Offline<A> {
+    chain: A,
+    frames: Option<usize>,
+}
+impl<A> Offline<A> {
+    pub fn new(chain: A) -> Self {
+        Self {
+            chain: chain,
+            frames: None,
+        }
+    }
+    /// stops after `frames` frames. without a limit, the chain must end by itself.
+    pub fn frames(self, frames: usize) -> Self {
+        Self {
+            frames: Some(frames),
+            ..self
+        }
+    }
+    /// collects the output of the chain.
+    pub fn render<Ctx>(self, ctx: Ctx) -> Result<Vec<A::Src>, Error>
+    where
+        A: Element<(), Ctx>,
+        A::Src: Copy + Default,
+        Ctx: Context,
+    {
+        let mut output = Vec::new();
+        run_offline(self.chain, ctx, self.frames, |block| {
+            output.extend_from_slice(block)
+        })?;
+        Ok(output)
+    }
+    /// feeds the output of the chain into `sink`, such as a `WAVSink`, and returns
+    /// the number of frames rendered.
+    pub fn render_into<B, Ctx>(self, sink: B, ctx: Ctx) -> Result<usize, Error>
+    where
+        A: Element<(), Ctx>,
+        A::Src: Copy + Default,
+        B: Element<A::Src, Ctx, Src = ()>,
+        Ctx: Context,
+    {
+        let mut frames = 0;
+        let chain = Pipe::new(self.chain, sink);
+        run_offline(chain, ctx, self.frames, |block| frames += block.len())?;
+        Ok(frames)
+    }
+}
+
+fn run_offline<A, Ctx>(
+    mut chain: A,
+    mut ctx: Ctx,
+    frames: Option<usize>,
+    mut f: impl FnMut(&[A::Src]),
+) -> Result<(), Error>
+where
+    A: Element<(), Ctx>,
+    A::Src: Copy + Default,
+    Ctx: Context,
+{
+    chain.init(&mut ctx)?;
+    let mut ctx = ctx.build()?;
+    chain.start(&ctx)?;
+    let input = [(); BLOCK_SIZE];
+    let mut output = [A::Src::default(); BLOCK_SIZE];
+    let mut remaining = frames.unwrap_or(usize::MAX);
+    let mut position = 0;
+    while remaining > 0 {
+        let len = remaining.min(BLOCK_SIZE);
+        ctx.set_position(position);
+        let n = chain.process(&input[..len], &mut output[..len], &ctx);
+        f(&output[..n]);
+        remaining -= n;
+        position += n as u64;
+        if n < len {
+            break;
+        }
+    }
+    chain.finish(&ctx);
+    Ok(())
+}
+
+// duplex
+
+/// connects a capture `PushElement` to a playback `PullElement` running on another
+/// clock. captured frames go through a ring buffer and then through `chain` on the
+/// playback side.
+pub struct Duplex<A, B, C> {
+    src: A,
+    chain: B,
+    sink: C,
+    latency: Duration,
+    stats: DuplexStats,
+}
+impl<A, B, C> Duplex<A, B, C> {
+    pub fn new(src: A, chain: B, sink: C) -> Self {
+        Self {
+            src: src,
+            chain: chain,
+            sink: sink,
+            latency: Duration::from_millis(20),
+            stats: DuplexStats::new(),
+        }
+    }
+    /// amount of audio kept in the ring buffer. defaults to 20 ms.
+    pub fn latency(self, latency: Duration) -> Self {
+        Self {
+            latency: latency,
+            ..self
+        }
+    }
+    pub fn stats(&self) -> DuplexStats {
+        self.stats.clone()
+    }
+}
+impl<A, B, C, Ctx> Pipeline<Ctx> for Duplex<A, B, C>
+where
+    A: PushElement<Ctx>,
+    A::Src: Sample + Default + Send + 'static,
+    <A::Src as Sample>::Member: IntoSampleType<f64> + FromSampleType<f64> + Send,
+    B: Element<A::Src, Ctx> + Send + 'static,
+    C: PullElement<B::Src, Ctx>,
+    Ctx: Context + FreqCtx + Clone + Send + 'static,
+{
+    fn start(mut self, mut ctx: Ctx) -> Result<PipelineHandle, Error> {
+        self.src.init(&mut ctx)?;
+        self.chain.init(&mut ctx)?;
+        self.sink.init(&mut ctx)?;
+        let ctx = ctx.build()?;
+
+        let target = (self.latency.as_secs_f64() * ctx.get_freq() as f64) as usize;
+        let target = target.max(2);
+        let (producer, consumer) = ring::channel(target * 4);
+        let writer = RingWriter {
+            producer: producer,
+            stats: self.stats.clone(),
+        };
+        let reader = RingReader::new(consumer, target, ctx.get_freq(), self.stats.clone());
+        let mut reader = Pipe::new(reader, self.chain);
+        reader.start(&ctx)?;
+
+        let handle = PipelineHandle::new();
+        self.sink.start(reader, ctx.clone(), handle.clone())?;
+        if let Err(err) = self.src.start(writer, ctx, handle.clone()) {
+            handle.stop();
+            return Err(err);
+        }
+        Ok(handle)
+    }
+}
+
+/// counters of a running `Duplex`, readable from any thread.
+#[derive(Clone)]
+pub struct DuplexStats {
+    shared: Arc<Counters>,
+}
+#[derive(Default)]
+struct Counters {
+    underruns: AtomicUsize,
+    overruns: AtomicUsize,
+    fill: AtomicUsize,
+}
+impl DuplexStats {
+    fn new() -> Self {
+        Self {
+            shared: Arc::new(Counters::default()),
+        }
+    }
+    /// times playback ran out of captured frames.
+    pub fn underruns(&self) -> usize {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+    /// captured frames dropped because the ring buffer was full.
+    pub fn overruns(&self) -> usize {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+    /// frames currently buffered between capture and playback.
+    pub fn fill(&self) -> usize {
+        self.shared.fill.load(Ordering::Relaxed)
+    }
+}
+
+struct RingWriter<S> {
+    producer: ring::Producer<S>,
+    stats: DuplexStats,
+}
+impl<S: Copy, Ctx> Element<S, Ctx> for RingWriter<S> {
+    type Src = ();
+    fn next(&mut self, sink: S, _ctx: &Ctx) -> Option<()> {
+        if self.producer.push(sink).is_err() {
+            self.stats.shared.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(())
+    }
+    fn process(&mut self, input: &[S], output: &mut [()], _ctx: &Ctx) -> usize {
+        let len = input.len().min(output.len());
+        let n = self.producer.push_slice(&input[..len]);
+        if n < len {
+            let overruns = &self.stats.shared.overruns;
+            overruns.fetch_add(len - n, Ordering::Relaxed);
+        }
+        len
+    }
+}
+
+// rate correction per relative fill error, and its limit
+const DRIFT_GAIN: f64 = 0.01;
+const MAX_DRIFT: f64 = 0.002;
+
+/// plays the ring buffer back at a rate slightly adjusted to keep its fill level
+/// at `target`, so the capture and playback clocks may drift apart.
+struct RingReader<S: Sample> {
+    consumer: ring::Consumer<S>,
+    stats: DuplexStats,
+    target: usize,
+    primed: bool,
+    // smoothed fill level, over about one second
+    fill: f64,
+    smoothing: f64,
+    // interpolation between `prev` and `cur`
+    pos: f64,
+    prev: Vec<f64>,
+    cur: Vec<f64>,
+    raw: Vec<S::Member>,
+}
+impl<S> RingReader<S>
+where
+    S: Sample,
+    S::Member: IntoSampleType<f64> + FromSampleType<f64>,
+{
+    fn new(consumer: ring::Consumer<S>, target: usize, freq: u32, stats: DuplexStats) -> Self {
+        Self {
+            consumer: consumer,
+            stats: stats,
+            target: target,
+            primed: false,
+            fill: 0.0,
+            smoothing: 1.0 / freq as f64,
+            pos: 0.0,
+            prev: vec![0.0; S::CHANNELS],
+            cur: vec![0.0; S::CHANNELS],
+            raw: vec![S::Member::REF_LEVEL; S::CHANNELS],
+        }
+    }
+    fn pop(&mut self) -> bool {
+        match self.consumer.pop() {
+            Some(s) => {
+                std::mem::swap(&mut self.prev, &mut self.cur);
+                s.to_raw(&mut self.raw);
+                for (cur, &member) in self.cur.iter_mut().zip(self.raw.iter()) {
+                    *cur = member.into_sampletype();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+impl<S, Ctx> Element<(), Ctx> for RingReader<S>
+where
+    S: Sample,
+    S::Member: IntoSampleType<f64> + FromSampleType<f64>,
+{
+    type Src = S;
+    fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<S> {
+        let fill = self.consumer.len();
+        self.stats.shared.fill.store(fill, Ordering::Relaxed);
+        // play silence until the buffer is filled up to the latency again
+        if !self.primed {
+            if fill < self.target {
+                return Some(S::SILENCE);
+            }
+            self.pop();
+            self.pop();
+            self.pos = 0.0;
+            self.fill = fill as f64;
+            self.primed = true;
+        }
+
+        for (i, member) in self.raw.iter_mut().enumerate() {
+            let (prev, cur) = (self.prev[i], self.cur[i]);
+            *member = (prev + (cur - prev) * self.pos).into_sampletype();
+        }
+        let s = S::from_raw(&self.raw).unwrap_or(S::SILENCE);
+
+        self.fill += (fill as f64 - self.fill) * self.smoothing;
+        let error = (self.fill - self.target as f64) / self.target as f64;
+        self.pos += 1.0 + (error * DRIFT_GAIN).clamp(-MAX_DRIFT, MAX_DRIFT);
+        while self.pos >= 1.0 {
+            self.pos -= 1.0;
+            if !self.pop() {
+                self.stats.shared.underruns.fetch_add(1, Ordering::Relaxed);
+                self.primed = false;
+                break;
+            }
+        }
+        Some(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::DefaultContext;
+    use crate::element::*;
+    use crate::param::*;
+    use std::sync::atomic::AtomicBool;
+
+    struct SlowFinish(Arc<AtomicBool>);
+    impl<T, Ctx> Element<T, Ctx> for SlowFinish {
+        type Src = ();
+        fn next(&mut self, _sink: T, _ctx: &Ctx) -> Option<()> {
+            Some(())
+        }
+        fn finish(&mut self, _ctx: &Ctx) {
+            thread::sleep(Duration::from_millis(50));
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn join_waits_for_finish() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let p = Pipe::new(
+            SineWave::<Mono<f64>>::new(440.0),
+            SlowFinish(finished.clone()),
+        );
+        let handle = Pipeline::start(p, DefaultContext::new().freq(48000)).unwrap();
+        handle.stop();
+        handle.join();
+        assert!(finished.load(Ordering::SeqCst));
+        assert!(handle.is_torn_down());
+        assert_eq!(handle.state(), PipelineState::Stopped);
+    }
+
+    // a stereo ramp that ends after `len` frames
+    struct Ramp {
+        n: usize,
+        len: usize,
+    }
+    impl<Ctx> Element<(), Ctx> for Ramp {
+        type Src = Stereo<f64>;
+        fn next(&mut self, _sink: (), _ctx: &Ctx) -> Option<Stereo<f64>> {
+            if self.n == self.len {
+                return None;
+            }
+            self.n += 1;
+            let x = (self.n % 50) as f64 / 25.0 - 1.0;
+            Some(Stereo { l: x, r: -x * 0.5 })
+        }
+    }
+
+    // a source through a forked pair of filters, one gliding and one automated
+    fn filtered_ramp(len: usize) -> (impl Element<(), DefaultContext, Src = Stereo<f64>>, Param) {
+        let low_pass = LowPassFilter::new(1000.0, 0.7);
+        let freq = low_pass.param("freq").unwrap();
+        let sweep = Automation::new().point(0, 200.0, Curve::Linear).point(
+            len as u64,
+            4000.0,
+            Curve::Linear,
+        );
+        let high_pass = HighPassFilter::new(200.0, 0.7)
+            .automate("freq", sweep)
+            .unwrap();
+        let chain = Pipe::new(
+            Ramp { n: 0, len: len },
+            Fork::new(low_pass, Pipe::new(high_pass, Gain::new(-6.0))),
+        );
+        (chain, freq)
+    }
+
+    #[test]
+    fn blocks_match_frames_and_end_short() {
+        const FRAMES: usize = 4 * BLOCK_SIZE + 44;
+        let mut ctx = DefaultContext::new().freq(48000);
+        let (mut chain, freq) = filtered_ramp(FRAMES);
+        chain.init(&mut ctx).unwrap();
+        let mut ctx = ctx.build().unwrap();
+        chain.start(&ctx).unwrap();
+        let input = [(); BLOCK_SIZE];
+        let mut output = [Stereo::default(); BLOCK_SIZE];
+        let mut blocks = Vec::new();
+        loop {
+            if blocks.len() == 2 * BLOCK_SIZE {
+                freq.set(5000.0);
+            }
+            ctx.set_position(blocks.len() as u64);
+            let n = chain.process(&input, &mut output, &ctx);
+            blocks.extend_from_slice(&output[..n]);
+            if n < BLOCK_SIZE {
+                assert_eq!(n, 44);
+                break;
+            }
+        }
+        assert_eq!(blocks.len(), FRAMES);
+
+        let mut ctx = DefaultContext::new().freq(48000);
+        let (mut chain, freq) = filtered_ramp(FRAMES);
+        chain.init(&mut ctx).unwrap();
+        let mut ctx = ctx.build().unwrap();
+        chain.start(&ctx).unwrap();
+        let mut frames = Vec::new();
+        loop {
+            if frames.len() == 2 * BLOCK_SIZE {
+                freq.set(5000.0);
+            }
+            ctx.set_position(frames.len() as u64);
+            match chain.next((), &ctx) {
+                Some(s) => frames.push(s),
+                None => break,
+            }
+        }
+        let pairs =
+            |v: &[Stereo<f64>]| -> Vec<(f64, f64)> { v.iter().map(|s| (s.l, s.r)).collect() };
+        assert_eq!(pairs(&blocks), pairs(&frames));
+    }
+
+    #[test]
+    fn offline_renders_sine_through_gain() {
+        let chain = Pipe::new(SineWave::<Mono<f64>>::new(1000.0), Gain::new(-6.0));
+        let out = Offline::new(chain)
+            .frames(4800)
+            .render(DefaultContext::new().freq(48000))
+            .unwrap();
+        assert_eq!(out.len(), 4800);
+        let peak = out.iter().fold(0.0, |peak: f64, s| peak.max(s.0.abs()));
+        assert!(
+            (peak - f64::powf(10.0, -6.0 / 20.0)).abs() < 1e-3,
+            "{}",
+            peak
+        );
+        // 48 samples per period, the first one already a step into it
+        assert!((out[11].0 - peak).abs() < 1e-9 && (out[35].0 + peak).abs() < 1e-9);
+    }
+
+    #[test]
+    fn offline_wav_round_trip() {
+        let path = std::env::temp_dir().join(format!("rust-streamer-{}.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+        let ctx = DefaultContext::new().freq(44100);
+        let expected = Offline::new(SineWave::<Stereo<f64>>::new(440.0))
+            .frames(1000)
+            .render(ctx.clone())
+            .unwrap();
+        let sink = WAVSink::new(path);
+        let written = Offline::new(SineWave::<Stereo<f64>>::new(440.0))
+            .frames(1000)
+            .render_into(sink, ctx)
+            .unwrap();
+        assert_eq!(written, 1000);
+
+        let source = WAVSource::<Stereo<f64>>::new(path).unwrap();
+        let out = Offline::new(source).render(DefaultContext::new());
+        std::fs::remove_file(path).unwrap();
+        let out = out.unwrap();
+        assert_eq!(out.len(), 1000);
+        for (s, e) in out.iter().zip(expected.iter()) {
+            assert!((s.l - e.l).abs() <= 1.0 / 32768.0 && (s.r - e.r).abs() <= 1.0 / 32768.0);
+        }
+    }
+
+    #[test]
+    fn ring_reader_primes_with_silence() {
+        let (mut producer, consumer) = ring::channel::<Stereo<u8>>(16);
+        let mut reader = RingReader::new(consumer, 4, 48000, DuplexStats::new());
+        let s: Option<Stereo<u8>> = reader.next((), &());
+        assert_eq!(s, Some(Stereo::new(0x80)));
+
+        for _ in 0..4 {
+            producer.push(Stereo::new(0xc0)).unwrap();
+        }
+        assert_eq!(reader.next((), &()), Some(Stereo::new(0xc0)));
+    }
+
+    // frames the reader has taken out of the ring so far
+    fn consumed(accepted: usize, reader: &RingReader<Mono<f64>>) -> usize {
+        accepted - reader.consumer.len()
+    }
+
+    #[test]
+    fn ring_reader_speeds_up_for_a_fast_producer() {
+        let (producer, consumer) = ring::channel::<Mono<f64>>(400);
+        let stats = DuplexStats::new();
+        let mut writer = RingWriter {
+            producer: producer,
+            stats: stats.clone(),
+        };
+        let mut reader = RingReader::new(consumer, 100, 100, stats.clone());
+        let mut pushed = 0;
+        let mut window = 0;
+        // three frames captured for every two played fill the ring to the top
+        for i in 0..20000 {
+            for _ in 0..1 + i % 2 {
+                writer.next(Mono(0.5), &()).unwrap();
+                pushed += 1;
+            }
+            let s = reader.next((), &());
+            // silence until the latency is buffered
+            assert_eq!(s.map(|s| s.0), Some(if i < 66 { 0.0 } else { 0.5 }));
+            if i == 4999 {
+                window = consumed(pushed - stats.overruns(), &reader);
+            }
+        }
+        let window = consumed(pushed - stats.overruns(), &reader) - window;
+        assert!((window as f64 - 15000.0 * (1.0 + MAX_DRIFT)).abs() <= 1.0);
+        assert!(stats.overruns() > 0);
+        assert_eq!(stats.underruns(), 0);
+        assert!(stats.fill() >= 395);
+    }
+
+    #[test]
+    fn ring_reader_slows_down_for_a_slow_producer() {
+        let (producer, consumer) = ring::channel::<Mono<f64>>(400);
+        let stats = DuplexStats::new();
+        let mut writer = RingWriter {
+            producer: producer,
+            stats: stats.clone(),
+        };
+        let mut reader = RingReader::new(consumer, 100, 100, stats.clone());
+        let mut window = 0;
+        // 999 frames captured for every 1000 played
+        for i in 0..150_000 {
+            if i % 1000 != 999 {
+                writer.next(Mono(0.5), &()).unwrap();
+            }
+            reader.next((), &()).unwrap();
+            if i == 99_999 {
+                window = consumed(i + 1 - (i + 1) / 1000, &reader);
+            }
+        }
+        let window = consumed(150_000 - 150, &reader) - window;
+        assert!((window as f64 - 49950.0).abs() <= 2.0);
+        // the rate settles where the fill error makes up for the 0.1% difference
+        assert!((stats.fill() as i64 - 90).abs() <= 2);
+        assert_eq!(stats.overruns(), 0);
+        assert_eq!(stats.underruns(), 0);
+
+        // once capture stops the ring runs dry and plays silence until refilled
+        for _ in 0..200 {
+            reader.next((), &()).unwrap();
+        }
+        assert_eq!(stats.underruns(), 1);
+        assert_eq!(stats.fill(), 0);
+        assert_eq!(reader.next((), &()).map(|s| s.0), Some(0.0));
+        writer.next(Mono(0.5), &()).unwrap();
+        assert_eq!(reader.next((), &()).map(|s| s.0), Some(0.0));
+        assert_eq!(stats.underruns(), 1);
+    }
+}
@@ -0,0 +1,62 @@
+use rust_streamer::context::DefaultContext;
+use rust_streamer::launch::Registry;
+use rust_streamer::{Error, Pipeline};
+
+use std::env;
+use std::process;
+
+const USAGE: &str = "usage: rust-streamer [--list] [--rate HZ] [--resample] ELEMENT [! ELEMENT]...";
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let registry = Registry::new();
+    let mut ctx = DefaultContext::new();
+    let mut description = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-l" | "--list" => {
+                list(&registry);
+                return Ok(());
+            }
+            "-r" | "--rate" => match args.next().and_then(|rate| rate.parse().ok()) {
+                Some(rate) => ctx = ctx.freq(rate),
+                None => usage(),
+            },
+            "--resample" => ctx = ctx.auto_resample(true),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => description.push(arg),
+        }
+    }
+    if description.is_empty() {
+        usage();
+    }
+    // the shell has already split and unquoted the words
+    let launch = registry.parse_args(description)?;
+    launch.start(ctx)?.join();
+    Ok(())
+}
+
+fn list(registry: &Registry) {
+    for factory in registry.factories() {
+        println!("{:12} {}", factory.name, factory.description);
+        for (name, description) in factory.properties {
+            println!("  {:10} {}", name, description);
+        }
+    }
+    println!("sources also take resample=linear|low|medium|high to run at their own rate");
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
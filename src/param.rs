@@ -0,0 +1,276 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// describes a parameter an element can change while running.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub unit: &'static str,
+}
+
+/// a parameter value shared between an element and any number of control
+/// threads. clones refer to the same value.
+#[derive(Clone, Debug)]
+pub struct Param {
+    info: Arc<ParamInfo>,
+    value: Arc<AtomicU64>,
+}
+impl Param {
+    pub fn new(name: &'static str, min: f64, max: f64, unit: &'static str, value: f64) -> Self {
+        let param = Self {
+            info: Arc::new(ParamInfo {
+                name: name,
+                min: min,
+                max: max,
+                unit: unit,
+            }),
+            value: Arc::new(AtomicU64::new(0)),
+        };
+        param.set(value);
+        param
+    }
+    pub fn info(&self) -> &ParamInfo {
+        &self.info
+    }
+    pub fn name(&self) -> &'static str {
+        self.info.name
+    }
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.value.load(Ordering::Relaxed))
+    }
+    /// values outside the range are clamped.
+    pub fn set(&self, value: f64) {
+        let value = self.clamp(value);
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+    pub fn clamp(&self, value: f64) -> f64 {
+        value.max(self.info.min).min(self.info.max)
+    }
+}
+
+/// elements with parameters that can be changed while the pipeline runs. take
+/// the handles before moving the element into a pipeline.
+pub trait Params {
+    fn params(&self) -> Vec<Param>;
+    fn param(&self, name: &str) -> Option<Param> {
+        self.params().into_iter().find(|param| param.name() == name)
+    }
+}
+
+/// how an automation lane moves from one breakpoint to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// constant ratio per frame. falls back to linear between values of
+    /// different sign or zero.
+    Exponential,
+    /// keeps the value until the next breakpoint.
+    Hold,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub frame: u64,
+    pub value: f64,
+    /// the curve towards the next breakpoint.
+    pub curve: Curve,
+}
+
+/// breakpoints for one parameter, in frames of the pipeline position (see
+/// `Context::get_position`). the value before the first and after the last
+/// breakpoint is held.
+#[derive(Clone, Debug, Default)]
+pub struct Automation {
+    points: Vec<Breakpoint>,
+    cursor: usize,
+}
+impl Automation {
+    pub fn new() -> Self {
+        Self {
+            points: Vec::new(),
+            cursor: 0,
+        }
+    }
+    /// a breakpoint at the same frame as an existing one replaces it.
+    pub fn point(mut self, frame: u64, value: f64, curve: Curve) -> Self {
+        let point = Breakpoint {
+            frame: frame,
+            value: value,
+            curve: curve,
+        };
+        match self.points.binary_search_by_key(&frame, |p| p.frame) {
+            Ok(i) => self.points[i] = point,
+            Err(i) => self.points.insert(i, point),
+        }
+        self.cursor = 0;
+        self
+    }
+    pub fn points(&self) -> &[Breakpoint] {
+        &self.points
+    }
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+    /// cheap when called with increasing frames, as elements do.
+    pub fn value_at(&mut self, frame: u64) -> Option<f64> {
+        let first = self.points.first()?;
+        if frame <= first.frame {
+            return Some(first.value);
+        }
+        if self.points[self.cursor].frame > frame {
+            self.cursor = 0;
+        }
+        while self.cursor + 1 < self.points.len() && self.points[self.cursor + 1].frame <= frame {
+            self.cursor += 1;
+        }
+        let from = self.points[self.cursor];
+        let to = match self.points.get(self.cursor + 1) {
+            Some(to) => to,
+            None => return Some(from.value),
+        };
+        let t = (frame - from.frame) as f64 / (to.frame - from.frame) as f64;
+        let value = match from.curve {
+            Curve::Hold => from.value,
+            Curve::Exponential if from.value * to.value > 0.0 => {
+                from.value * (to.value / from.value).powf(t)
+            }
+            Curve::Linear | Curve::Exponential => from.value + (to.value - from.value) * t,
+        };
+        Some(value)
+    }
+}
+
+// a parameter together with the automation lane driving it, if any.
+pub(crate) struct Lane {
+    pub param: Param,
+    pub automation: Option<Automation>,
+}
+impl Lane {
+    pub fn new(param: Param) -> Self {
+        Self {
+            param: param,
+            automation: None,
+        }
+    }
+    /// the automated value at `frame`, or the current value of the parameter.
+    pub fn value(&mut self, frame: u64) -> f64 {
+        match self.automated(frame) {
+            Some(value) => value,
+            None => self.param.get(),
+        }
+    }
+    /// the automated value at `frame`, also published to the parameter so that
+    /// control threads can follow it.
+    pub fn automated(&mut self, frame: u64) -> Option<f64> {
+        let value = self.automation.as_mut()?.value_at(frame)?;
+        let value = self.param.clamp(value);
+        self.param.set(value);
+        Some(value)
+    }
+}
+
+// the frame an element is at: the position the pipeline supplies in the context,
+// plus the frames the element has run through since that position last changed.
+pub(crate) struct Playhead {
+    position: u64,
+    offset: u64,
+}
+impl Playhead {
+    pub fn new() -> Self {
+        Self {
+            position: 0,
+            offset: 0,
+        }
+    }
+    /// returns the current frame and moves on by one.
+    pub fn advance(&mut self, position: u64) -> u64 {
+        if position != self.position {
+            self.position = position;
+            self.offset = 0;
+        }
+        let frame = self.position + self.offset;
+        self.offset += 1;
+        frame
+    }
+    /// moves on by `frames` without a change of position.
+    pub fn skip(&mut self, frames: u64) {
+        self.offset += frames;
+    }
+    pub fn reset(&mut self, position: u64) {
+        self.position = position;
+        self.offset = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_at_holds_outside_the_breakpoints() {
+        let mut automation = Automation::new();
+        assert_eq!(automation.value_at(0), None);
+        automation = automation
+            .point(100, 2.0, Curve::Linear)
+            .point(200, 4.0, Curve::Linear);
+        assert_eq!(automation.value_at(0), Some(2.0));
+        assert_eq!(automation.value_at(100), Some(2.0));
+        assert_eq!(automation.value_at(200), Some(4.0));
+        assert_eq!(automation.value_at(1000), Some(4.0));
+    }
+
+    #[test]
+    fn curves_interpolate() {
+        let mut automation = Automation::new()
+            .point(0, 1.0, Curve::Linear)
+            .point(100, 3.0, Curve::Exponential)
+            .point(200, 12.0, Curve::Hold)
+            .point(300, 0.0, Curve::Exponential)
+            .point(400, 8.0, Curve::Linear);
+        assert_eq!(automation.value_at(25), Some(1.5));
+        assert_eq!(automation.value_at(50), Some(2.0));
+        // a constant ratio per frame
+        assert!((automation.value_at(150).unwrap() - 6.0).abs() < 1e-12);
+        assert_eq!(automation.value_at(250), Some(12.0));
+        assert_eq!(automation.value_at(299), Some(12.0));
+        // towards zero the exponential curve is linear
+        assert_eq!(automation.value_at(350), Some(4.0));
+    }
+
+    #[test]
+    fn value_at_goes_back() {
+        let mut automation = Automation::new()
+            .point(0, 0.0, Curve::Linear)
+            .point(10, 10.0, Curve::Linear)
+            .point(20, 0.0, Curve::Linear);
+        assert_eq!(automation.value_at(15), Some(5.0));
+        assert_eq!(automation.value_at(5), Some(5.0));
+        assert_eq!(automation.value_at(12), Some(8.0));
+    }
+
+    #[test]
+    fn point_replaces_the_same_frame() {
+        let automation = Automation::new()
+            .point(10, 1.0, Curve::Linear)
+            .point(0, 0.0, Curve::Linear)
+            .point(10, 2.0, Curve::Hold);
+        let frames: Vec<_> = automation
+            .points()
+            .iter()
+            .map(|p| (p.frame, p.value))
+            .collect();
+        assert_eq!(frames, vec![(0, 0.0), (10, 2.0)]);
+    }
+
+    #[test]
+    fn playhead_follows_the_position() {
+        let mut playhead = Playhead::new();
+        assert_eq!(playhead.advance(0), 0);
+        assert_eq!(playhead.advance(0), 1);
+        assert_eq!(playhead.advance(64), 64);
+        assert_eq!(playhead.advance(64), 65);
+        assert_eq!(playhead.advance(1000), 1000);
+    }
+}
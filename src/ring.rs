@@ -0,0 +1,241 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// single producer, single consumer ring buffer. `head` and `tail` count the
+// frames popped and pushed modulo twice the capacity, so a full buffer
+// (`tail - head == capacity`) can be told from an empty one and any capacity
+// stays correct when the counters wrap.
+
+struct Shared<T> {
+    buf: Box<[UnsafeCell<T>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// slots between `head` and `tail` belong to the consumer, the others to the producer
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        self.distance(self.head.load(Ordering::Acquire), tail)
+    }
+    // frames from counter `from` up to counter `to`
+    fn distance(&self, from: usize, to: usize) -> usize {
+        let wrap = 2 * self.buf.len();
+        (to + wrap - from) % wrap
+    }
+    fn advance(&self, counter: usize, n: usize) -> usize {
+        (counter + n) % (2 * self.buf.len())
+    }
+    fn slot(&self, counter: usize, i: usize) -> &UnsafeCell<T> {
+        &self.buf[(counter + i) % self.buf.len()]
+    }
+}
+
+/// creates a lock-free ring buffer holding up to `capacity` frames.
+pub fn channel<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "ring buffer capacity must not be zero");
+    let shared = Arc::new(Shared {
+        buf: (0..capacity)
+            .map(|_| UnsafeCell::new(T::default()))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared: shared },
+    )
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T: Copy> Producer<T> {
+    /// gives the frame back if the buffer is full.
+    pub fn push(&mut self, t: T) -> Result<(), T> {
+        if self.push_slice(&[t]) == 0 {
+            return Err(t);
+        }
+        Ok(())
+    }
+    /// pushes as many frames as fit and returns how many were pushed.
+    pub fn push_slice(&mut self, ts: &[T]) -> usize {
+        let shared = &*self.shared;
+        let capacity = shared.buf.len();
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        let n = ts.len().min(capacity - shared.distance(head, tail));
+        for (i, &t) in ts[..n].iter().enumerate() {
+            unsafe { *shared.slot(tail, i).get() = t };
+        }
+        shared
+            .tail
+            .store(shared.advance(tail, n), Ordering::Release);
+        n
+    }
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn capacity(&self) -> usize {
+        self.shared.buf.len()
+    }
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T: Copy> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        if shared.tail.load(Ordering::Acquire) == head {
+            return None;
+        }
+        let t = unsafe { *shared.slot(head, 0).get() };
+        shared
+            .head
+            .store(shared.advance(head, 1), Ordering::Release);
+        Some(t)
+    }
+    /// pops as many frames as are available and returns how many were popped.
+    pub fn pop_slice(&mut self, ts: &mut [T]) -> usize {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        let n = ts.len().min(shared.distance(head, tail));
+        for (i, t) in ts[..n].iter_mut().enumerate() {
+            *t = unsafe { *shared.slot(head, i).get() };
+        }
+        shared
+            .head
+            .store(shared.advance(head, n), Ordering::Release);
+        n
+    }
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn capacity(&self) -> usize {
+        self.shared.buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn full_and_empty() {
+        let (mut producer, mut consumer) = channel::<u32>(3);
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.pop(), None);
+        for i in 0..3 {
+            producer.push(i).unwrap();
+        }
+        assert_eq!(producer.len(), 3);
+        assert_eq!(producer.push(3), Err(3));
+        assert_eq!(producer.push_slice(&[3, 4]), 0);
+        assert_eq!(consumer.pop(), Some(0));
+        assert_eq!(producer.push(3), Ok(()));
+        assert_eq!((consumer.len(), consumer.capacity()), (3, 3));
+        assert_eq!(
+            (consumer.pop(), consumer.pop(), consumer.pop()),
+            (Some(1), Some(2), Some(3))
+        );
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.is_empty());
+    }
+
+    #[test]
+    fn partial_slices() {
+        let (mut producer, mut consumer) = channel::<u32>(4);
+        assert_eq!(producer.push_slice(&[0, 1, 2, 3, 4, 5]), 4);
+        let mut out = [9; 3];
+        assert_eq!(consumer.pop_slice(&mut out), 3);
+        assert_eq!(out, [0, 1, 2]);
+        assert_eq!(producer.push_slice(&[6, 7]), 2);
+        let mut out = [9; 5];
+        assert_eq!(consumer.pop_slice(&mut out), 3);
+        assert_eq!(out, [3, 6, 7, 9, 9]);
+        assert_eq!(consumer.pop_slice(&mut out), 0);
+    }
+
+    #[test]
+    fn slices_wrap_around() {
+        let (mut producer, mut consumer) = channel::<usize>(5);
+        let mut next = 0;
+        let mut expected = 0;
+        for round in 0..20 {
+            // lengths that keep moving the start of each slice around the buffer
+            let input: Vec<usize> = (next..next + 2 + round % 3).collect();
+            next += producer.push_slice(&input);
+            let mut out = vec![0; 1 + round % 4];
+            let n = consumer.pop_slice(&mut out);
+            for &x in &out[..n] {
+                assert_eq!(x, expected);
+                expected += 1;
+            }
+            assert_eq!(consumer.len(), next - expected);
+        }
+    }
+
+    #[test]
+    fn counters_wrap_around() {
+        let (mut producer, mut consumer) = channel::<u32>(3);
+        // counters run modulo 6, so this batch wraps them back to the start
+        producer.shared.head.store(4, Ordering::SeqCst);
+        producer.shared.tail.store(4, Ordering::SeqCst);
+        for round in 0..4 {
+            let input = [3 * round, 3 * round + 1, 3 * round + 2, 99];
+            assert_eq!(producer.push_slice(&input), 3);
+            assert_eq!(consumer.len(), 3);
+            assert_eq!(producer.push(99), Err(99));
+            let mut out = [0; 4];
+            assert_eq!(consumer.pop_slice(&mut out[..2]), 2);
+            assert_eq!(consumer.pop(), Some(3 * round + 2));
+            assert_eq!(out[..2], input[..2]);
+            assert!(consumer.is_empty());
+        }
+    }
+
+    #[test]
+    fn frames_cross_threads_in_order() {
+        const FRAMES: u32 = 20_000;
+        let (mut producer, mut consumer) = channel::<u32>(64);
+        let writer = thread::spawn(move || {
+            let mut next = 0;
+            while next < FRAMES {
+                let input: Vec<u32> = (next..(next + 7).min(FRAMES)).collect();
+                match producer.push_slice(&input) {
+                    0 => thread::yield_now(),
+                    n => next += n as u32,
+                }
+            }
+        });
+        let mut expected = 0;
+        let mut out = [0; 5];
+        while expected < FRAMES {
+            let n = consumer.pop_slice(&mut out);
+            if n == 0 {
+                thread::yield_now();
+            }
+            for &x in &out[..n] {
+                assert_eq!(x, expected);
+                expected += 1;
+            }
+        }
+        writer.join().unwrap();
+        assert!(consumer.is_empty());
+    }
+}
@@ -1,6 +1,8 @@
-use std::ops::{Add, Div, Mul, Sub};
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Stereo<T: SampleType> {
     pub l: T,
     pub r: T,
@@ -18,17 +20,7 @@ impl<T1: SampleType> Stereo<T1> {
     }
 }
 
-impl<T: SampleType> Add for Stereo<T> {
-    type Output = Self;
-    fn add(self, rhs: Self) -> Self {
-        Stereo {
-            l: self.l + rhs.l,
-            r: self.r + rhs.r,
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Mono<T: SampleType>(pub T);
 
 impl<T1: SampleType> Mono<T1> {
@@ -40,22 +32,251 @@ impl<T1: SampleType> Mono<T1> {
     }
 }
 
-impl<T: SampleType> Add for Mono<T> {
-    type Output = Self;
-    fn add(self, rhs: Self) -> Self {
-        Mono(self.0 + rhs.0)
+/// four channel frame in WAV channel order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quad<T: SampleType> {
+    pub fl: T,
+    pub fr: T,
+    pub bl: T,
+    pub br: T,
+}
+
+impl<T1: SampleType> Quad<T1> {
+    pub fn new(s: T1) -> Self {
+        Quad {
+            fl: s,
+            fr: s,
+            bl: s,
+            br: s,
+        }
+    }
+    pub fn map<T2: SampleType, F: Fn(T1) -> T2>(self, f: F) -> Quad<T2> {
+        Quad {
+            fl: f(self.fl),
+            fr: f(self.fr),
+            bl: f(self.bl),
+            br: f(self.br),
+        }
+    }
+}
+
+/// 5.1 frame in WAV channel order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Surround51<T: SampleType> {
+    pub fl: T,
+    pub fr: T,
+    pub c: T,
+    pub lfe: T,
+    pub bl: T,
+    pub br: T,
+}
+
+impl<T1: SampleType> Surround51<T1> {
+    pub fn new(s: T1) -> Self {
+        Surround51 {
+            fl: s,
+            fr: s,
+            c: s,
+            lfe: s,
+            bl: s,
+            br: s,
+        }
+    }
+    pub fn map<T2: SampleType, F: Fn(T1) -> T2>(self, f: F) -> Surround51<T2> {
+        Surround51 {
+            fl: f(self.fl),
+            fr: f(self.fr),
+            c: f(self.c),
+            lfe: f(self.lfe),
+            bl: f(self.bl),
+            br: f(self.br),
+        }
+    }
+}
+
+/// 7.1 frame in WAV channel order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Surround71<T: SampleType> {
+    pub fl: T,
+    pub fr: T,
+    pub c: T,
+    pub lfe: T,
+    pub bl: T,
+    pub br: T,
+    pub sl: T,
+    pub sr: T,
+}
+
+impl<T1: SampleType> Surround71<T1> {
+    pub fn new(s: T1) -> Self {
+        Surround71 {
+            fl: s,
+            fr: s,
+            c: s,
+            lfe: s,
+            bl: s,
+            br: s,
+            sl: s,
+            sr: s,
+        }
     }
+    pub fn map<T2: SampleType, F: Fn(T1) -> T2>(self, f: F) -> Surround71<T2> {
+        Surround71 {
+            fl: f(self.fl),
+            fr: f(self.fr),
+            c: f(self.c),
+            lfe: f(self.lfe),
+            bl: f(self.bl),
+            br: f(self.br),
+            sl: f(self.sl),
+            sr: f(self.sr),
+        }
+    }
+}
+
+// channel by channel arithmetic, for every frame type. `$index` is the position of
+// the channel in the array form of the frame. adding and subtracting need members
+// whose silence is zero, which the offset unsigned types are not. multiplying and
+// dividing need floats, since a product of full scale integers overflows.
+macro_rules! frame_ops {
+    ($frame:ident { $($field:tt: $index:expr),+ }, $n:expr) => {
+        impl<T1: SampleType> $frame<T1> {
+            pub fn zip_map<T2, T3, F>(self, other: $frame<T2>, f: F) -> $frame<T3>
+            where
+                T2: SampleType,
+                T3: SampleType,
+                F: Fn(T1, T2) -> T3,
+            {
+                $frame { $($field: f(self.$field, other.$field)),+ }
+            }
+        }
+        impl<T: SignedSampleType> Add for $frame<T> {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                self.zip_map(rhs, |a, b| a + b)
+            }
+        }
+        impl<T: SignedSampleType> Sub for $frame<T> {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                self.zip_map(rhs, |a, b| a - b)
+            }
+        }
+        impl<T: FloatSampleType> Mul for $frame<T> {
+            type Output = Self;
+            fn mul(self, rhs: Self) -> Self {
+                self.zip_map(rhs, |a, b| a * b)
+            }
+        }
+        impl<T: FloatSampleType> Div for $frame<T> {
+            type Output = Self;
+            fn div(self, rhs: Self) -> Self {
+                self.zip_map(rhs, |a, b| a / b)
+            }
+        }
+        impl<T: FloatSampleType> Mul<T> for $frame<T> {
+            type Output = Self;
+            fn mul(self, rhs: T) -> Self {
+                self.map(|a| a * rhs)
+            }
+        }
+        impl<T: FloatSampleType> Div<T> for $frame<T> {
+            type Output = Self;
+            fn div(self, rhs: T) -> Self {
+                self.map(|a| a / rhs)
+            }
+        }
+        impl<T: SampleType + Neg<Output = T>> Neg for $frame<T> {
+            type Output = Self;
+            fn neg(self) -> Self {
+                self.map(|a| -a)
+            }
+        }
+        impl<T: SignedSampleType> AddAssign for $frame<T> {
+            fn add_assign(&mut self, rhs: Self) {
+                *self = *self + rhs;
+            }
+        }
+        impl<T: SignedSampleType> SubAssign for $frame<T> {
+            fn sub_assign(&mut self, rhs: Self) {
+                *self = *self - rhs;
+            }
+        }
+        impl<T: FloatSampleType> MulAssign for $frame<T> {
+            fn mul_assign(&mut self, rhs: Self) {
+                *self = *self * rhs;
+            }
+        }
+        impl<T: FloatSampleType> DivAssign for $frame<T> {
+            fn div_assign(&mut self, rhs: Self) {
+                *self = *self / rhs;
+            }
+        }
+        impl<T: FloatSampleType> MulAssign<T> for $frame<T> {
+            fn mul_assign(&mut self, rhs: T) {
+                *self = *self * rhs;
+            }
+        }
+        impl<T: FloatSampleType> DivAssign<T> for $frame<T> {
+            fn div_assign(&mut self, rhs: T) {
+                *self = *self / rhs;
+            }
+        }
+        impl<T: SignedSampleType> Sum for $frame<T> {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::default(), Add::add)
+            }
+        }
+        impl<'a, T: SignedSampleType> Sum<&'a $frame<T>> for $frame<T> {
+            fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+                iter.fold(Self::default(), |acc, s| acc + *s)
+            }
+        }
+        /// silence, every channel at `SampleType::REF_LEVEL`.
+        impl<T: SampleType> Default for $frame<T> {
+            fn default() -> Self {
+                $frame { $($field: T::REF_LEVEL),+ }
+            }
+        }
+        impl<T: SampleType> From<[T; $n]> for $frame<T> {
+            fn from(array: [T; $n]) -> Self {
+                $frame { $($field: array[$index]),+ }
+            }
+        }
+        impl<T: SampleType> From<$frame<T>> for [T; $n] {
+            fn from(s: $frame<T>) -> Self {
+                [$(s.$field),+]
+            }
+        }
+    };
 }
 
+frame_ops! { Stereo { l: 0, r: 1 }, 2 }
+frame_ops! { Mono { 0: 0 }, 1 }
+frame_ops! { Quad { fl: 0, fr: 1, bl: 2, br: 3 }, 4 }
+frame_ops! { Surround51 { fl: 0, fr: 1, c: 2, lfe: 3, bl: 4, br: 5 }, 6 }
+frame_ops! { Surround71 { fl: 0, fr: 1, c: 2, lfe: 3, bl: 4, br: 5, sl: 6, sr: 7 }, 8 }
+
 pub trait Sample: Copy {
     type Member: SampleType;
+    const CHANNELS: usize;
+    /// every channel at `SampleType::REF_LEVEL`. zero is full scale negative for
+    /// the offset unsigned types, so use this rather than zeroed frames.
+    const SILENCE: Self;
     fn to_stereo(self) -> Stereo<Self::Member>;
     fn to_mono(self) -> Mono<Self::Member>;
     fn from_raw(raw: &[Self::Member]) -> Option<Self>;
+    /// writes the members in channel order. `raw` must hold `CHANNELS` members.
+    fn to_raw(self, raw: &mut [Self::Member]);
 }
 
 impl<T: SampleType> Sample for Stereo<T> {
     type Member = T;
+    const CHANNELS: usize = 2;
+    const SILENCE: Self = Stereo {
+        l: T::REF_LEVEL,
+        r: T::REF_LEVEL,
+    };
     fn to_stereo(self) -> Stereo<Self::Member> {
         self
     }
@@ -71,10 +292,16 @@ impl<T: SampleType> Sample for Stereo<T> {
             r: raw[1],
         })
     }
+    fn to_raw(self, raw: &mut [Self::Member]) {
+        raw[0] = self.l;
+        raw[1] = self.r;
+    }
 }
 
 impl<T: SampleType> Sample for Mono<T> {
     type Member = T;
+    const CHANNELS: usize = 1;
+    const SILENCE: Self = Mono(T::REF_LEVEL);
     fn to_stereo(self) -> Stereo<Self::Member> {
         Stereo {
             l: self.0,
@@ -90,6 +317,192 @@ impl<T: SampleType> Sample for Mono<T> {
         }
         Some(Mono(raw[0]))
     }
+    fn to_raw(self, raw: &mut [Self::Member]) {
+        raw[0] = self.0;
+    }
+}
+
+impl<T> Sample for Quad<T>
+where
+    T: SampleType + IntoSampleType<f64> + FromSampleType<f64>,
+{
+    type Member = T;
+    const CHANNELS: usize = 4;
+    const SILENCE: Self = Quad {
+        fl: T::REF_LEVEL,
+        fr: T::REF_LEVEL,
+        bl: T::REF_LEVEL,
+        br: T::REF_LEVEL,
+    };
+    fn to_stereo(self) -> Stereo<Self::Member> {
+        Stereo {
+            l: fold(self.fl, &[self.bl]),
+            r: fold(self.fr, &[self.br]),
+        }
+    }
+    fn to_mono(self) -> Mono<Self::Member> {
+        self.to_stereo().to_mono()
+    }
+    fn from_raw(raw: &[Self::Member]) -> Option<Self> {
+        if raw.len() != 4 {
+            return None;
+        }
+        Some(Quad {
+            fl: raw[0],
+            fr: raw[1],
+            bl: raw[2],
+            br: raw[3],
+        })
+    }
+    fn to_raw(self, raw: &mut [Self::Member]) {
+        raw[0] = self.fl;
+        raw[1] = self.fr;
+        raw[2] = self.bl;
+        raw[3] = self.br;
+    }
+}
+
+impl<T> Sample for Surround51<T>
+where
+    T: SampleType + IntoSampleType<f64> + FromSampleType<f64>,
+{
+    type Member = T;
+    const CHANNELS: usize = 6;
+    const SILENCE: Self = Surround51 {
+        fl: T::REF_LEVEL,
+        fr: T::REF_LEVEL,
+        c: T::REF_LEVEL,
+        lfe: T::REF_LEVEL,
+        bl: T::REF_LEVEL,
+        br: T::REF_LEVEL,
+    };
+    fn to_stereo(self) -> Stereo<Self::Member> {
+        Stereo {
+            l: fold(self.fl, &[self.c, self.bl]),
+            r: fold(self.fr, &[self.c, self.br]),
+        }
+    }
+    fn to_mono(self) -> Mono<Self::Member> {
+        self.to_stereo().to_mono()
+    }
+    fn from_raw(raw: &[Self::Member]) -> Option<Self> {
+        if raw.len() != 6 {
+            return None;
+        }
+        Some(Surround51 {
+            fl: raw[0],
+            fr: raw[1],
+            c: raw[2],
+            lfe: raw[3],
+            bl: raw[4],
+            br: raw[5],
+        })
+    }
+    fn to_raw(self, raw: &mut [Self::Member]) {
+        raw[0] = self.fl;
+        raw[1] = self.fr;
+        raw[2] = self.c;
+        raw[3] = self.lfe;
+        raw[4] = self.bl;
+        raw[5] = self.br;
+    }
+}
+
+impl<T> Sample for Surround71<T>
+where
+    T: SampleType + IntoSampleType<f64> + FromSampleType<f64>,
+{
+    type Member = T;
+    const CHANNELS: usize = 8;
+    const SILENCE: Self = Surround71 {
+        fl: T::REF_LEVEL,
+        fr: T::REF_LEVEL,
+        c: T::REF_LEVEL,
+        lfe: T::REF_LEVEL,
+        bl: T::REF_LEVEL,
+        br: T::REF_LEVEL,
+        sl: T::REF_LEVEL,
+        sr: T::REF_LEVEL,
+    };
+    fn to_stereo(self) -> Stereo<Self::Member> {
+        Stereo {
+            l: fold(self.fl, &[self.c, self.bl, self.sl]),
+            r: fold(self.fr, &[self.c, self.br, self.sr]),
+        }
+    }
+    fn to_mono(self) -> Mono<Self::Member> {
+        self.to_stereo().to_mono()
+    }
+    fn from_raw(raw: &[Self::Member]) -> Option<Self> {
+        if raw.len() != 8 {
+            return None;
+        }
+        Some(Surround71 {
+            fl: raw[0],
+            fr: raw[1],
+            c: raw[2],
+            lfe: raw[3],
+            bl: raw[4],
+            br: raw[5],
+            sl: raw[6],
+            sr: raw[7],
+        })
+    }
+    fn to_raw(self, raw: &mut [Self::Member]) {
+        raw[0] = self.fl;
+        raw[1] = self.fr;
+        raw[2] = self.c;
+        raw[3] = self.lfe;
+        raw[4] = self.bl;
+        raw[5] = self.br;
+        raw[6] = self.sl;
+        raw[7] = self.sr;
+    }
+}
+
+/// frame of `N` channels without a named layout. `to_stereo` keeps the first two
+/// channels, or duplicates a single one.
+impl<T: SampleType, const N: usize> Sample for [T; N] {
+    type Member = T;
+    const CHANNELS: usize = N;
+    const SILENCE: Self = [T::REF_LEVEL; N];
+    fn to_stereo(self) -> Stereo<Self::Member> {
+        match N {
+            0 => Stereo::new(T::REF_LEVEL),
+            1 => Stereo::new(self[0]),
+            _ => Stereo {
+                l: self[0],
+                r: self[1],
+            },
+        }
+    }
+    fn to_mono(self) -> Mono<Self::Member> {
+        self.to_stereo().to_mono()
+    }
+    fn from_raw(raw: &[Self::Member]) -> Option<Self> {
+        if raw.len() != N {
+            return None;
+        }
+        let mut s = [T::REF_LEVEL; N];
+        s.copy_from_slice(raw);
+        Some(s)
+    }
+    fn to_raw(self, raw: &mut [Self::Member]) {
+        raw[..N].copy_from_slice(&self);
+    }
+}
+
+// adds the other channels at -3 dB, as in the ITU-R BS.775 downmix
+fn fold<T>(s: T, others: &[T]) -> T
+where
+    T: IntoSampleType<f64> + FromSampleType<f64>,
+{
+    let s: f64 = s.into_sampletype();
+    let s = others.iter().fold(s, |acc, &other| {
+        let other: f64 = other.into_sampletype();
+        acc + other * FRAC_1_SQRT_2
+    });
+    T::from_sampletype(s)
 }
 
 pub trait FromSample<T> {
@@ -110,46 +523,378 @@ where
     }
 }
 
-impl<S1, S2> FromSample<Stereo<S1>> for Stereo<S2>
+impl<S, S2> FromSample<S> for Stereo<S2>
+where
+    S: Sample,
+    S::Member: IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: S) -> Stereo<S2> {
+        t.to_stereo().map(|s| s.into_sampletype())
+    }
+}
+impl<S, S2> FromSample<S> for Mono<S2>
+where
+    S: Sample,
+    S::Member: IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: S) -> Mono<S2> {
+        t.to_mono().map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Quad<S1>> for Quad<S2>
 where
     S1: SampleType + IntoSampleType<S2>,
     S2: SampleType,
 {
-    fn from_sample(t: Stereo<S1>) -> Stereo<S2> {
-        Stereo {
-            l: t.l.into_sampletype(),
-            r: t.r.into_sampletype(),
+    fn from_sample(t: Quad<S1>) -> Quad<S2> {
+        t.map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Mono<S1>> for Quad<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Mono<S1>) -> Quad<S2> {
+        let z = S1::REF_LEVEL;
+        Quad {
+            fl: t.0,
+            fr: t.0,
+            bl: z,
+            br: z,
         }
+        .map(|s| s.into_sampletype())
     }
 }
-impl<S1, S2> FromSample<Stereo<S1>> for Mono<S2>
+impl<S1, S2> FromSample<Stereo<S1>> for Quad<S2>
 where
     S1: SampleType + IntoSampleType<S2>,
     S2: SampleType,
 {
-    fn from_sample(t: Stereo<S1>) -> Mono<S2> {
-        t.to_mono().into_sample()
+    fn from_sample(t: Stereo<S1>) -> Quad<S2> {
+        let z = S1::REF_LEVEL;
+        Quad {
+            fl: t.l,
+            fr: t.r,
+            bl: z,
+            br: z,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Surround51<S1>> for Quad<S2>
+where
+    S1: SampleType + IntoSampleType<S2> + IntoSampleType<f64> + FromSampleType<f64>,
+    S2: SampleType,
+{
+    fn from_sample(t: Surround51<S1>) -> Quad<S2> {
+        Quad {
+            fl: fold(t.fl, &[t.c]),
+            fr: fold(t.fr, &[t.c]),
+            bl: t.bl,
+            br: t.br,
+        }
+        .map(|s| s.into_sampletype())
     }
 }
-impl<S1, S2> FromSample<Mono<S1>> for Mono<S2>
+impl<S1, S2> FromSample<Surround71<S1>> for Quad<S2>
+where
+    S1: SampleType + IntoSampleType<S2> + IntoSampleType<f64> + FromSampleType<f64>,
+    S2: SampleType,
+{
+    fn from_sample(t: Surround71<S1>) -> Quad<S2> {
+        Quad {
+            fl: fold(t.fl, &[t.c]),
+            fr: fold(t.fr, &[t.c]),
+            bl: fold(t.bl, &[t.sl]),
+            br: fold(t.br, &[t.sr]),
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Surround51<S1>> for Surround51<S2>
 where
     S1: SampleType + IntoSampleType<S2>,
     S2: SampleType,
 {
-    fn from_sample(t: Mono<S1>) -> Mono<S2> {
-        Mono(t.0.into_sampletype())
+    fn from_sample(t: Surround51<S1>) -> Surround51<S2> {
+        t.map(|s| s.into_sampletype())
     }
 }
-impl<S1, S2> FromSample<Mono<S1>> for Stereo<S2>
+impl<S1, S2> FromSample<Mono<S1>> for Surround51<S2>
 where
     S1: SampleType + IntoSampleType<S2>,
     S2: SampleType,
 {
-    fn from_sample(t: Mono<S1>) -> Stereo<S2> {
-        t.to_stereo().into_sample()
+    fn from_sample(t: Mono<S1>) -> Surround51<S2> {
+        let z = S1::REF_LEVEL;
+        Surround51 {
+            fl: z,
+            fr: z,
+            c: t.0,
+            lfe: z,
+            bl: z,
+            br: z,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Stereo<S1>> for Surround51<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Stereo<S1>) -> Surround51<S2> {
+        let z = S1::REF_LEVEL;
+        Surround51 {
+            fl: t.l,
+            fr: t.r,
+            c: z,
+            lfe: z,
+            bl: z,
+            br: z,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Quad<S1>> for Surround51<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Quad<S1>) -> Surround51<S2> {
+        let z = S1::REF_LEVEL;
+        Surround51 {
+            fl: t.fl,
+            fr: t.fr,
+            c: z,
+            lfe: z,
+            bl: t.bl,
+            br: t.br,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Surround71<S1>> for Surround51<S2>
+where
+    S1: SampleType + IntoSampleType<S2> + IntoSampleType<f64> + FromSampleType<f64>,
+    S2: SampleType,
+{
+    fn from_sample(t: Surround71<S1>) -> Surround51<S2> {
+        Surround51 {
+            fl: t.fl,
+            fr: t.fr,
+            c: t.c,
+            lfe: t.lfe,
+            bl: fold(t.bl, &[t.sl]),
+            br: fold(t.br, &[t.sr]),
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Surround71<S1>> for Surround71<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Surround71<S1>) -> Surround71<S2> {
+        t.map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Mono<S1>> for Surround71<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Mono<S1>) -> Surround71<S2> {
+        let z = S1::REF_LEVEL;
+        Surround71 {
+            fl: z,
+            fr: z,
+            c: t.0,
+            lfe: z,
+            bl: z,
+            br: z,
+            sl: z,
+            sr: z,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Stereo<S1>> for Surround71<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Stereo<S1>) -> Surround71<S2> {
+        let z = S1::REF_LEVEL;
+        Surround71 {
+            fl: t.l,
+            fr: t.r,
+            c: z,
+            lfe: z,
+            bl: z,
+            br: z,
+            sl: z,
+            sr: z,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Quad<S1>> for Surround71<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Quad<S1>) -> Surround71<S2> {
+        let z = S1::REF_LEVEL;
+        Surround71 {
+            fl: t.fl,
+            fr: t.fr,
+            c: z,
+            lfe: z,
+            bl: t.bl,
+            br: t.br,
+            sl: z,
+            sr: z,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2> FromSample<Surround51<S1>> for Surround71<S2>
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Surround51<S1>) -> Surround71<S2> {
+        let z = S1::REF_LEVEL;
+        Surround71 {
+            fl: t.fl,
+            fr: t.fr,
+            c: t.c,
+            lfe: t.lfe,
+            bl: t.bl,
+            br: t.br,
+            sl: z,
+            sr: z,
+        }
+        .map(|s| s.into_sampletype())
+    }
+}
+impl<S1, S2, const N: usize> FromSample<[S1; N]> for [S2; N]
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: [S1; N]) -> [S2; N] {
+        let mut s = [S2::REF_LEVEL; N];
+        for (s, t) in s.iter_mut().zip(t.iter()) {
+            *s = t.into_sampletype();
+        }
+        s
     }
 }
 
+impl<S1, S2, const N: usize> FromSample<Mono<S1>> for [S2; N]
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Mono<S1>) -> [S2; N] {
+        from_channels(&[t.0])
+    }
+}
+impl<S1, S2, const N: usize> FromSample<Stereo<S1>> for [S2; N]
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Stereo<S1>) -> [S2; N] {
+        from_channels(&[t.l, t.r])
+    }
+}
+impl<S1, S2, const N: usize> FromSample<Quad<S1>> for [S2; N]
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Quad<S1>) -> [S2; N] {
+        from_channels(&[t.fl, t.fr, t.bl, t.br])
+    }
+}
+impl<S1, S2, const N: usize> FromSample<Surround51<S1>> for [S2; N]
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Surround51<S1>) -> [S2; N] {
+        from_channels(&[t.fl, t.fr, t.c, t.lfe, t.bl, t.br])
+    }
+}
+impl<S1, S2, const N: usize> FromSample<Surround71<S1>> for [S2; N]
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    fn from_sample(t: Surround71<S1>) -> [S2; N] {
+        from_channels(&[t.fl, t.fr, t.c, t.lfe, t.bl, t.br, t.sl, t.sr])
+    }
+}
+
+// arrays have no layout of their own, so named layouts keep their channels in
+// order and extra channels are dropped or filled with the reference level
+fn from_channels<S1, S2, const N: usize>(t: &[S1]) -> [S2; N]
+where
+    S1: SampleType + IntoSampleType<S2>,
+    S2: SampleType,
+{
+    let mut s = [S2::REF_LEVEL; N];
+    for (s, t) in s.iter_mut().zip(t.iter()) {
+        *s = t.into_sampletype();
+    }
+    s
+}
+
+/// frames that can be converted from every named layout, so that streams with
+/// 1, 2, 4, 6 or 8 channels can be read as them.
+pub trait FromLayout:
+    Sample
+    + FromSample<Mono<f64>>
+    + FromSample<Stereo<f64>>
+    + FromSample<Quad<f64>>
+    + FromSample<Surround51<f64>>
+    + FromSample<Surround71<f64>>
+{
+    /// converts the channels of a frame in the named layout with that many
+    /// channels. `None` if there is no such layout.
+    fn from_layout(raw: &[f64]) -> Option<Self> {
+        Some(match raw.len() {
+            1 => Mono::from_raw(raw)?.into_sample(),
+            2 => Stereo::from_raw(raw)?.into_sample(),
+            4 => Quad::from_raw(raw)?.into_sample(),
+            6 => Surround51::from_raw(raw)?.into_sample(),
+            8 => Surround71::from_raw(raw)?.into_sample(),
+            _ => return None,
+        })
+    }
+}
+impl<T> FromLayout for T where
+    T: Sample
+        + FromSample<Mono<f64>>
+        + FromSample<Stereo<f64>>
+        + FromSample<Quad<f64>>
+        + FromSample<Surround51<f64>>
+        + FromSample<Surround71<f64>>
+{
+}
+
+/// whether `FromLayout::from_layout` accepts frames with `channels` channels.
+pub fn has_layout(channels: usize) -> bool {
+    matches!(channels, 1 | 2 | 4 | 6 | 8)
+}
+
 pub trait SampleType:
     Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>
 {
@@ -158,17 +903,49 @@ pub trait SampleType:
     const REF_LEVEL: Self;
     fn from_i32(i: i32) -> Self;
 }
+impl SampleType for i8 {
+    const MIN_LEVEL: Self = i8::MIN;
+    const MAX_LEVEL: Self = i8::MAX;
+    const REF_LEVEL: Self = 0;
+    fn from_i32(i: i32) -> Self {
+        i as Self
+    }
+}
+impl SampleType for u8 {
+    const MIN_LEVEL: Self = u8::MIN;
+    const MAX_LEVEL: Self = u8::MAX;
+    const REF_LEVEL: Self = 0x80;
+    fn from_i32(i: i32) -> Self {
+        i as Self
+    }
+}
 impl SampleType for i16 {
-    const MIN_LEVEL: Self = ::std::i16::MIN;
-    const MAX_LEVEL: Self = ::std::i16::MAX;
+    const MIN_LEVEL: Self = i16::MIN;
+    const MAX_LEVEL: Self = i16::MAX;
     const REF_LEVEL: Self = 0;
     fn from_i32(i: i32) -> Self {
         i as Self
     }
 }
+impl SampleType for u16 {
+    const MIN_LEVEL: Self = u16::MIN;
+    const MAX_LEVEL: Self = u16::MAX;
+    const REF_LEVEL: Self = 0x8000;
+    fn from_i32(i: i32) -> Self {
+        i as Self
+    }
+}
+impl SampleType for I24 {
+    const MIN_LEVEL: Self = I24::MIN;
+    const MAX_LEVEL: Self = I24::MAX;
+    const REF_LEVEL: Self = I24(0);
+    fn from_i32(i: i32) -> Self {
+        I24::wrapping_from(i)
+    }
+}
 impl SampleType for i32 {
-    const MIN_LEVEL: Self = ::std::i32::MIN;
-    const MAX_LEVEL: Self = ::std::i32::MAX;
+    const MIN_LEVEL: Self = i32::MIN;
+    const MAX_LEVEL: Self = i32::MAX;
     const REF_LEVEL: Self = 0;
     fn from_i32(i: i32) -> Self {
         i as Self
@@ -191,6 +968,88 @@ impl SampleType for f64 {
     }
 }
 
+/// sample types whose `REF_LEVEL` is zero, so that frames of them add and subtract
+/// channel by channel.
+pub trait SignedSampleType: SampleType {}
+impl SignedSampleType for i8 {}
+impl SignedSampleType for i16 {}
+impl SignedSampleType for I24 {}
+impl SignedSampleType for i32 {}
+impl SignedSampleType for f32 {}
+impl SignedSampleType for f64 {}
+
+/// float sample types, so that frames of them also multiply and divide channel by
+/// channel.
+pub trait FloatSampleType: SignedSampleType {}
+impl FloatSampleType for f32 {}
+impl FloatSampleType for f64 {}
+
+/// a signed 24-bit sample, as packed in 24-bit PCM. arithmetic wraps around
+/// within 24 bits.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct I24(i32);
+impl I24 {
+    pub const MIN: I24 = I24(-0x80_0000);
+    pub const MAX: I24 = I24(0x7f_ffff);
+    /// `None` if `i` does not fit in 24 bits.
+    pub fn new(i: i32) -> Option<Self> {
+        if (I24::MIN.0..=I24::MAX.0).contains(&i) {
+            Some(I24(i))
+        } else {
+            None
+        }
+    }
+    /// keeps the low 24 bits of `i`.
+    pub fn wrapping_from(i: i32) -> Self {
+        I24((i << 8) >> 8)
+    }
+    pub fn get(self) -> i32 {
+        self.0
+    }
+    pub fn from_le_bytes(bytes: [u8; 3]) -> Self {
+        I24(i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
+    }
+    pub fn to_le_bytes(self) -> [u8; 3] {
+        let b = self.0.to_le_bytes();
+        [b[0], b[1], b[2]]
+    }
+}
+impl From<I24> for i32 {
+    fn from(i: I24) -> i32 {
+        i.0
+    }
+}
+impl Add for I24 {
+    type Output = I24;
+    fn add(self, other: I24) -> I24 {
+        I24::wrapping_from(self.0.wrapping_add(other.0))
+    }
+}
+impl Sub for I24 {
+    type Output = I24;
+    fn sub(self, other: I24) -> I24 {
+        I24::wrapping_from(self.0.wrapping_sub(other.0))
+    }
+}
+impl Mul for I24 {
+    type Output = I24;
+    fn mul(self, other: I24) -> I24 {
+        I24::wrapping_from(self.0.wrapping_mul(other.0))
+    }
+}
+impl Div for I24 {
+    type Output = I24;
+    fn div(self, other: I24) -> I24 {
+        I24::wrapping_from(self.0 / other.0)
+    }
+}
+impl Neg for I24 {
+    type Output = I24;
+    fn neg(self) -> I24 {
+        I24::wrapping_from(-self.0)
+    }
+}
+
 pub trait FromSampleType<T: SampleType>: SampleType {
     fn from_sampletype(_: T) -> Self;
 }
@@ -216,63 +1075,308 @@ impl<T: SampleType> FromSampleType<T> for T {
     }
 }
 
-impl FromSampleType<i16> for i32 {
-    fn from_sampletype(t: i16) -> i32 {
-        t as i32 * (i32::MAX_LEVEL / i16::MAX_LEVEL as i32)
+// integers are scaled by 2^(bits - 1) around `REF_LEVEL`, so that every integer
+// sample survives a round trip through floats or wider integers and widening is
+// a plain shift. narrowing and floats are rounded to the nearest step and
+// clipped; NaN becomes silence.
+trait Pcm: SampleType {
+    const BITS: u32;
+    /// the distance from `REF_LEVEL`.
+    fn to_signed(self) -> i64;
+    /// `s` must be in range.
+    fn from_signed(s: i64) -> Self;
+    fn scale() -> f64 {
+        (1u64 << (Self::BITS - 1)) as f64
     }
-}
-impl FromSampleType<i16> for f32 {
-    fn from_sampletype(t: i16) -> f32 {
-        t as f32 / i16::MAX_LEVEL as f32
+    fn quantize(t: f64) -> Self {
+        let scale = Self::scale();
+        Self::from_signed((t * scale).round().clamp(-scale, scale - 1.0) as i64)
     }
 }
-impl FromSampleType<i16> for f64 {
-    fn from_sampletype(t: i16) -> f64 {
-        t as f64 / i16::MAX_LEVEL as f64
-    }
+macro_rules! pcm {
+    ($t:ty, $bits:expr) => {
+        impl Pcm for $t {
+            const BITS: u32 = $bits;
+            fn to_signed(self) -> i64 {
+                i64::from(self) - i64::from(Self::REF_LEVEL)
+            }
+            fn from_signed(s: i64) -> Self {
+                (s + i64::from(Self::REF_LEVEL)) as $t
+            }
+        }
+    };
 }
-impl FromSampleType<i32> for i16 {
-    fn from_sampletype(t: i32) -> i16 {
-        (t / (i32::MAX_LEVEL / i16::MAX_LEVEL as i32)) as i16
+pcm!(i8, 8);
+pcm!(u8, 8);
+pcm!(i16, 16);
+pcm!(u16, 16);
+pcm!(i32, 32);
+impl Pcm for I24 {
+    const BITS: u32 = 24;
+    fn to_signed(self) -> i64 {
+        i64::from(self.0)
     }
-}
-impl FromSampleType<i32> for f32 {
-    fn from_sampletype(t: i32) -> f32 {
-        t as f32 / i32::MAX_LEVEL as f32
+    fn from_signed(s: i64) -> Self {
+        I24(s as i32)
     }
 }
-impl FromSampleType<i32> for f64 {
-    fn from_sampletype(t: i32) -> f64 {
-        t as f64 / i32::MAX_LEVEL as f64
-    }
+
+fn rescale<S: Pcm, D: Pcm>(t: S) -> D {
+    let s = t.to_signed();
+    let d = if D::BITS >= S::BITS {
+        s << (D::BITS - S::BITS)
+    } else {
+        let shift = S::BITS - D::BITS;
+        ((s + (1 << (shift - 1))) >> shift).min((1 << (D::BITS - 1)) - 1)
+    };
+    D::from_signed(d)
 }
-impl FromSampleType<f32> for i16 {
-    fn from_sampletype(t: f32) -> i16 {
-        (t * i16::MAX_LEVEL as f32) as i16
-    }
+
+// every pair of integer types, both ways
+macro_rules! pcm_conversions {
+    ($t:ty $(, $rest:ty)*) => {
+        $(
+            impl FromSampleType<$t> for $rest {
+                fn from_sampletype(t: $t) -> $rest {
+                    rescale(t)
+                }
+            }
+            impl FromSampleType<$rest> for $t {
+                fn from_sampletype(t: $rest) -> $t {
+                    rescale(t)
+                }
+            }
+        )*
+        pcm_conversions!($($rest),*);
+    };
+    () => {};
 }
-impl FromSampleType<f32> for i32 {
-    fn from_sampletype(t: f32) -> i32 {
-        (t * i32::MAX_LEVEL as f32) as i32
-    }
+pcm_conversions!(i8, u8, i16, u16, I24, i32);
+
+macro_rules! float_conversions {
+    ($($t:ty),*) => {
+        $(
+            impl FromSampleType<$t> for f32 {
+                fn from_sampletype(t: $t) -> f32 {
+                    (t.to_signed() as f64 / <$t>::scale()) as f32
+                }
+            }
+            impl FromSampleType<$t> for f64 {
+                fn from_sampletype(t: $t) -> f64 {
+                    t.to_signed() as f64 / <$t>::scale()
+                }
+            }
+            impl FromSampleType<f32> for $t {
+                fn from_sampletype(t: f32) -> $t {
+                    <$t>::quantize(t as f64)
+                }
+            }
+            impl FromSampleType<f64> for $t {
+                fn from_sampletype(t: f64) -> $t {
+                    <$t>::quantize(t)
+                }
+            }
+        )*
+    };
 }
+float_conversions!(i8, u8, i16, u16, I24, i32);
+
+// floats pass levels beyond full scale through unclipped, so that a float chain
+// keeps its headroom until the final conversion to an integer format clips it.
 impl FromSampleType<f32> for f64 {
     fn from_sampletype(t: f32) -> f64 {
         t as f64
     }
 }
-impl FromSampleType<f64> for i16 {
-    fn from_sampletype(t: f64) -> i16 {
-        (t * i16::MAX_LEVEL as f64) as i16
-    }
-}
-impl FromSampleType<f64> for i32 {
-    fn from_sampletype(t: f64) -> i32 {
-        (t * i32::MAX_LEVEL as f64) as i32
-    }
-}
 impl FromSampleType<f64> for f32 {
     fn from_sampletype(t: f64) -> f32 {
         t as f32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip_through_arrays() {
+        let mono = Mono::from([1.0]);
+        assert_eq!(mono, Mono(1.0));
+        assert_eq!(<[f64; 1]>::from(mono), [1.0]);
+
+        let stereo = Stereo::from([1.0, 2.0]);
+        assert_eq!(stereo, Stereo { l: 1.0, r: 2.0 });
+        assert_eq!(<[f64; 2]>::from(stereo), [1.0, 2.0]);
+
+        let quad = Quad::from([1.0, 2.0, 3.0, 4.0]);
+        assert_eq!((quad.fl, quad.fr, quad.bl, quad.br), (1.0, 2.0, 3.0, 4.0));
+        assert_eq!(<[f64; 4]>::from(quad), [1.0, 2.0, 3.0, 4.0]);
+
+        let array = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let s51 = Surround51::from(array);
+        assert_eq!((s51.c, s51.lfe, s51.br), (3.0, 4.0, 6.0));
+        assert_eq!(<[f64; 6]>::from(s51), array);
+
+        let array = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        let s71 = Surround71::from(array);
+        assert_eq!((s71.c, s71.lfe, s71.sl, s71.sr), (3.0, 4.0, 7.0, 8.0));
+        assert_eq!(<[f64; 8]>::from(s71), array);
+    }
+
+    #[test]
+    fn sum_of_nothing_is_silence() {
+        let sum: Stereo<f64> = std::iter::empty::<Stereo<f64>>().sum();
+        assert_eq!(sum, Stereo::SILENCE);
+        let sum: Surround71<i16> = [].iter().sum();
+        assert_eq!(sum, Surround71::SILENCE);
+
+        let frames = [Stereo { l: 1, r: 2 }, Stereo { l: 10, r: 20 }];
+        assert_eq!(frames.iter().sum::<Stereo<i32>>(), Stereo { l: 11, r: 22 });
+        assert_eq!(Stereo::<u8>::default(), Stereo::new(0x80));
+    }
+
+    #[test]
+    fn zip_map_keeps_the_channel_order() {
+        let a = Surround51::from([1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let b = Surround51::from([10.0, 20.0, 30.0, 40.0, 50.0, 60.0]);
+        let s: [f64; 6] = a.zip_map(b, |a, b| b - a).into();
+        assert_eq!(s, [9.0, 18.0, 27.0, 36.0, 45.0, 54.0]);
+
+        let a = Surround71::from([1, 2, 3, 4, 5, 6, 7, 8]);
+        let b = Surround71::from([8, 7, 6, 5, 4, 3, 2, 1]);
+        let s: [i32; 8] = a.zip_map(b, |a, b| a * 10 + b).into();
+        assert_eq!(s, [18, 27, 36, 45, 54, 63, 72, 81]);
+        let frame = a.zip_map(b, |a: i32, b: i32| a - b);
+        assert_eq!((frame.sl, frame.sr), (5, 7));
+    }
+
+    // min, silence and max of `$s` in every other integer type. narrowing clips
+    // the max to the max, widening keeps its exact level and converts back.
+    macro_rules! check_levels {
+        ($s:ty => $($d:ty),+) => {$(
+            let name = concat!(stringify!($s), " to ", stringify!($d));
+            let (min, silence) = (<$s>::MIN_LEVEL, <$s>::REF_LEVEL);
+            assert_eq!(<$d>::from_sampletype(min), <$d>::MIN_LEVEL, "{}", name);
+            assert_eq!(<$d>::from_sampletype(silence), <$d>::REF_LEVEL, "{}", name);
+            let max = <$d>::from_sampletype(<$s>::MAX_LEVEL);
+            if <$d>::BITS <= <$s>::BITS {
+                assert_eq!(max, <$d>::MAX_LEVEL, "{}", name);
+            } else {
+                let d: f64 = max.into_sampletype();
+                assert_eq!(d, f64::from_sampletype(<$s>::MAX_LEVEL), "{}", name);
+                assert_eq!(<$s>::from_sampletype(max), <$s>::MAX_LEVEL, "{}", name);
+            }
+        )+};
+    }
+
+    #[test]
+    fn integer_levels_in_every_direction() {
+        check_levels!(i8 => i8, u8, i16, u16, I24, i32);
+        check_levels!(u8 => i8, u8, i16, u16, I24, i32);
+        check_levels!(i16 => i8, u8, i16, u16, I24, i32);
+        check_levels!(u16 => i8, u8, i16, u16, I24, i32);
+        check_levels!(I24 => i8, u8, i16, u16, I24, i32);
+        check_levels!(i32 => i8, u8, i16, u16, I24, i32);
+    }
+
+    #[test]
+    fn offset_silence_is_the_middle() {
+        assert_eq!(Stereo::<u8>::default(), Stereo::<u8>::SILENCE);
+        assert_eq!(Stereo::<u8>::SILENCE, Stereo::new(0x80));
+        assert_eq!(<[u16; 3]>::SILENCE, [0x8000; 3]);
+    }
+
+    #[test]
+    fn offset_samples_convert_around_the_middle() {
+        assert_eq!(i16::from_sampletype(0x80u8), 0);
+        assert_eq!(i16::from_sampletype(0xffu8), 0x7f00);
+        assert_eq!(u16::from_sampletype(0x81u8), 0x8100);
+        assert_eq!(u8::from_sampletype(0i8), 0x80);
+        assert_eq!(u8::from_sampletype(-1i8), 0x7f);
+        assert_eq!(u8::from_sampletype(0xffffu16), 0xff);
+        assert_eq!(u8::from_sampletype(0x817fu16), 0x81);
+        assert_eq!(u8::from_sampletype(0x8180u16), 0x82);
+        assert_eq!(I24::from_sampletype(0u16), I24::MIN);
+        assert_eq!(i32::from_sampletype(0x8001u16), 0x1_0000);
+        assert_eq!(f64::from_sampletype(0u8), -1.0);
+        assert_eq!(f64::from_sampletype(0xc000u16), 0.5);
+    }
+
+    #[test]
+    fn integers_round_trip_through_wider_types() {
+        for i in i8::MIN..=i8::MAX {
+            let u = u8::from_sampletype(i);
+            assert_eq!(i8::from_sampletype(u), i);
+            assert_eq!(i8::from_sampletype(i16::from_sampletype(i)), i);
+            assert_eq!(u8::from_sampletype(I24::from_sampletype(u)), u);
+            assert_eq!(u8::from_sampletype(i32::from_sampletype(u)), u);
+            assert_eq!(i8::from_sampletype(f32::from_sampletype(i)), i);
+        }
+        for &i in [i16::MIN, -12345, -1, 0, 1, 12345, i16::MAX].iter() {
+            assert_eq!(i16::from_sampletype(u16::from_sampletype(i)), i);
+            assert_eq!(i16::from_sampletype(I24::from_sampletype(i)), i);
+            assert_eq!(i16::from_sampletype(i32::from_sampletype(i)), i);
+            assert_eq!(i16::from_sampletype(f64::from_sampletype(i)), i);
+        }
+        for &i in [I24::MIN.get(), -1234567, -1, 0, 1, I24::MAX.get()].iter() {
+            let s = I24::new(i).unwrap();
+            assert_eq!(I24::from_sampletype(i32::from_sampletype(s)), s);
+            assert_eq!(I24::from_sampletype(f64::from_sampletype(s)), s);
+        }
+    }
+
+    #[test]
+    fn i24_packs_into_three_bytes() {
+        assert_eq!(I24::new(-1).unwrap().to_le_bytes(), [0xff, 0xff, 0xff]);
+        assert_eq!(I24::MIN.to_le_bytes(), [0x00, 0x00, 0x80]);
+        assert_eq!(I24::MAX.to_le_bytes(), [0xff, 0xff, 0x7f]);
+        assert_eq!(I24::from_le_bytes([0x00, 0x00, 0x80]), I24::MIN);
+        assert_eq!(I24::from_le_bytes([0x01, 0x00, 0xff]).get(), -0xff_ff);
+        let values = [-0x80_0000, -0x12_3456, -256, -1, 0, 1, 0x12_3456, 0x7f_ffff];
+        for &i in values.iter() {
+            let s = I24::new(i).unwrap();
+            assert_eq!(I24::from_le_bytes(s.to_le_bytes()), s);
+        }
+        assert_eq!(I24::new(0x80_0000), None);
+        assert_eq!(I24::wrapping_from(0x80_0000), I24::MIN);
+        assert_eq!(I24::wrapping_from(-0x80_0001), I24::MAX);
+    }
+
+    #[test]
+    fn floats_clip_to_integers() {
+        assert_eq!(i16::from_sampletype(1.5f64), i16::MAX);
+        assert_eq!(i16::from_sampletype(-1.5f64), i16::MIN);
+        assert_eq!(i16::from_sampletype(1.0f64), i16::MAX);
+        assert_eq!(i16::from_sampletype(-1.0f32), i16::MIN);
+        assert_eq!(u8::from_sampletype(1.5f32), u8::MAX);
+        assert_eq!(u8::from_sampletype(-1.5f64), u8::MIN);
+        assert_eq!(I24::from_sampletype(2.0f64), I24::MAX);
+        assert_eq!(i32::from_sampletype(-2.0f64), i32::MIN);
+        assert_eq!(i32::from_sampletype(f64::INFINITY), i32::MAX);
+        // floats keep their headroom
+        assert_eq!(f32::from_sampletype(1.5f64), 1.5);
+    }
+
+    #[test]
+    fn floats_round_to_the_nearest_step() {
+        let step = 1.0 / 32768.0;
+        assert_eq!(i16::from_sampletype(0.4 * step), 0);
+        assert_eq!(i16::from_sampletype(0.6 * step), 1);
+        assert_eq!(i16::from_sampletype(-0.6 * step), -1);
+        assert_eq!(i16::from_sampletype(2.49 * step), 2);
+        let step = 1.0 / 128.0;
+        assert_eq!(u8::from_sampletype(0.4 * step), 0x80);
+        assert_eq!(u8::from_sampletype(-0.6 * step), 0x7f);
+        assert_eq!(u16::from_sampletype(0.5f32), 0xc000);
+    }
+
+    #[test]
+    fn nan_becomes_silence() {
+        assert_eq!(i8::from_sampletype(f64::NAN), 0);
+        assert_eq!(u8::from_sampletype(f64::NAN), 0x80);
+        assert_eq!(i16::from_sampletype(f32::NAN), 0);
+        assert_eq!(u16::from_sampletype(f32::NAN), 0x8000);
+        assert_eq!(I24::from_sampletype(f64::NAN), I24::default());
+        assert_eq!(i32::from_sampletype(f64::NAN), 0);
+    }
+}
@@ -1,158 +1,723 @@
 use std::error;
 use std::fmt;
 use std::io::prelude::*;
-use std::mem::transmute;
+use std::io::{self, SeekFrom};
 
 use crate::sample::*;
 
-pub struct WAV {
+pub struct WAVHeader {
     pub format: u16,
     pub channels: u16,
     pub samplerate: u32,
     pub bytepersec: u32,
     pub blockalign: u16,
     pub bitswidth: u16,
-    pub raw_data: Vec<u8>,
+    /// format tag of the samples. same as `format` unless the stream uses
+    /// WAVE_FORMAT_EXTENSIBLE, where it is taken from the subformat GUID.
+    pub subformat: u16,
+    /// number of significant bits in each sample.
+    pub validbits: u16,
+    /// speaker positions of the channels, 0 if not specified.
+    pub channelmask: u32,
 }
 
+pub const WAVE_FORMAT_PCM: u16 = 1;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
 type Result<T> = ::std::result::Result<T, Error>;
 
-impl WAV {
-    pub fn new<R: Read>(mut inner: R) -> Result<WAV> {
-        let mut riff = [0; 4];
-        inner.read_exact(&mut riff)?;
-        if riff != [0x52, 0x49, 0x46, 0x46] {
-            return Err(Error::WAVFormat);
+const BUFFER_SIZE: usize = 4096;
+/// largest channel count `WAVReader` decodes.
+pub const MAX_CHANNELS: u16 = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub id: [u8; 4],
+    pub size: u32,
+    /// position of the chunk data in the stream.
+    pub offset: u64,
+}
+
+/// reads the frames of a WAV stream on demand through a small buffer.
+pub struct WAVReader<R> {
+    inner: R,
+    pub header: WAVHeader,
+    chunks: Vec<Chunk>,
+    data_start: u64,
+    frames: usize,
+    pos: usize,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    buf_end: usize,
+}
+
+impl<R: Read + Seek> WAVReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        if file_len < 12 {
+            return Err(Error::Truncated);
         }
 
-        let mut riff_size = [0; 4];
-        inner.read_exact(&mut riff_size)?;
-        let riff_size = u32::from_le(unsafe { transmute(riff_size) });
-        let _ = riff_size;
+        let riff_id = read_id(&mut inner)?;
+        let riff_size = read_u32(&mut inner)?;
+        let riff_type = read_id(&mut inner)?;
+        if riff_id != *b"RIFF" || riff_type != *b"WAVE" {
+            return Err(Error::NotWAVE);
+        }
+        // streaming writers leave the RIFF size unset
+        let riff_end = match riff_size {
+            0 | 0xffff_ffff => file_len,
+            size => 8 + size as u64,
+        };
 
-        let mut riff_type = [0; 4];
-        inner.read_exact(&mut riff_type)?;
-        if riff_type != [0x57, 0x41, 0x56, 0x45] {
-            return Err(Error::WAVFormat);
+        let mut chunks = Vec::new();
+        let mut header = None;
+        let mut offset = 12;
+        while offset + 8 <= riff_end.min(file_len) {
+            inner.seek(SeekFrom::Start(offset))?;
+            let chunk = Chunk {
+                id: read_id(&mut inner)?,
+                size: read_u32(&mut inner)?,
+                offset: offset + 8,
+            };
+            let end = chunk.offset + chunk.size as u64;
+            // a short data chunk is tolerated and read up to the end of the file
+            if chunk.id != *b"data" {
+                if end > riff_end {
+                    return Err(Error::BadChunkSize {
+                        id: chunk.id,
+                        size: chunk.size,
+                    });
+                }
+                if end > file_len {
+                    return Err(Error::Truncated);
+                }
+            }
+            if chunk.id == *b"fmt " {
+                header = Some(read_fmt(&mut inner, chunk.size)?);
+            }
+            chunks.push(chunk);
+            // chunks are padded to an even size
+            offset = end + (end & 1);
         }
 
-        let mut fmt_id = [0; 4];
-        inner.read_exact(&mut fmt_id)?;
-        if fmt_id != [0x66, 0x6d, 0x74, 0x20] {
-            return Err(Error::WAVFormat);
+        let header = header.ok_or(Error::MissingFmt)?;
+        match header.subformat {
+            WAVE_FORMAT_PCM | WAVE_FORMAT_IEEE_FLOAT => {}
+            format => return Err(Error::UnsupportedFormat(format)),
+        }
+        match (header.channels, header.subformat, header.bitswidth) {
+            (1..=MAX_CHANNELS, WAVE_FORMAT_PCM, 8)
+            | (1..=MAX_CHANNELS, WAVE_FORMAT_PCM, 16)
+            | (1..=MAX_CHANNELS, WAVE_FORMAT_PCM, 24)
+            | (1..=MAX_CHANNELS, WAVE_FORMAT_PCM, 32)
+            | (1..=MAX_CHANNELS, WAVE_FORMAT_IEEE_FLOAT, 32)
+            | (1..=MAX_CHANNELS, WAVE_FORMAT_IEEE_FLOAT, 64) => {}
+            (channels, _, bitswidth) => {
+                return Err(Error::UnsupportedLayout {
+                    channels: channels,
+                    bitswidth: bitswidth,
+                })
+            }
+        }
+        if header.blockalign != header.channels * header.bitswidth / 8 {
+            return Err(Error::InvalidFmt);
         }
 
-        let mut fmt_size = [0; 4];
-        inner.read_exact(&mut fmt_size)?;
-        let fmt_size = u32::from_le(unsafe { transmute(fmt_size) });
-        let _ = fmt_size;
+        let data = chunks
+            .iter()
+            .find(|chunk| chunk.id == *b"data")
+            .ok_or(Error::MissingData)?;
+        let data_start = data.offset;
+        let data_len = u64::min(data.size as u64, file_len - data.offset);
+        inner.seek(SeekFrom::Start(data_start))?;
 
-        let mut fmt_format = [0; 2];
-        inner.read_exact(&mut fmt_format)?;
-        let fmt_format = u16::from_le(unsafe { transmute(fmt_format) });
+        let frames = data_len as usize / header.blockalign as usize;
+        let buf_len = BUFFER_SIZE / header.blockalign as usize * header.blockalign as usize;
+        Ok(WAVReader {
+            inner: inner,
+            header: header,
+            chunks: chunks,
+            data_start: data_start,
+            frames: frames,
+            pos: 0,
+            buf: vec![0; buf_len],
+            buf_pos: 0,
+            buf_end: 0,
+        })
+    }
 
-        let mut fmt_channels = [0; 2];
-        inner.read_exact(&mut fmt_channels)?;
-        let fmt_channels = u16::from_le(unsafe { transmute(fmt_channels) });
+    /// every chunk found in the RIFF container, in file order.
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
 
-        let mut fmt_samplerate = [0; 4];
-        inner.read_exact(&mut fmt_samplerate)?;
-        let fmt_samplerate = u32::from_le(unsafe { transmute(fmt_samplerate) });
+    /// total number of frames in the data chunk.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
 
-        let mut fmt_bytepersec = [0; 4];
-        inner.read_exact(&mut fmt_bytepersec)?;
-        let fmt_bytepersec = u32::from_le(unsafe { transmute(fmt_bytepersec) });
+    /// index of the frame returned by the next read.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
 
-        let mut fmt_blockalign = [0; 2];
-        inner.read_exact(&mut fmt_blockalign)?;
-        let fmt_blockalign = u16::from_le(unsafe { transmute(fmt_blockalign) });
+    /// moves to `frame`. `frames()` itself is the end of the stream; frames past
+    /// it fail with `Error::SeekPastEnd` and leave the position unchanged.
+    pub fn seek(&mut self, frame: usize) -> Result<()> {
+        if frame > self.frames {
+            return Err(Error::SeekPastEnd {
+                frame: frame,
+                frames: self.frames,
+            });
+        }
+        let offset = frame as u64 * self.header.blockalign as u64;
+        self.inner.seek(SeekFrom::Start(self.data_start + offset))?;
+        self.pos = frame;
+        self.buf_pos = 0;
+        self.buf_end = 0;
+        Ok(())
+    }
 
-        let mut fmt_bitswidth = [0; 2];
-        inner.read_exact(&mut fmt_bitswidth)?;
-        let fmt_bitswidth = u16::from_le(unsafe { transmute(fmt_bitswidth) });
+    /// fails with `Error::ChannelMismatch` if frames of `T` can't be read from
+    /// this stream.
+    pub fn check_frame<T: Sample>(&self) -> Result<()> {
+        let channels = self.header.channels;
+        if T::CHANNELS == channels as usize || has_layout(channels as usize) {
+            Ok(())
+        } else {
+            Err(Error::ChannelMismatch {
+                channels: channels,
+                frame_channels: T::CHANNELS,
+            })
+        }
+    }
 
-        let mut data_id = [0; 4];
-        inner.read_exact(&mut data_id)?;
-        if data_id != [0x64, 0x61, 0x74, 0x61] {
-            return Err(Error::WAVFormat);
+    pub fn read_sample_as<T>(&mut self) -> Result<Option<T>>
+    where
+        T: FromLayout,
+        T::Member: FromSampleType<i32> + FromSampleType<f64>,
+    {
+        self.check_frame::<T>()?;
+        if self.buf_pos >= self.buf_end && !self.fill()? {
+            return Ok(None);
+        }
+        let blockalign = self.header.blockalign as usize;
+        let sample = decode(
+            &self.header,
+            &self.buf[self.buf_pos..self.buf_pos + blockalign],
+        );
+        if sample.is_some() {
+            self.buf_pos += blockalign;
+            self.pos += 1;
         }
+        Ok(sample)
+    }
 
-        let mut data_size = [0; 4];
-        inner.read_exact(&mut data_size)?;
-        let data_size = u32::from_le(unsafe { transmute(data_size) });
+    /// reads up to `buf.len()` frames and returns how many were read.
+    pub fn read_samples_as<T>(&mut self, buf: &mut [T]) -> Result<usize>
+    where
+        T: FromLayout,
+        T::Member: FromSampleType<i32> + FromSampleType<f64>,
+    {
+        self.check_frame::<T>()?;
+        let blockalign = self.header.blockalign as usize;
+        let mut done = 0;
+        while done < buf.len() {
+            if self.buf_pos >= self.buf_end && !self.fill()? {
+                break;
+            }
+            let raw = &self.buf[self.buf_pos..self.buf_end];
+            let n = (raw.len() / blockalign).min(buf.len() - done);
+            for (dst, raw) in buf[done..done + n]
+                .iter_mut()
+                .zip(raw.chunks_exact(blockalign))
+            {
+                match decode(&self.header, raw) {
+                    Some(s) => *dst = s,
+                    None => return Ok(done),
+                }
+            }
+            self.buf_pos += n * blockalign;
+            self.pos += n;
+            done += n;
+        }
+        Ok(done)
+    }
 
-        let mut data_data = Vec::new();
-        inner.take(data_size as u64).read_to_end(&mut data_data)?;
+    fn fill(&mut self) -> Result<bool> {
+        let blockalign = self.header.blockalign as usize;
+        let len = self.buf.len().min((self.frames - self.pos) * blockalign);
+        let mut filled = 0;
+        while filled < len {
+            match self.inner.read(&mut self.buf[filled..len]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        // a truncated data chunk ends at the last complete frame
+        let filled = filled / blockalign * blockalign;
+        if filled < len {
+            self.frames = self.pos + filled / blockalign;
+        }
+        self.buf_pos = 0;
+        self.buf_end = filled;
+        Ok(filled > 0)
+    }
+}
 
-        Ok(WAV {
-            format: fmt_format,
-            channels: fmt_channels,
-            samplerate: fmt_samplerate,
-            bytepersec: fmt_bytepersec,
-            blockalign: fmt_blockalign,
-            bitswidth: fmt_bitswidth,
-            raw_data: data_data,
+// PCM is widened to i32 and float to f64, so no precision is lost on the way to
+// `T::Member`. a file with another channel count is decoded in its own layout
+// and converted from there.
+fn decode<T: FromLayout>(header: &WAVHeader, raw: &[u8]) -> Option<T>
+where
+    T::Member: FromSampleType<i32> + FromSampleType<f64>,
+{
+    let channels = header.channels as usize;
+    let width = header.bitswidth as usize / 8;
+    if T::CHANNELS == channels {
+        let mut members = [T::Member::REF_LEVEL; MAX_CHANNELS as usize];
+        for (member, raw) in members.iter_mut().zip(raw.chunks_exact(width)) {
+            *member = decode_member(header.subformat, raw)?;
+        }
+        T::from_raw(&members[..channels])
+    } else {
+        let mut members = [0.0; MAX_CHANNELS as usize];
+        for (member, raw) in members.iter_mut().zip(raw.chunks_exact(width)) {
+            *member = decode_member(header.subformat, raw)?;
+        }
+        T::from_layout(&members[..channels])
+    }
+}
+
+fn decode_member<M>(subformat: u16, raw: &[u8]) -> Option<M>
+where
+    M: FromSampleType<i32> + FromSampleType<f64>,
+{
+    Some(match (subformat, raw.len()) {
+        // 8-bit PCM is unsigned
+        (WAVE_FORMAT_PCM, 1) => i32::from_sampletype(raw[0]).into_sampletype(),
+        (WAVE_FORMAT_PCM, 2) => {
+            let s = i16::from_le_bytes([raw[0], raw[1]]);
+            i32::from_sampletype(s).into_sampletype()
+        }
+        (WAVE_FORMAT_PCM, 3) => {
+            let s = I24::from_le_bytes([raw[0], raw[1], raw[2]]);
+            i32::from_sampletype(s).into_sampletype()
+        }
+        (WAVE_FORMAT_PCM, 4) => {
+            let s = i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+            s.into_sampletype()
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 4) => {
+            let s = f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+            (s as f64).into_sampletype()
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 8) => {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(raw);
+            f64::from_le_bytes(buf).into_sampletype()
+        }
+        _ => return None,
+    })
+}
+
+/// sample encoding written by `WAVWriter`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WAVFormat {
+    PCM8,
+    PCM16,
+    PCM24,
+    PCM32,
+    Float32,
+    Float64,
+}
+impl WAVFormat {
+    pub fn bitswidth(self) -> u16 {
+        match self {
+            WAVFormat::PCM8 => 8,
+            WAVFormat::PCM16 => 16,
+            WAVFormat::PCM24 => 24,
+            WAVFormat::PCM32 | WAVFormat::Float32 => 32,
+            WAVFormat::Float64 => 64,
+        }
+    }
+    fn tag(self) -> u16 {
+        match self {
+            WAVFormat::Float32 | WAVFormat::Float64 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        }
+    }
+}
+
+/// writes frames to a WAV stream. the RIFF and data sizes are patched when the
+/// writer is finalized or dropped. streams of more than two channels are written
+/// as WAVE_FORMAT_EXTENSIBLE, with the speaker positions of quad, 5.1 and 7.1 in
+/// the channel mask.
+pub struct WAVWriter<W: Write + Seek> {
+    inner: W,
+    pub header: WAVHeader,
+    format: WAVFormat,
+    data_start: u64,
+    frames: usize,
+    buf: Vec<u8>,
+    finalized: bool,
+}
+
+impl<W: Write + Seek> WAVWriter<W> {
+    pub fn new(mut inner: W, format: WAVFormat, channels: u16, samplerate: u32) -> Result<Self> {
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(Error::UnsupportedLayout {
+                channels: channels,
+                bitswidth: format.bitswidth(),
+            });
+        }
+        let blockalign = channels * format.bitswidth() / 8;
+        let extensible = channels > 2;
+        let header = WAVHeader {
+            format: if extensible {
+                WAVE_FORMAT_EXTENSIBLE
+            } else {
+                format.tag()
+            },
+            channels: channels,
+            samplerate: samplerate,
+            bytepersec: samplerate * blockalign as u32,
+            blockalign: blockalign,
+            bitswidth: format.bitswidth(),
+            subformat: format.tag(),
+            validbits: format.bitswidth(),
+            channelmask: if extensible {
+                channel_mask(channels)
+            } else {
+                0
+            },
+        };
+        // non-PCM formats carry an extension size and a fact chunk
+        let float = header.subformat != WAVE_FORMAT_PCM;
+        let fmt_size: u32 = match (extensible, float) {
+            (true, _) => 40,
+            (false, true) => 18,
+            (false, false) => 16,
+        };
+
+        inner.seek(SeekFrom::Start(0))?;
+        inner.write_all(b"RIFF")?;
+        inner.write_all(&0u32.to_le_bytes())?;
+        inner.write_all(b"WAVE")?;
+        inner.write_all(b"fmt ")?;
+        inner.write_all(&fmt_size.to_le_bytes())?;
+        inner.write_all(&header.format.to_le_bytes())?;
+        inner.write_all(&header.channels.to_le_bytes())?;
+        inner.write_all(&header.samplerate.to_le_bytes())?;
+        inner.write_all(&header.bytepersec.to_le_bytes())?;
+        inner.write_all(&header.blockalign.to_le_bytes())?;
+        inner.write_all(&header.bitswidth.to_le_bytes())?;
+        if extensible {
+            inner.write_all(&22u16.to_le_bytes())?;
+            inner.write_all(&header.validbits.to_le_bytes())?;
+            inner.write_all(&header.channelmask.to_le_bytes())?;
+            inner.write_all(&header.subformat.to_le_bytes())?;
+            inner.write_all(&KSDATAFORMAT_SUFFIX)?;
+        } else if float {
+            inner.write_all(&0u16.to_le_bytes())?;
+        }
+        if float {
+            inner.write_all(b"fact")?;
+            inner.write_all(&4u32.to_le_bytes())?;
+            inner.write_all(&0u32.to_le_bytes())?;
+        }
+        inner.write_all(b"data")?;
+        inner.write_all(&0u32.to_le_bytes())?;
+        let data_start = inner.stream_position()?;
+
+        Ok(WAVWriter {
+            inner: inner,
+            header: header,
+            format: format,
+            data_start: data_start,
+            frames: 0,
+            buf: Vec::with_capacity(BUFFER_SIZE),
+            finalized: false,
         })
     }
 
-    pub fn get_sample_as<T>(&self, index: usize) -> Option<T>
+    /// number of frames written so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// frames with as many channels as the stream are written as they are. other
+    /// frames are mixed to stereo or mono for streams of that many channels and
+    /// fail with `Error::ChannelMismatch` otherwise.
+    pub fn write_sample<T>(&mut self, sample: T) -> Result<()>
     where
         T: Sample,
-        T::Member: FromSampleType<i16>,
+        T::Member: IntoSampleType<f64>,
     {
-        match (self.channels, self.bitswidth) {
-            (2, 16) => {
-                if self.raw_data.len() < (index + 1) * 4 {
-                    return None;
-                }
-                let l = [self.raw_data[index * 4 + 0], self.raw_data[index * 4 + 1]];
-                let l = i16::from_le(unsafe { transmute(l) });
-                let r = [self.raw_data[index * 4 + 2], self.raw_data[index * 4 + 3]];
-                let r = i16::from_le(unsafe { transmute(r) });
-                T::from_raw(&[l.into_sampletype(), r.into_sampletype()])
-            }
-            (2, 8) => {
-                if self.raw_data.len() < (index + 1) * 2 {
-                    return None;
-                }
-                let l = self.raw_data[index * 2 + 0] as i8;
-                let l = (l as i16) << 8;
-                let r = self.raw_data[index * 2 + 1] as i8;
-                let r = (r as i16) << 8;
-                T::from_raw(&[l.into_sampletype(), r.into_sampletype()])
-            }
-            (1, 16) => {
-                if self.raw_data.len() < (index + 1) * 2 {
-                    return None;
+        let blockalign = self.header.blockalign as u64;
+        if self.data_start + (self.frames as u64 + 1) * blockalign > u32::MAX as u64 {
+            return Err(Error::TooLarge);
+        }
+        match self.header.channels {
+            channels if channels as usize == T::CHANNELS => {
+                let mut members = [T::Member::REF_LEVEL; MAX_CHANNELS as usize];
+                sample.to_raw(&mut members);
+                for &member in members[..T::CHANNELS].iter() {
+                    encode(self.format, member.into_sampletype(), &mut self.buf);
                 }
-                let s = [self.raw_data[index * 2 + 0], self.raw_data[index * 2 + 1]];
-                let s = i16::from_le(unsafe { transmute(s) });
-                T::from_raw(&[s.into_sampletype()])
             }
-            (1, 8) => {
-                if self.raw_data.len() < (index + 1) {
-                    return None;
-                }
-                let s = self.raw_data[index] as i8;
-                let s = (s as i16) << 8;
-                T::from_raw(&[s.into_sampletype()])
+            2 => {
+                let Stereo { l, r } = sample.to_stereo();
+                encode(self.format, l.into_sampletype(), &mut self.buf);
+                encode(self.format, r.into_sampletype(), &mut self.buf);
+            }
+            1 => {
+                let Mono(s) = sample.to_mono();
+                encode(self.format, s.into_sampletype(), &mut self.buf);
+            }
+            channels => {
+                return Err(Error::ChannelMismatch {
+                    channels: channels,
+                    frame_channels: T::CHANNELS,
+                })
             }
-            _ => panic!(),
         }
+        self.frames += 1;
+        if self.buf.len() >= BUFFER_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn write_samples<T>(&mut self, samples: &[T]) -> Result<()>
+    where
+        T: Sample,
+        T::Member: IntoSampleType<f64>,
+    {
+        for &sample in samples {
+            self.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    /// flushes buffered frames and patches the chunk sizes. dropping the writer
+    /// does the same but ignores errors.
+    pub fn finalize(mut self) -> Result<()> {
+        self.finalized = true;
+        self.update()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<()> {
+        self.flush()?;
+        let data_len = self.frames as u64 * self.header.blockalign as u64;
+        let pad = data_len & 1;
+        if pad == 1 {
+            self.inner.write_all(&[0])?;
+        }
+        let riff_size = self.data_start + data_len + pad - 8;
+        self.inner.seek(SeekFrom::Start(4))?;
+        self.inner.write_all(&(riff_size as u32).to_le_bytes())?;
+        if self.header.subformat != WAVE_FORMAT_PCM {
+            self.inner.seek(SeekFrom::Start(self.data_start - 12))?;
+            self.inner.write_all(&(self.frames as u32).to_le_bytes())?;
+        }
+        self.inner.seek(SeekFrom::Start(self.data_start - 4))?;
+        self.inner.write_all(&(data_len as u32).to_le_bytes())?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+        Ok(())
     }
 }
 
+impl<W: Write + Seek> Drop for WAVWriter<W> {
+    fn drop(&mut self) {
+        if !self.finalized {
+            let _ = self.update();
+        }
+    }
+}
+
+// speaker positions of the layouts `FromLayout` knows, in WAV channel order
+fn channel_mask(channels: u16) -> u32 {
+    match channels {
+        // FL FR BL BR
+        4 => 0x33,
+        // FL FR FC LFE BL BR
+        6 => 0x3f,
+        // FL FR FC LFE BL BR SL SR
+        8 => 0x63f,
+        _ => 0,
+    }
+}
+
+fn encode(format: WAVFormat, s: f64, buf: &mut Vec<u8>) {
+    match format {
+        WAVFormat::PCM8 => buf.push(u8::from_sampletype(s)),
+        WAVFormat::PCM16 => buf.extend_from_slice(&i16::from_sampletype(s).to_le_bytes()),
+        WAVFormat::PCM24 => buf.extend_from_slice(&I24::from_sampletype(s).to_le_bytes()),
+        WAVFormat::PCM32 => buf.extend_from_slice(&i32::from_sampletype(s).to_le_bytes()),
+        WAVFormat::Float32 => buf.extend_from_slice(&(s as f32).to_le_bytes()),
+        WAVFormat::Float64 => buf.extend_from_slice(&s.to_le_bytes()),
+    }
+}
+
+fn read_fmt<R: Read>(inner: &mut R, size: u32) -> Result<WAVHeader> {
+    if size < 16 {
+        return Err(Error::BadChunkSize {
+            id: *b"fmt ",
+            size: size,
+        });
+    }
+    let format = read_u16(inner)?;
+    let channels = read_u16(inner)?;
+    let samplerate = read_u32(inner)?;
+    let bytepersec = read_u32(inner)?;
+    let blockalign = read_u16(inner)?;
+    let bitswidth = read_u16(inner)?;
+    let (subformat, validbits, channelmask) = if format == WAVE_FORMAT_EXTENSIBLE {
+        if size < 40 || read_u16(inner)? < 22 {
+            return Err(Error::InvalidFmt);
+        }
+        let validbits = read_u16(inner)?;
+        let channelmask = read_u32(inner)?;
+        let mut guid = [0; 16];
+        inner.read_exact(&mut guid)?;
+        // the subformat GUID is a format tag followed by a fixed suffix
+        let subformat = if guid[2..] == KSDATAFORMAT_SUFFIX {
+            u16::from_le_bytes([guid[0], guid[1]])
+        } else {
+            0
+        };
+        (subformat, validbits, channelmask)
+    } else {
+        (format, bitswidth, 0)
+    };
+    if validbits == 0 || validbits > bitswidth {
+        return Err(Error::InvalidFmt);
+    }
+    Ok(WAVHeader {
+        format: format,
+        channels: channels,
+        samplerate: samplerate,
+        bytepersec: bytepersec,
+        blockalign: blockalign,
+        bitswidth: bitswidth,
+        subformat: subformat,
+        validbits: validbits,
+        channelmask: channelmask,
+    })
+}
+
+const KSDATAFORMAT_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+fn read_id<R: Read>(inner: &mut R) -> Result<[u8; 4]> {
+    let mut buf = [0; 4];
+    inner.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u16<R: Read>(inner: &mut R) -> Result<u16> {
+    let mut buf = [0; 2];
+    inner.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(inner: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    inner.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
 #[derive(Debug)]
 pub enum Error {
     Io(::std::io::Error),
-    WAVFormat,
+    /// the stream does not start with a RIFF/WAVE header.
+    NotWAVE,
+    /// the stream ends inside the header or a chunk other than `data`.
+    Truncated,
+    MissingFmt,
+    MissingData,
+    /// a chunk claims to extend past the end of the RIFF container.
+    BadChunkSize {
+        id: [u8; 4],
+        size: u32,
+    },
+    /// the fmt chunk contents are inconsistent.
+    InvalidFmt,
+    /// the samples use a compressed or unknown encoding with this format tag.
+    UnsupportedFormat(u16),
+    UnsupportedLayout {
+        channels: u16,
+        bitswidth: u16,
+    },
+    /// the data no longer fits in a RIFF container.
+    TooLarge,
+    /// the frame is past the end of the data chunk.
+    SeekPastEnd {
+        frame: usize,
+        frames: usize,
+    },
+    /// the stream has no named layout for its channel count, so it can only be
+    /// read or written as frames with that many channels.
+    ChannelMismatch {
+        channels: u16,
+        frame_channels: usize,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             Error::Io(ref err) => write!(f, "IO error: {}", err),
-            Error::WAVFormat => write!(f, "WAV format error"),
+            Error::NotWAVE => write!(f, "not a RIFF/WAVE stream"),
+            Error::Truncated => write!(f, "truncated WAV stream"),
+            Error::MissingFmt => write!(f, "missing fmt chunk"),
+            Error::MissingData => write!(f, "missing data chunk"),
+            Error::BadChunkSize { ref id, size } => write!(
+                f,
+                "bad size {} of chunk \"{}\"",
+                size,
+                String::from_utf8_lossy(id)
+            ),
+            Error::InvalidFmt => write!(f, "invalid fmt chunk"),
+            Error::UnsupportedFormat(format) => {
+                write!(f, "unsupported WAV format tag {:#06x}", format)
+            }
+            Error::UnsupportedLayout {
+                channels,
+                bitswidth,
+            } => write!(
+                f,
+                "unsupported WAV layout: {} channels, {} bits",
+                channels, bitswidth
+            ),
+            Error::TooLarge => write!(f, "WAV data exceeds 4 GiB"),
+            Error::SeekPastEnd { frame, frames } => write!(
+                f,
+                "can't seek to frame {} of a WAV stream of {} frames",
+                frame, frames
+            ),
+            Error::ChannelMismatch {
+                channels,
+                frame_channels,
+            } => write!(
+                f,
+                "can't convert between {} WAV channels and {} channels",
+                channels, frame_channels
+            ),
         }
     }
 }
@@ -161,7 +726,17 @@ impl error::Error for Error {
     fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             Error::Io(ref err) => Some(err),
-            Error::WAVFormat => None,
+            Error::NotWAVE
+            | Error::Truncated
+            | Error::MissingFmt
+            | Error::MissingData
+            | Error::BadChunkSize { .. }
+            | Error::InvalidFmt
+            | Error::UnsupportedFormat(_)
+            | Error::UnsupportedLayout { .. }
+            | Error::TooLarge
+            | Error::SeekPastEnd { .. }
+            | Error::ChannelMismatch { .. } => None,
         }
     }
 }
@@ -171,3 +746,385 @@ impl From<::std::io::Error> for Error {
         Error::Io(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // a canonical 16 bit mono WAV stream
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let data_size = samples.len() as u32 * 2;
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&44100u32.to_le_bytes());
+        bytes.extend_from_slice(&88200u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        for s in samples {
+            bytes.extend_from_slice(&s.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn read(reader: &mut WAVReader<Cursor<Vec<u8>>>) -> Option<i16> {
+        reader.read_sample_as::<Mono<i16>>().unwrap().map(|s| s.0)
+    }
+
+    // a RIFF/WAVE stream holding `chunks`, padded to even sizes
+    fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (id, data) in chunks.iter() {
+            body.extend_from_slice(&id[..]);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    // a plain fmt chunk
+    fn fmt(format: u16, channels: u16, bitswidth: u16) -> Vec<u8> {
+        let blockalign = channels * bitswidth / 8;
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&44100u32.to_le_bytes());
+        fmt.extend_from_slice(&(44100 * blockalign as u32).to_le_bytes());
+        fmt.extend_from_slice(&blockalign.to_le_bytes());
+        fmt.extend_from_slice(&bitswidth.to_le_bytes());
+        fmt
+    }
+
+    // every frame as a pair of channels
+    fn read_all(bytes: Vec<u8>) -> Result<Vec<(i16, i16)>> {
+        let mut reader = WAVReader::new(Cursor::new(bytes))?;
+        let mut out = vec![Stereo::default(); reader.frames() + 1];
+        let n = reader.read_samples_as(&mut out)?;
+        Ok(out[..n].iter().map(|s: &Stereo<i16>| (s.l, s.r)).collect())
+    }
+
+    const FRAMES: [u8; 8] = [0x01, 0x00, 0x02, 0x00, 0xff, 0xff, 0xfe, 0xff];
+
+    #[test]
+    fn reader_skips_unknown_and_odd_chunks() {
+        let bytes = riff(&[
+            (b"LIST", b"INFOx"),
+            (b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 16)),
+            (b"junk", b"abc"),
+            (b"data", &FRAMES),
+        ]);
+        let reader = WAVReader::new(Cursor::new(bytes.clone())).unwrap();
+        let ids: Vec<[u8; 4]> = reader.chunks().iter().map(|chunk| chunk.id).collect();
+        assert_eq!(ids, [*b"LIST", *b"fmt ", *b"junk", *b"data"]);
+        let offsets: Vec<u64> = reader.chunks().iter().map(|chunk| chunk.offset).collect();
+        assert_eq!(offsets, [20, 34, 58, 70]);
+        assert_eq!(reader.header.channels, 2);
+        assert_eq!(read_all(bytes).unwrap(), [(1, 2), (-1, -2)]);
+    }
+
+    #[test]
+    fn reader_needs_fmt_and_data() {
+        let bytes = riff(&[(b"data", &FRAMES)]);
+        assert!(matches!(read_all(bytes), Err(Error::MissingFmt)));
+        let bytes = riff(&[(b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 16)), (b"LIST", b"")]);
+        assert!(matches!(read_all(bytes), Err(Error::MissingData)));
+        assert!(matches!(read_all(b"RIFF".to_vec()), Err(Error::Truncated)));
+        let mut bytes = riff(&[(b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 16))]);
+        bytes[8..12].copy_from_slice(b"AVI ");
+        assert!(matches!(read_all(bytes), Err(Error::NotWAVE)));
+    }
+
+    #[test]
+    fn reader_ends_a_truncated_data_chunk_at_the_last_frame() {
+        let mut bytes = riff(&[(b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 16)), (b"data", &FRAMES)]);
+        // the data chunk claims 4 frames, the file holds 1.5
+        let data_size = bytes.len() - FRAMES.len() - 4;
+        bytes[data_size..data_size + 4].copy_from_slice(&16u32.to_le_bytes());
+        bytes.truncate(bytes.len() - 2);
+        assert_eq!(read_all(bytes).unwrap(), [(1, 2)]);
+    }
+
+    #[test]
+    fn reader_rejects_chunks_past_the_container() {
+        let mut bytes = riff(&[
+            (b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 16)),
+            (b"junk", b"abcd"),
+            (b"data", &FRAMES),
+        ]);
+        // the junk chunk claims more than the RIFF size
+        bytes[40..44].copy_from_slice(&1000u32.to_le_bytes());
+        match read_all(bytes.clone()) {
+            Err(Error::BadChunkSize { id, size: 1000 }) => assert_eq!(&id, b"junk"),
+            other => panic!("{:?}", other),
+        }
+        // the RIFF size covers it but the file ends first
+        bytes[4..8].copy_from_slice(&2000u32.to_le_bytes());
+        assert!(matches!(read_all(bytes), Err(Error::Truncated)));
+        let bytes = riff(&[(b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 16)[..14])]);
+        assert!(matches!(read_all(bytes), Err(Error::BadChunkSize { .. })));
+    }
+
+    #[test]
+    fn reader_seeks_within_the_data() {
+        let samples: Vec<i16> = (0..10).map(|i| i * 100).collect();
+        let mut reader = WAVReader::new(Cursor::new(wav(&samples))).unwrap();
+        let mut out = [Mono(0i16); 3];
+        reader.read_samples_as(&mut out).unwrap();
+
+        reader.seek(6).unwrap();
+        assert_eq!(reader.position(), 6);
+        assert_eq!(read(&mut reader), Some(600));
+        assert_eq!(reader.position(), 7);
+        reader.seek(2).unwrap();
+        assert_eq!(reader.read_samples_as(&mut out).unwrap(), 3);
+        assert_eq!(out.iter().map(|s| s.0).collect::<Vec<_>>(), [200, 300, 400]);
+
+        reader.seek(10).unwrap();
+        assert_eq!(read(&mut reader), None);
+        match reader.seek(11) {
+            Err(Error::SeekPastEnd {
+                frame: 11,
+                frames: 10,
+            }) => {}
+            other => panic!("{:?}", other.err()),
+        }
+        assert_eq!(reader.position(), 10);
+        reader.seek(9).unwrap();
+        assert_eq!(read(&mut reader), Some(900));
+    }
+
+    fn u16_at(bytes: &[u8], i: usize) -> u16 {
+        u16::from_le_bytes([bytes[i], bytes[i + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+    }
+
+    // the data of the first chunk called `id`
+    fn find_chunk<'a>(bytes: &'a [u8], id: &[u8; 4]) -> Option<&'a [u8]> {
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let size = u32_at(bytes, offset + 4) as usize;
+            if bytes[offset..offset + 4] == id[..] {
+                return Some(&bytes[offset + 8..offset + 8 + size]);
+            }
+            offset += 8 + size + (size & 1);
+        }
+        None
+    }
+
+    // a WAVE_FORMAT_EXTENSIBLE fmt chunk
+    fn fmt_extensible(subformat: u16, channels: u16, bitswidth: u16, mask: u32) -> Vec<u8> {
+        let mut fmt = fmt(WAVE_FORMAT_EXTENSIBLE, channels, bitswidth);
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&bitswidth.to_le_bytes());
+        fmt.extend_from_slice(&mask.to_le_bytes());
+        fmt.extend_from_slice(&subformat.to_le_bytes());
+        fmt.extend_from_slice(&KSDATAFORMAT_SUFFIX);
+        fmt
+    }
+
+    #[test]
+    fn reader_decodes_extensible_pcm_and_packed_24_bit() {
+        let data = [
+            0x56, 0x34, 0x12, 0xaa, 0xcb, 0xed, 0xff, 0xff, 0x7f, 0x00, 0x00, 0x80,
+        ];
+        let bytes = riff(&[
+            (b"fmt ", &fmt_extensible(WAVE_FORMAT_PCM, 2, 24, 0x3)),
+            (b"data", &data),
+        ]);
+        let mut reader = WAVReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.header.format, WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(reader.header.subformat, WAVE_FORMAT_PCM);
+        assert_eq!(reader.header.channelmask, 0x3);
+        assert_eq!(reader.frames(), 2);
+        let s: Stereo<I24> = reader.read_sample_as().unwrap().unwrap();
+        assert_eq!((s.l.get(), s.r.get()), (0x12_3456, -0x12_3456));
+        let s: Stereo<i32> = reader.read_sample_as().unwrap().unwrap();
+        assert_eq!((s.l, s.r), (i32::MAX - 0xff, i32::MIN));
+        assert!(reader.read_sample_as::<Stereo<i32>>().unwrap().is_none());
+    }
+
+    #[test]
+    fn reader_decodes_floats() {
+        let mut data = Vec::new();
+        for &x in [0.5f32, -0.25, 1.5, -1.0].iter() {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+        let bytes = riff(&[
+            (b"fmt ", &fmt_extensible(WAVE_FORMAT_IEEE_FLOAT, 2, 32, 0x3)),
+            (b"data", &data),
+        ]);
+        let mut reader = WAVReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.header.subformat, WAVE_FORMAT_IEEE_FLOAT);
+        let mut out = [Stereo::new(0.0f32); 2];
+        assert_eq!(reader.read_samples_as(&mut out).unwrap(), 2);
+        let out: Vec<_> = out.iter().map(|s| (s.l, s.r)).collect();
+        assert_eq!(out, [(0.5, -0.25), (1.5, -1.0)]);
+
+        let mut data = Vec::new();
+        for &x in [0.125f64, -0.5].iter() {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+        let bytes = riff(&[
+            (b"fmt ", &fmt(WAVE_FORMAT_IEEE_FLOAT, 1, 64)),
+            (b"data", &data),
+        ]);
+        let mut reader = WAVReader::new(Cursor::new(bytes)).unwrap();
+        let s: Mono<i16> = reader.read_sample_as().unwrap().unwrap();
+        assert_eq!(s, Mono(4096));
+        let s: Mono<f64> = reader.read_sample_as().unwrap().unwrap();
+        assert_eq!(s, Mono(-0.5));
+    }
+
+    #[test]
+    fn reader_checks_the_extensible_fields() {
+        let mut fmt = fmt_extensible(WAVE_FORMAT_PCM, 2, 16, 0x3);
+        // a GUID outside the KSDATAFORMAT family
+        fmt[30] = 0xff;
+        let bytes = riff(&[(b"fmt ", &fmt), (b"data", &FRAMES)]);
+        assert!(matches!(read_all(bytes), Err(Error::UnsupportedFormat(0))));
+
+        let mut fmt = fmt_extensible(WAVE_FORMAT_PCM, 2, 16, 0x3);
+        fmt[16..18].copy_from_slice(&0u16.to_le_bytes());
+        let bytes = riff(&[(b"fmt ", &fmt), (b"data", &FRAMES)]);
+        assert!(matches!(read_all(bytes), Err(Error::InvalidFmt)));
+
+        // more valid bits than the container holds
+        let mut fmt = fmt_extensible(WAVE_FORMAT_PCM, 2, 16, 0x3);
+        fmt[18..20].copy_from_slice(&20u16.to_le_bytes());
+        let bytes = riff(&[(b"fmt ", &fmt), (b"data", &FRAMES)]);
+        assert!(matches!(read_all(bytes), Err(Error::InvalidFmt)));
+    }
+
+    fn write<T>(format: WAVFormat, channels: u16, frames: &[T]) -> Vec<u8>
+    where
+        T: Sample,
+        T::Member: IntoSampleType<f64>,
+    {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WAVWriter::new(&mut cursor, format, channels, 48000).unwrap();
+        writer.write_samples(frames).unwrap();
+        writer.finalize().unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn writer_fills_in_the_header_of_every_format() {
+        let frames = [
+            Stereo { l: 0.5, r: -0.25 },
+            Stereo { l: -1.0, r: 0.0 },
+            Stereo::new(0.125),
+        ];
+        let formats = [
+            (WAVFormat::PCM8, WAVE_FORMAT_PCM, 16),
+            (WAVFormat::PCM16, WAVE_FORMAT_PCM, 16),
+            (WAVFormat::PCM24, WAVE_FORMAT_PCM, 16),
+            (WAVFormat::PCM32, WAVE_FORMAT_PCM, 16),
+            (WAVFormat::Float32, WAVE_FORMAT_IEEE_FLOAT, 18),
+            (WAVFormat::Float64, WAVE_FORMAT_IEEE_FLOAT, 18),
+        ];
+        for &(format, tag, fmt_size) in formats.iter() {
+            let bytes = write(format, 2, &frames);
+            assert_eq!(&bytes[..4], b"RIFF");
+            assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+            let blockalign = 2 * format.bitswidth();
+
+            let fmt = find_chunk(&bytes, b"fmt ").unwrap();
+            assert_eq!(fmt.len(), fmt_size, "{:?}", format);
+            assert_eq!(u16_at(fmt, 0), tag);
+            assert_eq!(u16_at(fmt, 2), 2);
+            assert_eq!(u32_at(fmt, 4), 48000);
+            assert_eq!(u32_at(fmt, 8), 48000 * blockalign as u32 / 8);
+            assert_eq!(u16_at(fmt, 12), blockalign / 8);
+            assert_eq!(u16_at(fmt, 14), format.bitswidth());
+            match find_chunk(&bytes, b"fact") {
+                Some(fact) => {
+                    assert_eq!(tag, WAVE_FORMAT_IEEE_FLOAT);
+                    assert_eq!(u32_at(fact, 0), 3);
+                }
+                None => assert_eq!(tag, WAVE_FORMAT_PCM),
+            }
+            let data = find_chunk(&bytes, b"data").unwrap();
+            assert_eq!(data.len(), 3 * blockalign as usize / 8);
+
+            let mut reader = WAVReader::new(Cursor::new(bytes)).unwrap();
+            let mut out = [Stereo::new(0.0f64); 3];
+            assert_eq!(reader.read_samples_as(&mut out).unwrap(), 3);
+            for (s, frame) in out.iter().zip(frames.iter()) {
+                assert!((s.l - frame.l).abs() <= 1.0 / 128.0, "{:?}", format);
+                assert!((s.r - frame.r).abs() <= 1.0 / 128.0, "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn writer_pads_an_odd_data_chunk() {
+        let bytes = write(WAVFormat::PCM8, 1, &[Mono(0.0f64); 3]);
+        assert_eq!(find_chunk(&bytes, b"data").unwrap(), &[0x80; 3]);
+        assert_eq!(bytes.len() % 2, 0);
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(WAVReader::new(Cursor::new(bytes)).unwrap().frames(), 3);
+    }
+
+    #[test]
+    fn writer_uses_extensible_for_more_channels() {
+        let frame = Surround51::from_raw(&[0.5, -0.5, 0.25, -0.25, 0.125, -0.125]).unwrap();
+        for &format in [WAVFormat::PCM24, WAVFormat::Float32].iter() {
+            let bytes = write(format, 6, &[frame; 3]);
+            let fmt = find_chunk(&bytes, b"fmt ").unwrap();
+            assert_eq!(fmt.len(), 40);
+            assert_eq!(u16_at(fmt, 0), WAVE_FORMAT_EXTENSIBLE);
+            assert_eq!(u16_at(fmt, 12), 6 * format.bitswidth() / 8);
+            assert_eq!(u16_at(fmt, 16), 22);
+            assert_eq!(u16_at(fmt, 18), format.bitswidth());
+            assert_eq!(u32_at(fmt, 20), 0x3f);
+            assert_eq!(u16_at(fmt, 24), format.tag());
+            assert_eq!(fmt[26..], KSDATAFORMAT_SUFFIX);
+            let fact = find_chunk(&bytes, b"fact");
+            assert_eq!(
+                fact.map(|fact| u32_at(fact, 0)),
+                match format {
+                    WAVFormat::Float32 => Some(3),
+                    _ => None,
+                }
+            );
+
+            let mut reader = WAVReader::new(Cursor::new(bytes)).unwrap();
+            assert_eq!(reader.header.subformat, format.tag());
+            assert_eq!(reader.header.channelmask, 0x3f);
+            let s: Surround51<f64> = reader.read_sample_as().unwrap().unwrap();
+            let (mut raw, mut expected) = ([0.0; 6], [0.0; 6]);
+            s.to_raw(&mut raw);
+            frame.to_raw(&mut expected);
+            for (x, e) in raw.iter().zip(expected.iter()) {
+                assert!((x - e).abs() < 1e-6, "{:?}", format);
+            }
+        }
+
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WAVWriter::new(&mut cursor, WAVFormat::PCM16, 6, 48000).unwrap();
+        match writer.write_sample(Stereo::new(0.0f64)) {
+            Err(Error::ChannelMismatch {
+                channels: 6,
+                frame_channels: 2,
+            }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(writer.frames(), 0);
+        assert!(WAVWriter::new(Cursor::new(Vec::new()), WAVFormat::PCM16, 33, 48000).is_err());
+    }
+}